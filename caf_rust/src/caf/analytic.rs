@@ -0,0 +1,110 @@
+// Real-valued input support
+// analytic_signal() builds x + j*hilbert(x) in the frequency domain:
+// the FFT of a real signal is doubled over the positive frequencies,
+// zeroed over the negative ones, and transformed back (as
+// scipy.signal.hilbert does). The result holds only the positive half
+// of the spectrum, so a real tone at f becomes a complex tone at +f.
+// Ddc then mixes a band of interest down to 0Hz, lowpasses it and
+// optionally decimates, giving complex baseband any backend accepts.
+
+use num_complex::Complex64;
+use rustfft::FFTplanner;
+
+use super::{Fir, Nco};
+
+// Stopband attenuation of the decimation filter in dB
+const DDC_ATTEN_DB: f64 = 60.0;
+// Fraction of the output band kept as passband
+const DDC_PASSBAND: f64 = 0.8;
+
+// Analytic signal of a real input, same length and sample rate
+pub fn analytic_signal(real: &[f64]) -> Vec<Complex64> {
+    let n = real.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Spectrum
+    let mut time: Vec<Complex64> = real.iter().map(|x| Complex64::new(*x, 0.0)).collect();
+    let mut freq = vec![Complex64::default(); n];
+    FFTplanner::new(false).plan_fft(n).process(&mut time, &mut freq);
+
+    // Keep DC (and Nyquist for even n) once, double the positive
+    // frequencies and drop the negative ones
+    let half = n.div_ceil(2);
+    for (k, bin) in freq.iter_mut().enumerate() {
+        if k == 0 || 2 * k == n {
+            continue;
+        } else if k < half {
+            *bin *= 2.0;
+        } else {
+            *bin = Complex64::default();
+        }
+    }
+
+    // Back to time, rustfft leaves the inverse unnormalized
+    FFTplanner::new(true).plan_fft(n).process(&mut freq, &mut time);
+    let scale = 1.0 / n as f64;
+    time.iter().map(|x| x * scale).collect()
+}
+
+// Digital down-converter: shift center_hz to 0Hz, lowpass and keep
+// every decimation-th sample
+#[derive(Clone, Debug)]
+pub struct Ddc {
+    center_hz: f64,
+    fs: u32,
+    decimation: usize,
+    fir: Fir,
+}
+
+impl Ddc {
+
+    // Constructor, no decimation. The lowpass keeps 80% of the band
+    pub fn new(center_hz: f64, fs: u32) -> Self {
+        Self::with_decimation(center_hz, fs, 1)
+    }
+
+    // Constructor decimating by a factor that divides fs. The lowpass
+    // keeps 80% of the output band and rejects what would alias into it
+    pub fn with_decimation(center_hz: f64, fs: u32, decimation: usize) -> Self {
+
+        // Sanity
+        assert!(decimation > 0);
+        assert!((fs as usize).is_multiple_of(decimation));
+
+        let out_fs = fs as f64 / decimation as f64;
+        let half_bw = 0.5 * DDC_PASSBAND * out_fs;
+        let transition = out_fs - 2.0 * half_bw;
+        let fir = Fir::kaiser_bandpass(-half_bw, half_bw, fs, transition, DDC_ATTEN_DB);
+        Ddc { center_hz, fs, decimation, fir }
+    }
+
+    // Sample rate of the output
+    pub fn output_rate(&self) -> u32 {
+        self.fs / self.decimation as u32
+    }
+
+    // Decimation factor
+    pub fn decimation(&self) -> usize {
+        self.decimation
+    }
+
+    // Lowpass filter applied after mixing
+    pub fn fir(&self) -> &Fir {
+        &self.fir
+    }
+
+    // Down-convert complex samples. Output sample n is input sample
+    // n * decimation, so lags scale by the decimation factor
+    pub fn process(&self, samples: &[Complex64]) -> Vec<Complex64> {
+        let mut mixed = samples.to_vec();
+        Nco::new(-self.center_hz, self.fs).mix(&mut mixed);
+        self.fir.decimate(&mixed, self.decimation)
+    }
+
+    // Down-convert a real signal through its analytic signal
+    pub fn process_real(&self, real: &[f64]) -> Vec<Complex64> {
+        self.process(&analytic_signal(real))
+    }
+}
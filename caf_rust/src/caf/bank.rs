@@ -0,0 +1,204 @@
+// Template bank: many short needles against one haystack
+// The haystack is transformed once and every template is correlated
+// against its spectrum at every frequency shift, in parallel. Scores
+// are normalized by the energy of the template and of the haystack
+// under it, so they run from 0 to 1 whatever the templates' lengths
+// and powers, and a single threshold suits the whole bank. A silent
+// template, or silence under one, scores 0.
+// A template at lag L starts at haystack sample L. Only lags where it
+// lies wholly inside the haystack are searched, and those never wrap
+// around the circular correlation, so no zero-padding is needed.
+
+use num_complex::Complex64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::xcor_rustfft::Xcor;
+use super::{normalized_score, prefix_energy, Nco, Weighting};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BankDetection {
+    pub template: usize, // index in the bank
+    pub name: String,
+    pub freq_hz: f64,
+    pub lag_samples: usize, // haystack sample where the template starts
+    pub lag_s: f64,
+    pub value: f64, // |xcor|^2
+    pub score: f64, // value / (template energy * haystack energy under it)
+}
+
+struct Template {
+    name: String,
+    samples: Vec<Complex64>,
+    energy: f64,
+}
+
+// Best (score, value, template, frequency index) seen so far per lag
+// and per template
+type Cell = (f64, f64, usize, usize);
+
+struct Scan {
+    per_lag: Vec<Option<Cell>>,
+    per_template: Vec<Option<(Cell, usize)>>, // and its lag
+}
+
+impl Scan {
+
+    fn new(n_lags: usize, n_templates: usize) -> Self {
+        Scan { per_lag: vec![None; n_lags], per_template: vec![None; n_templates] }
+    }
+
+    fn offer(&mut self, lag: usize, cell: Cell) {
+        if self.per_lag[lag].is_none_or(|best| cell.0 > best.0) {
+            self.per_lag[lag] = Some(cell);
+        }
+        let best = &mut self.per_template[cell.2];
+        if best.is_none_or(|(best, _)| cell.0 > best.0) {
+            *best = Some((cell, lag));
+        }
+    }
+
+    fn merge(mut self, other: Scan) -> Self {
+        for (lag, cell) in other.per_lag.into_iter().enumerate() {
+            if let Some(cell) = cell {
+                if self.per_lag[lag].is_none_or(|best| cell.0 > best.0) {
+                    self.per_lag[lag] = Some(cell);
+                }
+            }
+        }
+        for (best, other) in self.per_template.iter_mut().zip(other.per_template) {
+            if let Some(other) = other {
+                if best.is_none_or(|best| other.0 .0 > best.0 .0) {
+                    *best = Some(other);
+                }
+            }
+        }
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct TemplateBank {
+    templates: Vec<Template>,
+}
+
+impl TemplateBank {
+
+    // Constructor, empty
+    pub fn new() -> Self {
+        TemplateBank { templates: Vec::new() }
+    }
+
+    // Add a template, returning its index
+    pub fn add(&mut self, name: &str, samples: Vec<Complex64>) -> usize {
+        assert!(!samples.is_empty());
+        let energy = samples.iter().map(|x| x.norm_sqr()).sum();
+        self.templates.push(Template { name: name.to_string(), samples, energy });
+        self.templates.len() - 1
+    }
+
+    // Number of templates
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    // Template names in index order
+    pub fn names(&self) -> Vec<&str> {
+        self.templates.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    // Best lag and frequency of every template that fits in the
+    // haystack, best scoring first
+    pub fn search(&self, haystack: &[Complex64], freqs_hz: &[f64], fs: u32)
+        -> Vec<BankDetection> {
+
+        let scan = self.scan(haystack, freqs_hz, fs);
+        let mut best: Vec<BankDetection> = scan.per_template.iter()
+            .flatten()
+            .map(|(cell, lag)| self.detection(*cell, *lag, freqs_hz, fs))
+            .collect();
+        best.sort_by(|a, b| b.score.total_cmp(&a.score));
+        best
+    }
+
+    // Every place some template scores at least threshold, taking the
+    // best template and frequency there. Detections do not overlap: a
+    // weaker one within the span of a stronger one is dropped. Best
+    // scoring first
+    pub fn detect(&self, haystack: &[Complex64], freqs_hz: &[f64], fs: u32, threshold: f64)
+        -> Vec<BankDetection> {
+
+        let scan = self.scan(haystack, freqs_hz, fs);
+        let mut candidates: Vec<(Cell, usize)> = scan.per_lag.iter().enumerate()
+            .filter_map(|(lag, cell)| cell.map(|cell| (cell, lag)))
+            .filter(|(cell, _)| cell.0 >= threshold)
+            .collect();
+        candidates.sort_by(|a, b| b.0 .0.total_cmp(&a.0 .0));
+
+        let mut kept: Vec<(usize, usize)> = Vec::new(); // spans
+        let mut detections = Vec::new();
+        for (cell, lag) in candidates {
+            let end = lag + self.templates[cell.2].samples.len();
+            if kept.iter().all(|(start, stop)| end <= *start || lag >= *stop) {
+                kept.push((lag, end));
+                detections.push(self.detection(cell, lag, freqs_hz, fs));
+            }
+        }
+        detections
+    }
+
+    // Correlate every template at every frequency against the haystack
+    fn scan(&self, haystack: &[Complex64], freqs_hz: &[f64], fs: u32) -> Scan {
+
+        // Sanity
+        assert!(!self.templates.is_empty());
+        assert!(!freqs_hz.is_empty());
+
+        // Haystack spectrum, and its energy up to each sample
+        let n = haystack.len();
+        let xcor = Xcor::new(n);
+        let spectrum = xcor.spectrum(haystack);
+        let energy = prefix_energy(haystack);
+
+        // Every (template, frequency) that fits, folded into the best
+        // per lag and per template
+        let jobs: Vec<(usize, usize)> = self.templates.iter().enumerate()
+            .filter(|(_, template)| template.samples.len() <= n)
+            .flat_map(|(t, _)| (0..freqs_hz.len()).map(move |f| (t, f)))
+            .collect();
+        let n_templates = self.templates.len();
+        jobs.par_iter()
+            .fold(|| Scan::new(n, n_templates), |mut scan, &(t, f)| {
+                let template = &self.templates[t];
+                let len = template.samples.len();
+                let mut shifted = template.samples.clone();
+                Nco::new(freqs_hz[f], fs).mix(&mut shifted);
+                shifted.resize(n, Default::default());
+                let out = xcor.run_spectra(&spectrum, &xcor.spectrum(&shifted), Weighting::None);
+                for (lag, x) in out.iter().enumerate().take(n - len + 1) {
+                    let value = x.norm_sqr();
+                    let score = normalized_score(value, template.energy, &energy, lag, len);
+                    scan.offer(lag, (score, value, t, f));
+                }
+                scan
+            })
+            .reduce(|| Scan::new(n, n_templates), Scan::merge)
+    }
+
+    fn detection(&self, cell: Cell, lag: usize, freqs_hz: &[f64], fs: u32) -> BankDetection {
+        let (score, value, template, freq) = cell;
+        BankDetection {
+            template,
+            name: self.templates[template].name.clone(),
+            freq_hz: freqs_hz[freq],
+            lag_samples: lag,
+            lag_s: lag as f64 / fs as f64,
+            value,
+            score,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// CAF between the channels of a coherent multi-channel capture, or
+// between the signals of several receivers
+// Entry (i, j) of a CafMatrix is the peak of the CAF with channel i
+// as the needle and channel j as the haystack, i.e. the delay (TDOA)
+// and frequency offset (FDOA) of channel j relative to channel i.
+// Only one of (i, j) and (j, i) is computed, the other is its mirror
+// image (negated lag and frequency). The diagonal is left empty.
+// When every pair is computed, each triple i < j < k is checked for
+// closure: (i, j) + (j, k) should equal (i, k).
+
+use num_complex::Complex64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::xcor_rustfft::Xcor;
+use super::{surface_row, CafPeak, CafResult, CafSurfaceRow, Nco, Weighting};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pairing {
+    AllPairs, // every channel against every other
+    Reference(usize), // every channel against this one
+}
+
+// How far a triple of refined measurements is from adding up
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClosureCheck {
+    pub channels: (usize, usize, usize), // i < j < k
+    pub tdoa_s: f64, // tau_ij + tau_jk - tau_ik
+    pub fdoa_hz: f64, // f_ij + f_jk - f_ik
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafMatrix {
+    pub fs: u32,
+    pub n_channels: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<usize>, // set for Pairing::Reference
+    pub peaks: Vec<Option<CafPeak>>, // n_channels x n_channels, row-major
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub closures: Vec<ClosureCheck>, // every triple, when every pair is computed
+}
+
+impl CafMatrix {
+
+    // Run surface() on each pair the pairing asks for and keep its peak
+    pub(crate) fn compute<F>(channels: &[Vec<Complex64>], pairing: Pairing, fs: u32, surface: F)
+        -> Self where F: Fn(&[Complex64], &[Complex64]) -> Vec<CafSurfaceRow> {
+
+        // Sanity
+        check_channels(channels);
+
+        let reference = match pairing {
+            Pairing::AllPairs => None,
+            Pairing::Reference(r) => {
+                assert!(r < channels.len());
+                Some(r)
+            },
+        };
+        let mut matrix = CafMatrix::empty(fs, channels.len(), reference);
+        for (i, j) in matrix.pairs() {
+            matrix.set(i, j, &surface(&channels[i], &channels[j]));
+        }
+        matrix.check_closures();
+        matrix
+    }
+
+    // CAF of every pair of signals, reusing spectra across pairs.
+    // Each signal's spectrum is computed once for its role as the
+    // haystack and once per frequency for its role as the needle,
+    // rather than twice per pair and frequency. Frequencies run in
+    // parallel, and only one needle's pairs are held at a time
+    pub fn pairwise(signals: &[Vec<Complex64>], freqs_hz: &[f64], fs: u32,
+        weighting: Weighting) -> Self {
+
+        // Sanity
+        check_channels(signals);
+
+        // Zero-pad to 2N like the CafSurface backends
+        let m = signals.len();
+        let n = signals[0].len() * 2;
+        let padded = |signal: &[Complex64]| {
+            let mut padded = signal.to_vec();
+            padded.resize(n, Default::default());
+            padded
+        };
+        let xcor = Xcor::new(n);
+        let spectra: Vec<Vec<Complex64>> = signals.par_iter()
+            .map(|signal| xcor.spectrum(&padded(signal)))
+            .collect();
+
+        let mut matrix = CafMatrix::empty(fs, m, None);
+        for (i, signal) in signals.iter().enumerate().take(m - 1) {
+
+            // Shift needle i once per frequency, correlating it against
+            // every later signal. Rows come back frequency by pair
+            let needle = padded(signal);
+            let rows: Vec<Vec<CafSurfaceRow>> = freqs_hz.par_iter()
+                .map(|&freq| {
+                    let mut shifted = needle.clone();
+                    Nco::new(freq, fs).mix(&mut shifted);
+                    let shifted = xcor.spectrum(&shifted);
+                    (i + 1..m)
+                        .map(|j| surface_row(freq, xcor.run_spectra(&spectra[j], &shifted,
+                            weighting).iter().map(|x| x.norm_sqr()).collect()))
+                        .collect()
+                })
+                .collect();
+
+            // One surface per pair
+            let mut surfaces: Vec<Vec<CafSurfaceRow>> = (i + 1..m)
+                .map(|_| Vec::with_capacity(freqs_hz.len()))
+                .collect();
+            for freq_rows in rows {
+                for (surface, row) in surfaces.iter_mut().zip(freq_rows) {
+                    surface.push(row);
+                }
+            }
+            for (j, surface) in (i + 1..m).zip(surfaces.iter()) {
+                matrix.set(i, j, surface);
+            }
+        }
+        matrix.check_closures();
+        matrix
+    }
+
+    // (needle, haystack) channels of the pairs computed: (i, j) for
+    // i < j, or the reference against every other channel
+    pub fn pairs(&self) -> Vec<(usize, usize)> {
+        let n = self.n_channels;
+        match self.reference {
+            None => (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).collect(),
+            Some(r) => (0..n).filter(|j| *j != r).map(|j| (r, j)).collect(),
+        }
+    }
+
+    // Peak of channel j relative to channel i, if computed
+    pub fn get(&self, i: usize, j: usize) -> Option<&CafPeak> {
+        self.peaks[i * self.n_channels + j].as_ref()
+    }
+
+    // Refined delay of channel j relative to channel i in seconds
+    pub fn tdoa_s(&self) -> Vec<Vec<Option<f64>>> {
+        self.table(|peak| peak.refined_lag_s)
+    }
+
+    // Refined frequency offset of channel j relative to channel i in Hz
+    pub fn fdoa_hz(&self) -> Vec<Vec<Option<f64>>> {
+        self.table(|peak| peak.refined_freq_hz)
+    }
+
+    // Largest closure errors as (seconds, Hz), zero without closures
+    pub fn max_closure_error(&self) -> (f64, f64) {
+        self.closures.iter().fold((0.0, 0.0), |(t, f), check| {
+            (t.max(check.tdoa_s.abs()), f.max(check.fdoa_hz.abs()))
+        })
+    }
+
+    // True if every triple closes to within tdoa_tol_s and fdoa_tol_hz
+    pub fn is_consistent(&self, tdoa_tol_s: f64, fdoa_tol_hz: f64) -> bool {
+        let (tdoa, fdoa) = self.max_closure_error();
+        tdoa <= tdoa_tol_s && fdoa <= fdoa_tol_hz
+    }
+
+    fn empty(fs: u32, n_channels: usize, reference: Option<usize>) -> Self {
+        CafMatrix {
+            fs,
+            n_channels,
+            reference,
+            peaks: vec![None; n_channels * n_channels],
+            closures: Vec::new(),
+        }
+    }
+
+    // Record the peak of a pair's surface and its mirror image
+    fn set(&mut self, i: usize, j: usize, surface: &[CafSurfaceRow]) {
+        let n = self.n_channels;
+        let result = CafResult::from_surface(surface, self.fs, 1);
+        let peak = result.peak().clone();
+        self.peaks[j * n + i] = Some(mirror(&peak, result.config.n_lags));
+        self.peaks[i * n + j] = Some(peak);
+    }
+
+    // Closure of every triple, if every pair is there
+    fn check_closures(&mut self) {
+        let n = self.n_channels;
+        let mut closures = Vec::new();
+        for i in 0..n {
+            for j in i + 1..n {
+                for k in j + 1..n {
+                    if let (Some(ij), Some(jk), Some(ik)) =
+                        (self.get(i, j), self.get(j, k), self.get(i, k)) {
+
+                        closures.push(ClosureCheck {
+                            channels: (i, j, k),
+                            tdoa_s: ij.refined_lag_s + jk.refined_lag_s - ik.refined_lag_s,
+                            fdoa_hz: ij.refined_freq_hz + jk.refined_freq_hz - ik.refined_freq_hz,
+                        });
+                    }
+                }
+            }
+        }
+        self.closures = closures;
+    }
+
+    fn table<F: Fn(&CafPeak) -> f64>(&self, value: F) -> Vec<Vec<Option<f64>>> {
+        (0..self.n_channels)
+            .map(|i| (0..self.n_channels).map(|j| self.get(i, j).map(&value)).collect())
+            .collect()
+    }
+}
+
+// At least two channels, all the same length
+fn check_channels(channels: &[Vec<Complex64>]) {
+    assert!(channels.len() > 1);
+    assert!(channels.iter().all(|c| c.len() == channels[0].len()));
+}
+
+// The same peak seen with needle and haystack swapped
+fn mirror(peak: &CafPeak, n_lags: usize) -> CafPeak {
+    CafPeak {
+        freq_hz: -peak.freq_hz,
+        lag_idx: (n_lags - peak.lag_idx) % n_lags,
+        lag_samples: -peak.lag_samples,
+        lag_s: -peak.lag_s,
+        refined_freq_hz: -peak.refined_freq_hz,
+        refined_lag_samples: -peak.refined_lag_samples,
+        refined_lag_s: -peak.refined_lag_s,
+        tdoa_s: peak.tdoa_s.map(|t| -t),
+        refined_tdoa_s: peak.refined_tdoa_s.map(|t| -t),
+        ..peak.clone()
+    }
+}
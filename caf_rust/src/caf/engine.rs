@@ -0,0 +1,78 @@
+// Configurable CAF pipeline wrapping any CafSurface backend
+// Holds the pre-correlation processing (band-limiting filter applied
+// to both inputs, taper on the needle) and the GCC weighting, so
+// callers set them up once instead of massaging inputs by hand.
+
+use std::marker::PhantomData;
+
+use num_complex::Complex64;
+
+use super::{CafSurface, CafSurfaceRow, Fir, Weighting, Window};
+
+pub struct CafEngine<T: CafSurface> {
+    bandpass: Option<Fir>, // applied to needle and haystack
+    window: Option<Window>, // applied to the needle only
+    weighting: Weighting,
+    backend: PhantomData<T>,
+}
+
+impl<T: CafSurface> CafEngine<T> {
+
+    // Constructor, no filtering, tapering or weighting
+    pub fn new() -> Self {
+        CafEngine {
+            bandpass: None,
+            window: None,
+            weighting: Weighting::None,
+            backend: PhantomData,
+        }
+    }
+
+    // Band-limit both inputs before correlating
+    pub fn with_bandpass(mut self, fir: Fir) -> Self {
+        self.bandpass = Some(fir);
+        self
+    }
+
+    // Taper the needle before correlating
+    pub fn with_window(mut self, window: Window) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    // Weight the cross-spectrum
+    pub fn with_weighting(mut self, weighting: Weighting) -> Self {
+        self.weighting = weighting;
+        self
+    }
+
+    // Prepare the inputs and run the backend
+    pub fn caf_surface(&self, needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+
+        // Band-limit
+        let (mut needle, haystack) = match &self.bandpass {
+            Some(fir) => (fir.filter(needle), fir.filter(haystack)),
+            None => (needle.to_vec(), haystack.to_vec()),
+        };
+
+        // Taper
+        if let Some(window) = &self.window {
+            window.apply(&mut needle);
+        }
+
+        // Return our CAF surface
+        T::caf_surface_weighted(&needle, &haystack, freqs_hz, fs, self.weighting)
+    }
+
+    // Same as the backend's find_peak
+    pub fn find_peak(&self, surface: Vec<CafSurfaceRow>) -> (f64, usize) {
+        T::find_peak(surface)
+    }
+}
+
+impl<T: CafSurface> Default for CafEngine<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,165 @@
+// Tapering windows and windowed-sinc FIR design
+// Filters are complex so a bandpass can sit anywhere in the
+// (possibly asymmetric) complex baseband. Filtering is done with
+// the group delay removed, so sample indices (and therefore CAF
+// lags) line up with the unfiltered input.
+
+use std::f64::consts::PI;
+
+use num_complex::Complex64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    Kaiser(f64), // shape parameter beta
+}
+
+impl Window {
+
+    // Symmetric window coefficients of length n
+    pub fn coefficients(&self, n: usize) -> Vec<f64> {
+        if n <= 1 {
+            return vec![1.0; n];
+        }
+        let m = (n - 1) as f64;
+        (0..n).map(|i| {
+            let x = (i as f64) / m; // 0 to 1 across the window
+            match *self {
+                Window::Rectangular => 1.0,
+                Window::Hann => 0.5 - 0.5 * (2.0 * PI * x).cos(),
+                Window::Hamming => 0.54 - 0.46 * (2.0 * PI * x).cos(),
+                Window::Blackman => 0.42 - 0.5 * (2.0 * PI * x).cos()
+                    + 0.08 * (4.0 * PI * x).cos(),
+                Window::Kaiser(beta) => {
+                    let r = 2.0 * x - 1.0; // -1 to 1 across the window
+                    bessel_i0(beta * (1.0 - r * r).sqrt()) / bessel_i0(beta)
+                },
+            }
+        }).collect()
+    }
+
+    // Taper samples in place
+    pub fn apply(&self, samples: &mut [Complex64]) {
+        let coefficients = self.coefficients(samples.len());
+        for (samp, w) in samples.iter_mut().zip(coefficients.iter()) {
+            *samp *= w;
+        }
+    }
+
+    // Kaiser window meeting a stopband attenuation in dB
+    pub fn kaiser_for_attenuation(atten_db: f64) -> Self {
+        let beta = if atten_db > 50.0 {
+            0.1102 * (atten_db - 8.7)
+        } else if atten_db >= 21.0 {
+            0.5842 * (atten_db - 21.0).powf(0.4) + 0.07886 * (atten_db - 21.0)
+        } else {
+            0.0
+        };
+        Window::Kaiser(beta)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fir {
+    taps: Vec<Complex64>,
+}
+
+impl Fir {
+
+    // Constructor from arbitrary taps
+    pub fn new(taps: Vec<Complex64>) -> Self {
+        assert!(!taps.is_empty());
+        Fir { taps }
+    }
+
+    // Windowed-sinc lowpass passing |f| < cutoff_hz
+    pub fn lowpass(cutoff_hz: f64, fs: u32, num_taps: usize, window: Window) -> Self {
+        Self::bandpass(-cutoff_hz, cutoff_hz, fs, num_taps, window)
+    }
+
+    // Windowed-sinc complex bandpass passing low_hz < f < high_hz.
+    // A real lowpass of half the bandwidth is shifted up to the
+    // center of the band
+    pub fn bandpass(low_hz: f64, high_hz: f64, fs: u32, num_taps: usize,
+        window: Window) -> Self {
+
+        // Sanity
+        assert!(high_hz > low_hz);
+        assert!(num_taps > 0);
+
+        // Normalized half-bandwidth and center, in cycles/sample
+        let fs = fs as f64;
+        let half_bw = 0.5 * (high_hz - low_hz) / fs;
+        let center = 0.5 * (high_hz + low_hz) / fs;
+
+        // Windowed sinc, shifted to the center of the band
+        let mid = 0.5 * ((num_taps - 1) as f64);
+        let taps = window.coefficients(num_taps).iter().enumerate()
+            .map(|(i, w)| {
+                let t = (i as f64) - mid;
+                let x = 2.0 * half_bw * t;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                Complex64::from_polar(&(2.0 * half_bw * sinc * w), &(2.0 * PI * center * t))
+            })
+            .collect();
+        Fir { taps }
+    }
+
+    // Kaiser-designed bandpass, choosing the length and window shape
+    // for the requested transition width and stopband attenuation
+    pub fn kaiser_bandpass(low_hz: f64, high_hz: f64, fs: u32,
+        transition_hz: f64, atten_db: f64) -> Self {
+
+        // Kaiser's length estimate, rounded up to an odd length
+        // so the group delay is a whole number of samples
+        let width = 2.0 * PI * transition_hz / (fs as f64);
+        let num_taps = ((atten_db - 8.0) / (2.285 * width)).ceil().max(1.0) as usize;
+        let num_taps = num_taps | 1;
+        Self::bandpass(low_hz, high_hz, fs, num_taps,
+            Window::kaiser_for_attenuation(atten_db))
+    }
+
+    // Filter taps
+    pub fn taps(&self) -> &[Complex64] {
+        &self.taps
+    }
+
+    // Filter a slice, returning the same number of samples with the
+    // (num_taps - 1) / 2 sample group delay removed
+    pub fn filter(&self, samples: &[Complex64]) -> Vec<Complex64> {
+        self.decimate(samples, 1)
+    }
+
+    // Filter and keep every factor-th output, computing only those.
+    // Output n lines up with input n * factor
+    pub fn decimate(&self, samples: &[Complex64], factor: usize) -> Vec<Complex64> {
+        assert!(factor > 0);
+        let delay = (self.taps.len() - 1) / 2;
+        (0..samples.len()).step_by(factor).map(|n| {
+            let out_idx = n + delay;
+            let first = (out_idx + 1).saturating_sub(self.taps.len());
+            let last = out_idx.min(samples.len() - 1);
+            (first..=last)
+                .map(|k| samples[k] * self.taps[out_idx - k])
+                .fold(Complex64::new(0.0, 0.0), |acc, x| acc + x)
+        }).collect()
+    }
+}
+
+// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x_sq = 0.25 * x * x;
+    for k in 1..64 {
+        term *= half_x_sq / ((k * k) as f64);
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
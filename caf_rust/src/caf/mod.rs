@@ -6,25 +6,111 @@ use num_complex::Complex64;
 use rayon::prelude::*;
 use threadpool::ThreadPool;
 
+mod analytic;
+mod bank;
+mod batch;
+mod engine;
+mod filter;
+mod nco;
+mod resample;
+mod result;
+mod segmented;
+mod stream;
+mod weighting;
 mod xcor_fftw;
 mod xcor_rustfft;
 
+pub use analytic::{analytic_signal, Ddc};
+pub use bank::{BankDetection, TemplateBank};
+pub use batch::{CafMatrix, ClosureCheck, Pairing};
+pub use engine::CafEngine;
+pub use filter::{Fir, Window};
+pub use nco::Nco;
+pub use resample::Resampler;
+pub use result::{CafConfig, CafPeak, CafQuality, CafResult, CafStartTimes, CafTiming};
+pub use segmented::{Accumulation, SegmentPeak, SegmentedCaf, SegmentedSurface};
+pub use stream::{CafStream, StreamDetection};
+pub use weighting::Weighting;
+
 
 // Take in 2 signals and a range of frequency shifts to try
 // and compute their CAF. Return the surface as a 2D Vec of
 // cross correlation magnitudes squared (for efficiency)
-#[allow(dead_code)]
 pub struct CafSurfaceRow {
-    freq: f64,
-    xcor_mag: Vec<f64>,
-    xcor_peak_idx: usize,
-    xcor_peak_val: f64,
+    pub freq: f64,
+    pub xcor_mag: Vec<f64>,
+    pub xcor_peak_idx: usize,
+    pub xcor_peak_val: f64,
+}
+
+// Signed lag in samples of column idx of a row's xcor_mag. The
+// correlation is circular over the zero-padded length, so the upper
+// half of the row holds the negative lags
+pub fn signed_lag(idx: usize, row_len: usize) -> i64 {
+    if idx < row_len.div_ceil(2) {
+        idx as i64
+    } else {
+        idx as i64 - row_len as i64
+    }
+}
+
+// A row and its first largest value, as the backends give them
+fn surface_row(freq: f64, xcor_mag: Vec<f64>) -> CafSurfaceRow {
+    let mut max = 0.0;
+    let mut argmax = 0;
+    for (i, v) in xcor_mag.iter().enumerate() {
+        if *v > max {
+            max = *v;
+            argmax = i;
+        }
+    }
+    CafSurfaceRow { freq, xcor_mag, xcor_peak_idx: argmax, xcor_peak_val: max }
+}
+
+// Running energy of samples, entry k holding that of the first k, so
+// the energy of any span is the difference of two entries
+fn prefix_energy(samples: &[Complex64]) -> Vec<f64> {
+    let mut energy = vec![0.0; samples.len() + 1];
+    for (k, x) in samples.iter().enumerate() {
+        energy[k + 1] = energy[k] + x.norm_sqr();
+    }
+    energy
+}
+
+// |xcor|^2 at a lag over the energy of the needle and of the len
+// samples under it, 0 to 1, and 0 where either is silent
+fn normalized_score(value: f64, needle_energy: f64, energy: &[f64], lag: usize, len: usize)
+    -> f64 {
+
+    let product = needle_energy * (energy[lag + len] - energy[lag]);
+    if product > 0.0 { value / product } else { 0.0 }
+}
+
+// One plane of a (frequency rate, frequency, delay) CAF volume.
+// The needle was dechirped by `rate` Hz/s before computing `surface`
+pub struct CafDriftPlane {
+    pub rate: f64,
+    pub surface: Vec<CafSurfaceRow>,
+}
+
+// One plane of a (time scale, frequency, delay) wideband CAF volume.
+// The needle was resampled to x(scale * t) before computing `surface`
+pub struct CafScalePlane {
+    pub scale: f64,
+    pub surface: Vec<CafSurfaceRow>,
 }
+
 pub trait CafSurface {
 
     // Every implementation will be different
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow>;
+
+    // Plain (unweighted) cross-correlation CAF
     fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow>;
+        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+        Self::caf_surface_weighted(needle, haystack, freqs_hz, fs, Weighting::None)
+    }
 
     // Find the row with the highest correlation peak and return
     // its (frequency, sample_index)
@@ -41,6 +127,79 @@ pub trait CafSurface {
         (max.freq, max.xcor_peak_idx)
     }
 
+    // Extend the search with a frequency rate (drift) dimension.
+    // Each rate in rates_hz_per_s dechirps the needle with a quadratic
+    // phase and then runs the regular frequency/delay search on it
+    fn caf_volume(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], rates_hz_per_s: &[f64], fs: u32) -> Vec<CafDriftPlane> {
+
+        rates_hz_per_s.iter().map(|&rate| {
+            let dechirped = Self::apply_freq_drift(needle, rate, fs);
+            CafDriftPlane {
+                rate,
+                surface: Self::caf_surface(&dechirped, haystack, freqs_hz, fs),
+            }
+        }).collect()
+    }
+
+    // Find the best cell in a CAF volume and return its
+    // (frequency, frequency_rate, sample_index)
+    fn find_peak_drift(volume: Vec<CafDriftPlane>) -> (f64, f64, usize) {
+        let mut best = (0.0, 0.0, 0);
+        let mut best_val = 0.0;
+        for plane in volume.iter() {
+            for row in plane.surface.iter() {
+                if row.xcor_peak_val > best_val {
+                    best_val = row.xcor_peak_val;
+                    best = (row.freq, plane.rate, row.xcor_peak_idx);
+                }
+            }
+        }
+        best
+    }
+
+    // Wideband ambiguity search. Rather than modelling Doppler as a
+    // pure frequency shift, each factor in scales time-compresses
+    // (scale > 1) or dilates (scale < 1) the needle before the regular
+    // frequency/delay search. Delays are relative to the needle start
+    fn caf_wideband(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], scales: &[f64], fs: u32) -> Vec<CafScalePlane> {
+
+        scales.iter().map(|&scale| {
+            let resampled = Resampler::new(scale).resample(needle);
+            CafScalePlane {
+                scale,
+                surface: Self::caf_surface(&resampled, haystack, freqs_hz, fs),
+            }
+        }).collect()
+    }
+
+    // Find the best cell in a wideband CAF volume and return its
+    // (frequency, scale, sample_index)
+    fn find_peak_wideband(volume: Vec<CafScalePlane>) -> (f64, f64, usize) {
+        let mut best = (0.0, 1.0, 0);
+        let mut best_val = 0.0;
+        for plane in volume.iter() {
+            for row in plane.surface.iter() {
+                if row.xcor_peak_val > best_val {
+                    best_val = row.xcor_peak_val;
+                    best = (row.freq, plane.scale, row.xcor_peak_idx);
+                }
+            }
+        }
+        best
+    }
+
+    // CAF between the channels of a coherent capture, every pair or
+    // each against a reference, keeping the peak of each. Channels
+    // must be the same length
+    fn caf_matrix(channels: &[Vec<Complex64>], freqs_hz: &[f64], fs: u32,
+        pairing: Pairing) -> CafMatrix {
+
+        CafMatrix::compute(channels, pairing, fs,
+            |needle, haystack| Self::caf_surface(needle, haystack, freqs_hz, fs))
+    }
+
     // Takes in a slice of samples at samp_rate and applies
     // a frequency shift to it
     fn apply_freq_shift(samples: &[Complex64], freq_shift: f64, fs: u32)
@@ -51,24 +210,34 @@ pub trait CafSurface {
 
         // Apply to each sample
         // x *= e^(j*2pi*fs*df*t)
-        let dt = 1.0 / (fs as f64);
-        let shift = Complex64::from_polar(&(1.0),
-            &(2.0 * PI * freq_shift * dt));
-        let mut accum_shift = Complex64::new(1.0, 0.0);
-        for samp in samples.iter_mut() {
-            *samp *= accum_shift;
-            accum_shift *= shift;
-        }
+        let mut nco = Nco::new(freq_shift, fs);
+        nco.mix(&mut samples);
 
         // Return our shifted samples
         samples
     }
+
+    // Takes in a slice of samples at samp_rate and applies a linear
+    // frequency drift (starting at 0Hz) to it
+    fn apply_freq_drift(samples: &[Complex64], rate_hz_per_s: f64, fs: u32)
+        -> Vec<Complex64> {
+
+        // x *= e^(j*pi*rate*t^2), with the phase computed directly
+        // (and wrapped) per sample rather than accumulated
+        let half_rate = 0.5 * rate_hz_per_s / ((fs as f64) * (fs as f64));
+        samples.iter().enumerate().map(|(n, samp)| {
+            let n = n as f64;
+            let cycles = half_rate * n * n;
+            let phase = 2.0 * PI * (cycles - cycles.floor());
+            samp * Complex64::from_polar(&1.0, &phase)
+        }).collect()
+    }
 }
 pub struct CafFFTW {} // FFTW one thread
 impl CafSurface for CafFFTW {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut surface = Vec::new();
@@ -85,7 +254,7 @@ impl CafSurface for CafFFTW {
 
             // Generate a shifted copy and cross correlate with target
             let shifted = Self::apply_freq_shift(&needle, *freq, fs);
-            let xcor_res = xcor.run(&haystack, &shifted);
+            let xcor_res = xcor.run_weighted(&haystack, &shifted, weighting);
 
             // Take the magnitude squared of the result and find (arg)max
             let mut xcor_mag = Vec::with_capacity(xcor_res.len());
@@ -118,8 +287,8 @@ impl CafSurface for CafFFTW {
 pub struct CafRustFFT {} // RustFFT one thread
 impl CafSurface for CafRustFFT {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut surface = Vec::new();
@@ -136,7 +305,7 @@ impl CafSurface for CafRustFFT {
 
             // Generate a shifted copy and cross correlate with target
             let shifted = Self::apply_freq_shift(&needle, *freq, fs);
-            let xcor_res = xcor.run(&haystack, &shifted);
+            let xcor_res = xcor.run_weighted(&haystack, &shifted, weighting);
 
             // Take the magnitude squared of the result and find (arg)max
             let mut xcor_mag = Vec::with_capacity(xcor_res.len());
@@ -169,8 +338,8 @@ impl CafSurface for CafRustFFT {
 pub struct CafRustFFTRayon {} // RustFFT with Rayon parallelization
 impl CafSurface for CafRustFFTRayon {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut needle = needle.to_vec();
@@ -186,7 +355,7 @@ impl CafSurface for CafRustFFTRayon {
 
             // Generate a shifted copy and cross correlate with target
             let shifted = Self::apply_freq_shift(&needle, *freq, fs);
-            let xcor_res = xcor.clone().run(&haystack, &shifted);
+            let xcor_res = xcor.clone().run_weighted(&haystack, &shifted, weighting);
 
             // Take the magnitude squared of the result and find (arg)max
             let mut xcor_mag = Vec::with_capacity(xcor_res.len());
@@ -219,8 +388,8 @@ impl CafSurface for CafRustFFTRayon {
 pub struct CafRustFFTIter {} // RustFFT, but with iterators
 impl CafSurface for CafRustFFTIter {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut needle = needle.to_vec();
@@ -237,7 +406,7 @@ impl CafSurface for CafRustFFTIter {
 
             // Get the shifted copy and cross correlate with target
             .map(|&freq| (freq, Self::apply_freq_shift(&needle, freq, fs)))
-            .map(|(freq, shifted): (f64, Vec<Complex64>)| (freq, xcor.run(&haystack, &shifted)))
+            .map(|(freq, shifted): (f64, Vec<Complex64>)| (freq, xcor.run_weighted(&haystack, &shifted, weighting)))
 
             // Take the maginute squared of the result and find (arg)max
             .map(|(freq, xcor_res): (f64, Vec<Complex64>)| (freq, xcor_res.iter()
@@ -266,8 +435,8 @@ impl CafSurface for CafRustFFTIter {
 pub struct CafRustFFTIterRayon {} // RustFFT with Rayon-accelerated parallel iterators
 impl CafSurface for CafRustFFTIterRayon {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut needle = needle.to_vec();
@@ -284,7 +453,7 @@ impl CafSurface for CafRustFFTIterRayon {
 
             // Get the shifted copy and cross correlate with target
             .map(|&freq| (freq, Self::apply_freq_shift(&needle, freq, fs)))
-            .map(|(freq, shifted): (f64, Vec<Complex64>)| (freq, xcor.clone().run(&haystack, &shifted)))
+            .map(|(freq, shifted): (f64, Vec<Complex64>)| (freq, xcor.clone().run_weighted(&haystack, &shifted, weighting)))
 
             // Take the maginute squared of the result and find (arg)max
             .map(|(freq, xcor_res): (f64, Vec<Complex64>)| (freq, xcor_res.iter()
@@ -313,8 +482,8 @@ impl CafSurface for CafRustFFTIterRayon {
 pub struct CafRustFFTThreads {} // RustFFT using std::threads
 impl CafSurface for CafRustFFTThreads {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut surface = Vec::new();
@@ -347,7 +516,7 @@ impl CafSurface for CafRustFFTThreads {
 
                 // Generate a shifted copy and cross correlate with target
                 let shifted = Self::apply_freq_shift(&needle, freq, fs);
-                let xcor_res = xcor.run(&haystack, &shifted);
+                let xcor_res = xcor.run_weighted(&haystack, &shifted, weighting);
 
                 // Take the magnitude squared of the result and find (arg)max
                 let mut xcor_mag = Vec::with_capacity(xcor_res.len());
@@ -388,8 +557,8 @@ impl CafSurface for CafRustFFTThreads {
 pub struct CafRustFFTThreadpool {} // RustFFT using threadpool crate
 impl CafSurface for CafRustFFTThreadpool {
 
-    fn caf_surface(needle: &[Complex64], haystack: &[Complex64],
-        freqs_hz: &[f64], fs: u32) -> Vec<CafSurfaceRow> {
+    fn caf_surface_weighted(needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32, weighting: Weighting) -> Vec<CafSurfaceRow> {
 
         // Create our 2D surface and setup Vecs
         let mut surface = Vec::new();
@@ -423,7 +592,7 @@ impl CafSurface for CafRustFFTThreadpool {
 
                 // Generate a shifted copy and cross correlate with target
                 let shifted = Self::apply_freq_shift(&needle, freq, fs);
-                let xcor_res = xcor.run(&haystack, &shifted);
+                let xcor_res = xcor.run_weighted(&haystack, &shifted, weighting);
 
                 // Take the magnitude squared of the result and find (arg)max
                 let mut xcor_mag = Vec::with_capacity(xcor_res.len());
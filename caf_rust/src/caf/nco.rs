@@ -0,0 +1,81 @@
+// Numerically controlled oscillator for applying frequency shifts
+// Phase is tracked as a wrapped f64 accumulator (in cycles) and the
+// complex rotor is re-synthesized from it every RESYNC_PERIOD samples,
+// so rounding error no longer grows with the length of the input.
+// Between resyncs the rotor is advanced by cheap complex multiplies.
+
+use std::f64::consts::PI;
+
+use num_complex::{Complex32, Complex64};
+
+// Samples between exact rotor re-synthesis. Recursive multiplication
+// drifts by ~1e-16 per step, so this bounds the error to ~1e-13
+const RESYNC_PERIOD: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct Nco {
+    phase: f64, // current phase in cycles, wrapped to [0, 1)
+    phase_inc: f64, // phase increment in cycles per sample
+    step: Complex64, // rotor advance for one sample
+}
+
+impl Nco {
+
+    // Constructor, oscillating at freq_hz for a sample rate of fs
+    pub fn new(freq_hz: f64, fs: u32) -> Self {
+        let phase_inc = wrap_cycles(freq_hz / (fs as f64));
+        Nco {
+            phase: 0.0,
+            phase_inc,
+            step: Complex64::from_polar(&1.0, &(2.0 * PI * phase_inc)),
+        }
+    }
+
+    // Current phase in radians, wrapped to [0, 2pi)
+    pub fn phase(&self) -> f64 {
+        2.0 * PI * self.phase
+    }
+
+    // Multiply samples by e^(j*2pi*f*t) in place, continuing
+    // from wherever the previous call left off
+    pub fn mix(&mut self, samples: &mut [Complex64]) {
+        for block in samples.chunks_mut(RESYNC_PERIOD) {
+            let mut rotor = self.rotor();
+            for samp in block.iter_mut() {
+                *samp *= rotor;
+                rotor *= self.step;
+            }
+            self.advance(block.len());
+        }
+    }
+
+    // Same as mix(), but for single precision samples. The rotor is
+    // still run in f64 and only rounded when applied to each sample
+    pub fn mix_c32(&mut self, samples: &mut [Complex32]) {
+        for block in samples.chunks_mut(RESYNC_PERIOD) {
+            let mut rotor = self.rotor();
+            for samp in block.iter_mut() {
+                *samp *= Complex32::new(rotor.re as f32, rotor.im as f32);
+                rotor *= self.step;
+            }
+            self.advance(block.len());
+        }
+    }
+
+    // Exact rotor for the current phase
+    fn rotor(&self) -> Complex64 {
+        Complex64::from_polar(&1.0, &self.phase())
+    }
+
+    // Move the phase accumulator forward by n samples
+    fn advance(&mut self, n: usize) {
+        self.phase = wrap_cycles(self.phase + wrap_cycles(self.phase_inc * (n as f64)));
+    }
+}
+
+// Wrap a phase in cycles to [0, 1)
+fn wrap_cycles(cycles: f64) -> f64 {
+    let wrapped = cycles - cycles.floor();
+    // Guard against -tiny + 1.0 rounding up to exactly 1.0
+    if wrapped >= 1.0 { 0.0 } else { wrapped }
+}
@@ -0,0 +1,97 @@
+// Polyphase fractional resampler for time-scaling a signal
+// Computes y[n] = x(scale * n) with a Blackman-windowed sinc
+// interpolator. The prototype filter is tabulated at PHASES
+// fractional offsets and linearly interpolated between them.
+// When compressing (scale > 1) the cutoff is lowered to 0.5/scale
+// cycles/sample to avoid aliasing.
+
+use std::f64::consts::PI;
+
+use num_complex::Complex64;
+
+// Interpolator half-length in output-rate taps
+const HALF_TAPS: usize = 16;
+// Number of tabulated fractional offsets
+const PHASES: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct Resampler {
+    scale: f64, // input samples advanced per output sample
+    half_len: usize, // taps either side of the interpolation point
+    table: Vec<f64>, // (PHASES + 1) rows of 2 * half_len taps
+}
+
+impl Resampler {
+
+    // Constructor, precomputes the polyphase filter table
+    pub fn new(scale: f64) -> Self {
+
+        // Sanity
+        assert!(scale > 0.0);
+
+        // Lowpass to the narrower of the two Nyquist bands and widen
+        // the kernel by the same factor to keep its stopband
+        let cutoff = 0.5 * scale.recip().min(1.0);
+        let half_len = ((HALF_TAPS as f64) * scale.max(1.0)).ceil() as usize;
+        let width = 2 * half_len;
+
+        // Tabulate h(k - frac) for frac = p / PHASES, including
+        // frac == 1 so the interpolation never reads past the table
+        let mut table = Vec::with_capacity((PHASES + 1) * width);
+        for p in 0..=PHASES {
+            let frac = (p as f64) / (PHASES as f64);
+            for k in 0..width {
+                let u = (k as f64) - (half_len as f64 - 1.0) - frac;
+                table.push(windowed_sinc(u, cutoff, half_len as f64));
+            }
+        }
+
+        // Return new struct
+        Resampler { scale, half_len, table }
+    }
+
+    // Resample to the same length as the input. Samples that would
+    // come from outside the input are treated as zeros
+    pub fn resample(&self, samples: &[Complex64]) -> Vec<Complex64> {
+        let width = 2 * self.half_len;
+        let mut out = Vec::with_capacity(samples.len());
+        for n in 0..samples.len() {
+
+            // Split the input position into integer and fractional parts
+            let pos = self.scale * (n as f64);
+            let base = pos.floor();
+            let phase = (pos - base) * (PHASES as f64);
+            let p = (phase.floor() as usize).min(PHASES - 1);
+            let mu = phase - (p as f64);
+            let taps0 = &self.table[p * width..(p + 1) * width];
+            let taps1 = &self.table[(p + 1) * width..(p + 2) * width];
+
+            // Convolve over the taps that land inside the input
+            let first = base as i64 - (self.half_len as i64 - 1);
+            let mut acc = Complex64::new(0.0, 0.0);
+            for k in 0..width {
+                let idx = first + k as i64;
+                if idx < 0 || idx >= samples.len() as i64 {
+                    continue;
+                }
+                let tap = taps0[k] + mu * (taps1[k] - taps0[k]);
+                acc += samples[idx as usize] * tap;
+            }
+            out.push(acc);
+        }
+        out
+    }
+}
+
+// Blackman-windowed sinc lowpass evaluated at u input samples from
+// center, with cutoff in cycles/sample and window half-width half_len
+fn windowed_sinc(u: f64, cutoff: f64, half_len: f64) -> f64 {
+    if u.abs() >= half_len {
+        return 0.0;
+    }
+    let x = 2.0 * cutoff * u;
+    let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+    let theta = PI * u / half_len;
+    let blackman = 0.42 + 0.5 * theta.cos() + 0.08 * (2.0 * theta).cos();
+    2.0 * cutoff * sinc * blackman
+}
@@ -0,0 +1,252 @@
+// Machine-readable CAF results
+// CafResult collects the strongest peaks of a surface, refined to a
+// fraction of a bin by fitting a parabola through each peak and its
+// neighbours in lag and in frequency, a few figures of merit, and
+// whatever the caller records about how the surface was made.
+// Lags are relative to the first sample of each input. Given the
+// times those samples were recorded, each peak also gets an absolute
+// TDOA: the lag plus the haystack's start less the needle's.
+
+use serde::{Deserialize, Serialize};
+
+use super::{signed_lag, CafSurfaceRow, SegmentPeak, SegmentedSurface};
+use crate::utils::Timestamp;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafPeak {
+    pub freq_hz: f64,
+    pub lag_idx: usize, // column of xcor_mag, as find_peak returns
+    pub lag_samples: i64, // signed, negative lags wrap to the end of the row
+    pub lag_s: f64,
+    pub value: f64, // |xcor|^2
+    pub relative_db: f64, // to the strongest peak
+    pub refined_freq_hz: f64,
+    pub refined_lag_samples: f64,
+    pub refined_lag_s: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tdoa_s: Option<f64>, // lag_s plus the start time offset, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refined_tdoa_s: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafQuality {
+    pub mean_power: f64, // mean |xcor|^2 over the surface
+    pub peak_to_mean_db: f64,
+    pub peak_to_second_db: Option<f64>, // strongest peak over the next one
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafConfig {
+    pub fs: u32,
+    pub fmin_hz: f64,
+    pub fmax_hz: f64, // highest frequency searched, inclusive
+    pub fstep_hz: f64,
+    pub n_freqs: usize,
+    pub n_lags: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub needle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub haystack: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub needle_len: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub haystack_len: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafStartTimes {
+    pub needle: Timestamp, // of each input's first sample
+    pub haystack: Timestamp,
+    pub offset_ns: i64, // haystack less needle
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafTiming {
+    pub surface_s: f64, // computing the surface
+    pub total_s: f64, // including reading the inputs
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CafResult {
+    pub peaks: Vec<CafPeak>, // strongest first
+    pub quality: CafQuality,
+    pub config: CafConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<CafTiming>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_times: Option<CafStartTimes>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<SegmentPeak>, // of a segmented surface, each on its own
+}
+
+impl CafResult {
+
+    // Analyse a surface, keeping up to max_peaks local maxima. Rows may
+    // come in any frequency order. Input file names, backend and timing
+    // are left for the caller to fill in
+    pub fn from_surface(surface: &[CafSurfaceRow], fs: u32, max_peaks: usize) -> Self {
+
+        // Sanity
+        assert!(!surface.is_empty());
+        assert!(max_peaks > 0);
+
+        // Rows by frequency
+        let mut rows: Vec<&CafSurfaceRow> = surface.iter().collect();
+        rows.sort_by(|a, b| a.freq.partial_cmp(&b.freq).unwrap());
+        let row_len = rows[0].xcor_mag.len();
+
+        // Strongest local maxima as (value, row, col), best first
+        let mut found: Vec<(f64, usize, usize)> = Vec::with_capacity(max_peaks + 1);
+        let mut total = 0.0;
+        for r in 0..rows.len() {
+            for (c, v) in rows[r].xcor_mag.iter().enumerate() {
+                total += v;
+                if found.len() == max_peaks && *v <= found[max_peaks - 1].0 {
+                    continue;
+                }
+                if !is_local_max(&rows, r, c) {
+                    continue;
+                }
+                let pos = found.iter().position(|p| *v > p.0).unwrap_or(found.len());
+                found.insert(pos, (*v, r, c));
+                found.truncate(max_peaks);
+            }
+        }
+
+        // A flat surface has no maxima, report its first cell
+        if found.is_empty() {
+            found.push((rows[0].xcor_mag[0], 0, 0));
+        }
+        let best = found[0].0;
+        let mean = total / (rows.len() * row_len) as f64;
+
+        let freqs: Vec<f64> = rows.iter().map(|row| row.freq).collect();
+        let peaks = found.iter()
+            .map(|(value, r, c)| {
+                let lag_samples = signed_lag(*c, row_len);
+                let (refined_freq_hz, refined_lag_samples) = refine(&rows, *r, *c);
+                CafPeak {
+                    freq_hz: freqs[*r],
+                    lag_idx: *c,
+                    lag_samples,
+                    lag_s: lag_samples as f64 / fs as f64,
+                    value: *value,
+                    relative_db: 10.0 * (value / best).log10(),
+                    refined_freq_hz,
+                    refined_lag_samples,
+                    refined_lag_s: refined_lag_samples / fs as f64,
+                    tdoa_s: None,
+                    refined_tdoa_s: None,
+                }
+            })
+            .collect();
+
+        CafResult {
+            peaks,
+            quality: CafQuality {
+                mean_power: mean,
+                peak_to_mean_db: 10.0 * (best / mean).log10(),
+                peak_to_second_db: found.get(1).map(|p| 10.0 * (best / p.0).log10()),
+            },
+            config: CafConfig {
+                fs,
+                fmin_hz: freqs[0],
+                fmax_hz: freqs[freqs.len() - 1],
+                fstep_hz: if freqs.len() > 1 { freqs[1] - freqs[0] } else { 0.0 },
+                n_freqs: freqs.len(),
+                n_lags: row_len,
+                ..Default::default()
+            },
+            backend: None,
+            timing: None,
+            start_times: None,
+            segments: Vec::new(),
+        }
+    }
+
+    // Analyse an accumulated segmented surface, keeping its segments'
+    // own peaks alongside
+    pub fn from_segmented(segmented: &SegmentedSurface, fs: u32, max_peaks: usize) -> Self {
+        CafResult {
+            segments: segmented.segments.clone(),
+            ..Self::from_surface(&segmented.surface, fs, max_peaks)
+        }
+    }
+
+    // Make every peak's TDOA absolute, given when the first needle
+    // and haystack samples were recorded
+    pub fn with_start_times(mut self, needle: Timestamp, haystack: Timestamp) -> Self {
+        let offset_ns = haystack.nanos_since(&needle) as i64;
+        let offset_s = haystack.seconds_since(&needle);
+        for peak in self.peaks.iter_mut() {
+            peak.tdoa_s = Some(offset_s + peak.lag_s);
+            peak.refined_tdoa_s = Some(offset_s + peak.refined_lag_s);
+        }
+        self.start_times = Some(CafStartTimes { needle, haystack, offset_ns });
+        self
+    }
+
+    // Strongest peak
+    pub fn peak(&self) -> &CafPeak {
+        &self.peaks[0]
+    }
+}
+
+// True if cell (r, c) is at least as big as its 8 neighbours. Lags
+// wrap around, frequencies don't. Ties go to the first cell in scan
+// order so a plateau gives one maximum
+fn is_local_max(rows: &[&CafSurfaceRow], r: usize, c: usize) -> bool {
+    let row_len = rows[r].xcor_mag.len();
+    let v = rows[r].xcor_mag[c];
+    for dr in -1i64..=1 {
+        let nr = r as i64 + dr;
+        if nr < 0 || nr >= rows.len() as i64 {
+            continue;
+        }
+        for dc in -1i64..=1 {
+            if dr == 0 && dc == 0 {
+                continue;
+            }
+            let nc = (c as i64 + dc).rem_euclid(row_len as i64) as usize;
+            let n = rows[nr as usize].xcor_mag[nc];
+            let earlier = dr < 0 || (dr == 0 && dc < 0);
+            if (earlier && v <= n) || (!earlier && v < n) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Sub-bin (frequency Hz, signed lag samples) of the peak at (r, c)
+fn refine(rows: &[&CafSurfaceRow], r: usize, c: usize) -> (f64, f64) {
+    let row = &rows[r].xcor_mag;
+    let row_len = row.len();
+
+    // Lag, neighbours wrap around
+    let lag = signed_lag(c, row_len) as f64 + parabolic_offset(
+        row[(c + row_len - 1) % row_len], row[c], row[(c + 1) % row_len]);
+
+    // Frequency, only between two neighbouring rows
+    let freq = if r > 0 && r + 1 < rows.len() {
+        let step = (rows[r + 1].freq - rows[r - 1].freq) / 2.0;
+        rows[r].freq + step * parabolic_offset(
+            rows[r - 1].xcor_mag[c], row[c], rows[r + 1].xcor_mag[c])
+    } else {
+        rows[r].freq
+    };
+    (freq, lag)
+}
+
+// Offset in bins (-0.5 to 0.5) of the vertex of the parabola through
+// three equally spaced values, the middle one the largest
+fn parabolic_offset(before: f64, peak: f64, after: f64) -> f64 {
+    let curvature = before - 2.0 * peak + after;
+    if curvature >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+}
@@ -0,0 +1,172 @@
+// Segmented CAF for integrating longer than one FFT comfortably allows
+// The needle and haystack are cut into K segments of M samples and
+// segment k of the needle is correlated against segment k of the
+// haystack, each zero-padded to 2M, at every frequency shift. The
+// needle is shifted as a whole before it is cut, so every segment's
+// correlation carries the same phase for a signal at that frequency:
+//   coherent      |sum of xcor|^2, an SNR gain of K but only while the
+//                 signal stays phase-stable over the whole span
+//   non-coherent  sum of |xcor|^2, a smaller gain (about sqrt(K)) that
+//                 survives phase wander between segments
+// Lags run from -M to M, as caf_surface's do for an M sample needle.
+// Any samples past the last whole segment are left out.
+
+use std::fmt;
+use std::str::FromStr;
+
+use num_complex::Complex64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::xcor_rustfft::Xcor;
+use super::{signed_lag, surface_row, CafSurfaceRow, Nco, Weighting};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Accumulation {
+    Coherent,
+    NonCoherent,
+}
+
+impl FromStr for Accumulation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "coherent" => Ok(Accumulation::Coherent),
+            "noncoherent" | "non-coherent" | "incoherent" => Ok(Accumulation::NonCoherent),
+            _ => Err(format!("unknown accumulation '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Accumulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Accumulation::Coherent => "coherent",
+            Accumulation::NonCoherent => "non-coherent",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Strongest cell of one segment's own surface
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentPeak {
+    pub segment: usize,
+    pub start_sample: usize, // of the segment in the needle and haystack
+    pub freq_hz: f64,
+    pub lag_samples: i64,
+    pub lag_s: f64,
+    pub value: f64, // |xcor|^2
+}
+
+pub struct SegmentedSurface {
+    pub surface: Vec<CafSurfaceRow>, // accumulated over every segment
+    pub segments: Vec<SegmentPeak>, // in order
+    pub segment_len: usize,
+    pub accumulation: Accumulation,
+}
+
+pub struct SegmentedCaf {
+    segment_len: usize,
+    accumulation: Accumulation,
+}
+
+impl SegmentedCaf {
+
+    // Constructor, accumulating coherently
+    pub fn new(segment_len: usize) -> Self {
+        assert!(segment_len > 0);
+        SegmentedCaf { segment_len, accumulation: Accumulation::Coherent }
+    }
+
+    pub fn with_accumulation(mut self, accumulation: Accumulation) -> Self {
+        self.accumulation = accumulation;
+        self
+    }
+
+    // Number of whole segments in len samples
+    pub fn n_segments(&self, len: usize) -> usize {
+        len / self.segment_len
+    }
+
+    // Accumulated surface and per-segment peaks of needle against
+    // haystack, over as many whole segments as both hold
+    pub fn caf_surface(&self, needle: &[Complex64], haystack: &[Complex64],
+        freqs_hz: &[f64], fs: u32) -> SegmentedSurface {
+
+        // Sanity
+        let m = self.segment_len;
+        let n_segments = self.n_segments(needle.len().min(haystack.len()));
+        assert!(n_segments > 0);
+        assert!(!freqs_hz.is_empty());
+
+        // Haystack segments don't depend on the shift, transform them once
+        let n = 2 * m;
+        let xcor = Xcor::new(n);
+        let spectra: Vec<Vec<Complex64>> = haystack[..n_segments * m].par_chunks(m)
+            .map(|segment| {
+                let mut padded = segment.to_vec();
+                padded.resize(n, Default::default());
+                xcor.spectrum(&padded)
+            })
+            .collect();
+
+        // One accumulated row per shift, with each segment's best
+        // (value, column) at that shift
+        let rows: Vec<(CafSurfaceRow, Vec<(f64, usize)>)> = freqs_hz.par_iter()
+            .map(|&freq| {
+                let mut shifted = needle[..n_segments * m].to_vec();
+                Nco::new(freq, fs).mix(&mut shifted);
+                let mut sum = vec![Complex64::default(); n];
+                let mut power = vec![0.0; n];
+                let mut best = Vec::with_capacity(n_segments);
+                for (segment, spectrum) in shifted.chunks(m).zip(spectra.iter()) {
+                    let mut padded = segment.to_vec();
+                    padded.resize(n, Default::default());
+                    let out = xcor.run_spectra(spectrum, &xcor.spectrum(&padded), Weighting::None);
+                    let mut peak = (0.0, 0);
+                    for (col, x) in out.iter().enumerate() {
+                        let value = x.norm_sqr();
+                        sum[col] += x;
+                        power[col] += value;
+                        if value > peak.0 {
+                            peak = (value, col);
+                        }
+                    }
+                    best.push(peak);
+                }
+                let xcor_mag = match self.accumulation {
+                    Accumulation::Coherent => sum.iter().map(|x| x.norm_sqr()).collect(),
+                    Accumulation::NonCoherent => power,
+                };
+                (surface_row(freq, xcor_mag), best)
+            })
+            .collect();
+
+        // Each segment's peak is its best over every shift
+        let segments = (0..n_segments)
+            .map(|k| {
+                let (f, (value, col)) = rows.iter().enumerate()
+                    .map(|(f, (_, best))| (f, best[k]))
+                    .fold((0, (0.0, 0)), |a, b| if b.1 .0 > a.1 .0 { b } else { a });
+                let lag_samples = signed_lag(col, n);
+                SegmentPeak {
+                    segment: k,
+                    start_sample: k * m,
+                    freq_hz: freqs_hz[f],
+                    lag_samples,
+                    lag_s: lag_samples as f64 / fs as f64,
+                    value,
+                }
+            })
+            .collect();
+
+        SegmentedSurface {
+            surface: rows.into_iter().map(|(row, _)| row).collect(),
+            segments,
+            segment_len: m,
+            accumulation: self.accumulation,
+        }
+    }
+}
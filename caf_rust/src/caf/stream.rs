@@ -0,0 +1,162 @@
+// Streaming CAF over a live sample stream
+// A fixed needle of N samples is searched for in a stream arriving in
+// blocks of any size, by overlap-save. Each window of 2N stream
+// samples is correlated against the needle at every frequency shift,
+// from needle spectra computed once up front, and gives the N lags at
+// which the needle lies wholly inside it. The window then advances by
+// N, keeping its second half as overlap, so every start position is
+// tried exactly once. Memory is the needle spectra plus the window
+// and whatever block is being pushed, and a needle is reported at
+// most 2N - 1 samples after its first sample arrives.
+
+use num_complex::Complex64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::xcor_rustfft::Xcor;
+use super::{normalized_score, prefix_energy, Nco, Weighting};
+
+// Score a window's best lag must reach to be reported
+const DEFAULT_THRESHOLD: f64 = 0.5;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamDetection {
+    pub sample: u64, // stream sample where the needle starts
+    pub time_s: f64,
+    pub freq_hz: f64,
+    pub value: f64, // |xcor|^2
+    pub score: f64, // value / (needle energy * stream energy under it), 0 to 1
+}
+
+pub struct CafStream {
+    fs: u32,
+    freqs_hz: Vec<f64>,
+    needle_len: usize,
+    needle_energy: f64,
+    spectra: Vec<Vec<Complex64>>, // shifted needle per frequency, zero-padded to 2N
+    xcor: Xcor,
+    threshold: f64,
+    buffer: Vec<Complex64>, // samples not yet slid past
+    offset: u64, // stream sample of buffer[0]
+    last: Option<u64>, // start of the last detection
+}
+
+impl CafStream {
+
+    // Constructor, precomputing the needle's spectrum at every shift
+    pub fn new(needle: &[Complex64], freqs_hz: &[f64], fs: u32) -> Self {
+
+        // Sanity
+        assert!(!needle.is_empty());
+        assert!(!freqs_hz.is_empty());
+
+        let n = 2 * needle.len();
+        let xcor = Xcor::new(n);
+        let spectra = freqs_hz.par_iter()
+            .map(|freq| {
+                let mut shifted = needle.to_vec();
+                Nco::new(*freq, fs).mix(&mut shifted);
+                shifted.resize(n, Default::default());
+                xcor.spectrum(&shifted)
+            })
+            .collect();
+        CafStream {
+            fs,
+            freqs_hz: freqs_hz.to_vec(),
+            needle_len: needle.len(),
+            needle_energy: needle.iter().map(|x| x.norm_sqr()).sum(),
+            spectra,
+            xcor,
+            threshold: DEFAULT_THRESHOLD,
+            buffer: Vec::with_capacity(n),
+            offset: 0,
+            last: None,
+        }
+    }
+
+    // Report windows whose best score reaches threshold (0 to 1)
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    // Stream samples taken in so far
+    pub fn samples_seen(&self) -> u64 {
+        self.offset + self.buffer.len() as u64
+    }
+
+    // Most samples that can arrive after a needle starts before it is
+    // reported
+    pub fn latency_samples(&self) -> usize {
+        2 * self.needle_len - 1
+    }
+
+    // Take in the next block, returning detections in every window it
+    // completes. A detection overlapping the previous one is dropped
+    pub fn push(&mut self, samples: &[Complex64]) -> Vec<StreamDetection> {
+        self.buffer.extend_from_slice(samples);
+        let mut detections = Vec::new();
+        while self.buffer.len() >= 2 * self.needle_len {
+            detections.extend(self.window(self.needle_len));
+            self.slide(self.needle_len);
+        }
+        detections
+    }
+
+    // End of stream: search what is left, zero-padded, at the lags
+    // where the whole needle arrived
+    pub fn finish(&mut self) -> Vec<StreamDetection> {
+        let mut detections = Vec::new();
+        if self.buffer.len() >= self.needle_len {
+            detections.extend(self.window(self.buffer.len() - self.needle_len + 1));
+        }
+        self.slide(self.buffer.len());
+        detections
+    }
+
+    // Best of the first n_lags lags of the window at the head of the
+    // buffer, if it reaches the threshold
+    fn window(&mut self, n_lags: usize) -> Option<StreamDetection> {
+        let n = 2 * self.needle_len;
+        let mut window = self.buffer[..n.min(self.buffer.len())].to_vec();
+        window.resize(n, Default::default());
+        let spectrum = self.xcor.spectrum(&window);
+        let energy = prefix_energy(&window);
+
+        // (score, value, lag, frequency index) of the best cell
+        let len = self.needle_len;
+        let (score, value, lag, freq) = self.spectra.par_iter().enumerate()
+            .map(|(f, needle)| {
+                let out = self.xcor.run_spectra(&spectrum, needle, Weighting::None);
+                let mut best = (0.0, 0.0, 0, f);
+                for (lag, x) in out.iter().enumerate().take(n_lags) {
+                    let value = x.norm_sqr();
+                    let score = normalized_score(value, self.needle_energy, &energy, lag, len);
+                    if score > best.0 {
+                        best = (score, value, lag, f);
+                    }
+                }
+                best
+            })
+            .reduce(|| (0.0, 0.0, 0, 0), |a, b| if b.0 > a.0 { b } else { a });
+
+        let sample = self.offset + lag as u64;
+        let overlaps = self.last.is_some_and(|last| sample < last + len as u64);
+        if score < self.threshold || score == 0.0 || overlaps {
+            return None;
+        }
+        self.last = Some(sample);
+        Some(StreamDetection {
+            sample,
+            time_s: sample as f64 / self.fs as f64,
+            freq_hz: self.freqs_hz[freq],
+            value,
+            score,
+        })
+    }
+
+    fn slide(&mut self, count: usize) {
+        self.buffer.drain(..count);
+        self.offset += count as u64;
+    }
+}
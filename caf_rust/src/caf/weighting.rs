@@ -0,0 +1,98 @@
+// Generalized cross-correlation (GCC) weightings
+// Applied to the cross-spectrum Gab = FFT(a) * conj(FFT(b)) before the
+// inverse FFT (Knapp & Carter, 1976). Gaa and Gbb are the auto-spectra,
+// all three on the same scale: the engines divide Gab by n, so Gaa and
+// Gbb are too. SCOT, Eckart and ML need spectra estimated over more
+// than one bin (the coherence of a single periodogram is always 1, and
+// SCOT would reduce to PHAT), so they use spectra smoothed over
+// SMOOTH_BINS neighbouring bins.
+
+use itertools::izip;
+use num_complex::Complex64;
+
+// Width of the moving average used for coherence estimates
+const SMOOTH_BINS: usize = 9;
+// Bins whose denominator falls below this fraction of the largest
+// one are zeroed instead of blowing up
+const FLOOR: f64 = 1e-12;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Weighting {
+    #[default]
+    None, // plain cross-correlation
+    Phat, // 1 / |Gab|, phase transform
+    Scot, // 1 / sqrt(Gaa * Gbb), smoothed coherence transform
+    Roth, // 1 / Gaa, Roth processor (whitens by a's spectrum)
+    Eckart, // Gss / (Gn1n1 * Gn2n2), Eckart filter
+    Ml, // |y|^2 / (|Gab| * (1 - |y|^2)), maximum likelihood (Hannan-Thomson)
+}
+
+impl Weighting {
+
+    // Weight a cross-spectrum in place. cross is FFT(a) * conj(FFT(b))
+    // / n, and fa and fb are FFT(a) and FFT(b) (or its conjugate, only
+    // magnitudes are used)
+    pub fn apply(&self, cross: &mut [Complex64], fa: &[Complex64], fb: &[Complex64]) {
+
+        // Sanity
+        assert!(cross.len() == fa.len());
+        assert!(cross.len() == fb.len());
+
+        // Nothing to do for the unweighted correlator
+        if *self == Weighting::None {
+            return;
+        }
+
+        // Per-bin (numerator, denominator) of the weight, from raw
+        // or smoothed spectra
+        let n = cross.len() as f64;
+        let auto = |spectrum: &[Complex64]| smooth(spectrum.iter()
+            .map(|x| (x.norm_sqr() / n).into()));
+        let terms: Vec<(f64, f64)> = match self {
+            Weighting::None => unreachable!(),
+            Weighting::Phat => cross.iter()
+                .map(|ab| (1.0, ab.norm()))
+                .collect(),
+            Weighting::Scot => auto(fa).iter().zip(auto(fb).iter())
+                .map(|(aa, bb)| (1.0, (aa.re * bb.re).sqrt()))
+                .collect(),
+            Weighting::Roth => fa.iter()
+                .map(|a| (1.0, a.norm_sqr() / n))
+                .collect(),
+            Weighting::Eckart | Weighting::Ml => {
+                let gab = smooth(cross.iter().copied());
+                let (gaa, gbb) = (auto(fa), auto(fb));
+                izip!(gab.iter(), gaa.iter(), gbb.iter()).map(|(ab, aa, bb)| {
+                    let (ab, aa, bb) = (ab.norm(), aa.re, bb.re);
+                    if *self == Weighting::Eckart {
+                        // Signal ~ |Gab|, noise ~ what's left of each auto-spectrum
+                        (ab, (aa - ab).max(0.0) * (bb - ab).max(0.0))
+                    } else {
+                        let coherence = (ab * ab / (aa * bb)).min(1.0);
+                        (coherence, ab * (1.0 - coherence))
+                    }
+                }).collect()
+            },
+        };
+
+        // Apply the weights, zeroing bins with (almost) nothing in them
+        let max_den = terms.iter().fold(0.0, |acc: f64, (_, den)| acc.max(*den));
+        let floor = FLOOR * max_den;
+        for (bin, (num, den)) in cross.iter_mut().zip(terms.iter()) {
+            *bin *= if *den > floor { num / den } else { 0.0 };
+        }
+    }
+}
+
+// Circular moving average over SMOOTH_BINS bins
+fn smooth(spectrum: impl Iterator<Item = Complex64>) -> Vec<Complex64> {
+    let spectrum: Vec<Complex64> = spectrum.collect();
+    let n = spectrum.len();
+    let half = (SMOOTH_BINS / 2).min(n / 2);
+    let width = (2 * half + 1) as f64;
+    (0..n).map(|i| {
+        (0..=2 * half)
+            .map(|k| spectrum[(i + n + k - half) % n])
+            .fold(Complex64::new(0.0, 0.0), |acc, x| acc + x) / width
+    }).collect()
+}
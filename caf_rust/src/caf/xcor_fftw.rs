@@ -3,6 +3,7 @@
 // in and returns their (equal length) complex
 // cross-correlation
 // Naive: ifft(fft(a) * fft(b).conj())
+// Optionally GCC-weighted: ifft(W * fft(a) * fft(b).conj())
 
 use itertools::izip;
 use fftw::array::AlignedVec;
@@ -10,6 +11,8 @@ use fftw::plan::{C2CPlan, C2CPlan64};
 use fftw::types::{Sign, Flag};
 use num_complex::{Complex64};
 
+use super::Weighting;
+
 #[allow(dead_code)]
 pub struct Xcor {
     n: usize, // size of a, b, c
@@ -49,6 +52,14 @@ impl Xcor {
     // sized N
     #[allow(dead_code)]
     pub fn run(&mut self, a: &[Complex64], b: &[Complex64]) -> Vec<Complex64> {
+        self.run_weighted(a, b, Weighting::None)
+    }
+
+    // Run generalized cross-correlation, weighting the cross-spectrum
+    // before the inverse FFT
+    #[allow(dead_code)]
+    pub fn run_weighted(&mut self, a: &[Complex64], b: &[Complex64],
+        weighting: Weighting) -> Vec<Complex64> {
 
         // Sanity
         assert!(a.len() == self.n);
@@ -71,6 +82,7 @@ impl Xcor {
 
             *out = (a * b) / (self.n as f64);
         }
+        weighting.apply(&mut self.a, &self.b, &self.c);
 
         // Calculate IFFT of product and return
         self.reverse_planner.c2c(&mut self.a, &mut self.b).unwrap();
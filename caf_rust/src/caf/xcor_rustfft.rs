@@ -3,6 +3,7 @@
 // in and returns their (equal length) complex
 // cross-correlation
 // Naive: ifft(fft(a) * fft(b).conj())
+// Optionally GCC-weighted: ifft(W * fft(a) * fft(b).conj())
 
 use std::sync::Arc;
 
@@ -10,6 +11,8 @@ use itertools::izip;
 use num_complex::{Complex64};
 use rustfft::{FFTplanner, FFT};
 
+use super::Weighting;
+
 #[allow(dead_code)]
 pub struct Xcor {
     n: usize, // size of a, b, c
@@ -49,6 +52,14 @@ impl Xcor {
     // sized N
     #[allow(dead_code)]
     pub fn run(&mut self, a: &[Complex64], b: &[Complex64]) -> Vec<Complex64> {
+        self.run_weighted(a, b, Weighting::None)
+    }
+
+    // Run generalized cross-correlation, weighting the cross-spectrum
+    // before the inverse FFT
+    #[allow(dead_code)]
+    pub fn run_weighted(&mut self, a: &[Complex64], b: &[Complex64],
+        weighting: Weighting) -> Vec<Complex64> {
 
         // Sanity
         assert!(a.len() == self.n);
@@ -71,11 +82,49 @@ impl Xcor {
 
             *out = (a * b) / (self.n as f64);
         }
+        weighting.apply(&mut self.a, &self.b, &self.c);
 
         // Calculate IFFT of product and return
         self.ifft.process(&mut self.a, &mut self.b);
         self.b.to_vec()
     }
+
+    // FFT of one input sized N, so a signal used in several
+    // correlations is only transformed once
+    #[allow(dead_code)]
+    pub fn spectrum(&self, a: &[Complex64]) -> Vec<Complex64> {
+        assert!(a.len() == self.n);
+        let mut input = a.to_vec();
+        let mut output = vec![Default::default(); self.n];
+        self.fft.process(&mut input, &mut output);
+        output
+    }
+
+    // Same as run_weighted(), but from spectra already computed with
+    // spectrum(). Allocates its own buffers so it can be shared
+    // between threads
+    #[allow(dead_code)]
+    pub fn run_spectra(&self, fa: &[Complex64], fb: &[Complex64],
+        weighting: Weighting) -> Vec<Complex64> {
+
+        // Sanity
+        assert!(fa.len() == self.n);
+        assert!(fb.len() == self.n);
+
+        // FFT(a) * conj(FFT(b)), normalized and weighted. The weighting
+        // takes the raw spectra and scales them to match, exactly as
+        // run_weighted() does
+        let fb_conj: Vec<Complex64> = fb.iter().map(|bin| bin.conj()).collect();
+        let mut cross: Vec<Complex64> = fa.iter().zip(fb_conj.iter())
+            .map(|(a, b)| (a * b) / (self.n as f64))
+            .collect();
+        weighting.apply(&mut cross, fa, &fb_conj);
+
+        // IFFT of product
+        let mut output = vec![Default::default(); self.n];
+        self.ifft.process(&mut cross, &mut output);
+        output
+    }
 }
 
 // Implement the very cheap copy for FFT wisdom
@@ -0,0 +1,121 @@
+// Closed-form TDOA position (Chan & Ho, 1994)
+// With the reference receiver at the origin and d_k the range
+// difference |e - s_k| - |e|, each TDOA gives an equation linear in
+// the emitter e and its range R from the reference:
+//   -2 s_k.e - 2 d_k R = d_k^2 - |s_k|^2
+// Stage 1 solves these by weighted least squares, treating R as free.
+// Stage 2 then fits the squares of that solution under R^2 = |e|^2.
+// With exactly as many equations as coordinates, e is a line in R
+// instead and R^2 = |e|^2 is a quadratic with up to two roots.
+
+use super::linalg::{diag, dot, inverse, mul, mul_vec, sub, weighted_lstsq, Matrix};
+
+// A TDOA to the reference receiver as a range difference in metres
+pub struct RangeDifference {
+    pub position: [f64; 3], // of the other receiver
+    pub d: f64, // |e - position| - |e - reference|
+    pub sigma: f64, // standard deviation of d
+}
+
+// Candidate emitter positions, to be told apart by the caller. dims is
+// 2 (z ignored) or 3. Empty if the range differences cannot fix one
+pub fn chan_ho(reference: [f64; 3], diffs: &[RangeDifference], dims: usize) -> Vec<[f64; 3]> {
+    let s: Vec<[f64; 3]> = diffs.iter().map(|diff| sub(diff.position, reference)).collect();
+    let g: Matrix = diffs.iter().zip(s.iter())
+        .map(|(diff, s)| {
+            let mut row: Vec<f64> = s[..dims].iter().map(|v| -2.0 * v).collect();
+            row.push(-2.0 * diff.d);
+            row
+        })
+        .collect();
+    let h: Vec<f64> = diffs.iter().zip(s.iter())
+        .map(|(diff, s)| diff.d * diff.d - dot(*s, *s))
+        .collect();
+    let variances: Vec<f64> = diffs.iter().map(|diff| diff.sigma * diff.sigma).collect();
+
+    let candidates = if diffs.len() == dims {
+        minimal(&g, &h, dims)
+    } else if diffs.len() > dims {
+        two_stage(&g, &h, &s, &variances, dims)
+    } else {
+        Vec::new()
+    };
+    candidates.into_iter()
+        .map(|e| [e[0] + reference[0], e[1] + reference[1], e[2] + reference[2]])
+        .collect()
+}
+
+// Stage 2 and, in case it is off, stage 1
+fn two_stage(g: &[Vec<f64>], h: &[f64], s: &[[f64; 3]], variances: &[f64], dims: usize)
+    -> Vec<[f64; 3]> {
+
+    // Stage 1, weighted by the range noise alone and then by the 2 R_k
+    // it is scaled by in equation k
+    let w = diag(&variances.iter().map(|v| 1.0 / v).collect::<Vec<f64>>());
+    let theta = match weighted_lstsq(g, &w, h) {
+        Some((theta, _)) => theta,
+        None => return Vec::new(),
+    };
+    let rough = point(&theta, dims);
+    let w = diag(&s.iter().zip(variances.iter())
+        .map(|(s, v)| 1.0 / (4.0 * dot(sub(rough, *s), sub(rough, *s)) * v))
+        .collect::<Vec<f64>>());
+    let (theta, cov) = match weighted_lstsq(g, &w, h) {
+        Some(fit) => fit,
+        None => return vec![rough],
+    };
+    let stage1 = point(&theta, dims);
+
+    // Stage 2: fit (e_x^2, e_y^2, e_z^2) to the squares of theta, the
+    // last of which is R^2 = their sum
+    let b = diag(&theta);
+    let psi: Matrix = mul(&mul(&b, &cov), &b).into_iter()
+        .map(|row| row.into_iter().map(|v| 4.0 * v).collect())
+        .collect();
+    let g2: Matrix = (0..=dims)
+        .map(|r| (0..dims).map(|c| if r == dims || r == c { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let h2: Vec<f64> = theta.iter().map(|v| v * v).collect();
+    match inverse(&psi).and_then(|w2| weighted_lstsq(&g2, &w2, &h2)) {
+        Some((squares, _)) => {
+            let mut e = [0.0; 3];
+            for (k, v) in e.iter_mut().enumerate().take(dims) {
+                *v = theta[k].signum() * squares[k].abs().sqrt();
+            }
+            vec![e, stage1]
+        },
+        None => vec![stage1],
+    }
+}
+
+// e = a + b R from the coordinates' columns, then |e|^2 = R^2
+fn minimal(g: &[Vec<f64>], h: &[f64], dims: usize) -> Vec<[f64; 3]> {
+    let ge: Matrix = g.iter().map(|row| row[..dims].to_vec()).collect();
+    let inv = match inverse(&ge) {
+        Some(inv) => inv,
+        None => return Vec::new(),
+    };
+    let a = point(&mul_vec(&inv, h), dims);
+    let b = point(&mul_vec(&inv, &g.iter().map(|row| -row[dims]).collect::<Vec<f64>>()), dims);
+
+    // (|b|^2 - 1) R^2 + 2 a.b R + |a|^2 = 0. Noise can leave a slightly
+    // negative discriminant where the roots nearly meet, count it as zero
+    let qa = dot(b, b) - 1.0;
+    let qb = 2.0 * dot(a, b);
+    let qc = dot(a, a);
+    let roots = if qa.abs() < 1e-12 {
+        vec![-qc / qb]
+    } else {
+        let disc = (qb * qb - 4.0 * qa * qc).max(0.0).sqrt();
+        vec![(-qb + disc) / (2.0 * qa), (-qb - disc) / (2.0 * qa)]
+    };
+    roots.into_iter()
+        .filter(|r| r.is_finite() && *r >= 0.0)
+        .map(|r| [a[0] + b[0] * r, a[1] + b[1] * r, a[2] + b[2] * r])
+        .filter(|e| e.iter().all(|v| v.is_finite()))
+        .collect()
+}
+
+fn point(theta: &[f64], dims: usize) -> [f64; 3] {
+    [theta[0], theta[1], if dims == 3 { theta[2] } else { 0.0 }]
+}
@@ -0,0 +1,92 @@
+// WGS84 geodetic coordinates
+// Converts between latitude/longitude/height above the ellipsoid and
+// earth-centred earth-fixed (ECEF) metres, and gives the local east,
+// north, up axes for reading ECEF vectors and covariances.
+
+use serde::{Deserialize, Serialize};
+
+use super::linalg::{dot, sub};
+
+// Semi-major axis in metres
+pub const WGS84_A: f64 = 6378137.0;
+// Flattening
+pub const WGS84_F: f64 = 1.0 / 298.257223563;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Geodetic {
+    pub lat_deg: f64,
+    pub lon_deg: f64,
+    pub alt_m: f64, // above the ellipsoid
+}
+
+impl Geodetic {
+
+    // Constructor
+    pub fn new(lat_deg: f64, lon_deg: f64, alt_m: f64) -> Self {
+        Geodetic { lat_deg, lon_deg, alt_m }
+    }
+
+    // ECEF position in metres
+    pub fn to_ecef(&self) -> [f64; 3] {
+        let e2 = WGS84_F * (2.0 - WGS84_F);
+        let (sin_lat, cos_lat) = self.lat_deg.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = self.lon_deg.to_radians().sin_cos();
+        let n = WGS84_A / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+        [
+            (n + self.alt_m) * cos_lat * cos_lon,
+            (n + self.alt_m) * cos_lat * sin_lon,
+            (n * (1.0 - e2) + self.alt_m) * sin_lat,
+        ]
+    }
+
+    // Geodetic position of an ECEF point. Iterates on latitude, which
+    // settles to well under a millimetre in a few rounds anywhere
+    // outside the earth's core
+    pub fn from_ecef(p: [f64; 3]) -> Self {
+        let e2 = WGS84_F * (2.0 - WGS84_F);
+        let rho = p[0].hypot(p[1]);
+        let mut lat = p[2].atan2(rho * (1.0 - e2));
+        let mut n = WGS84_A;
+        for _ in 0..6 {
+            let sin_lat = lat.sin();
+            n = WGS84_A / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+            lat = (p[2] + e2 * n * sin_lat).atan2(rho);
+        }
+        let (sin_lat, cos_lat) = lat.sin_cos();
+        let alt_m = rho * cos_lat + (p[2] + e2 * n * sin_lat) * sin_lat - n;
+        Geodetic { lat_deg: lat.to_degrees(), lon_deg: p[1].atan2(p[0]).to_degrees(), alt_m }
+    }
+
+    // East, north and up unit vectors here, in ECEF
+    pub fn enu_axes(&self) -> [[f64; 3]; 3] {
+        let (sin_lat, cos_lat) = self.lat_deg.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = self.lon_deg.to_radians().sin_cos();
+        [
+            [-sin_lon, cos_lon, 0.0],
+            [-sin_lat * cos_lon, -sin_lat * sin_lon, cos_lat],
+            [cos_lat * cos_lon, cos_lat * sin_lon, sin_lat],
+        ]
+    }
+
+    // An ECEF point in metres east, north and up of here
+    pub fn enu(&self, p: [f64; 3]) -> [f64; 3] {
+        let d = sub(p, self.to_ecef());
+        let axes = self.enu_axes();
+        [dot(axes[0], d), dot(axes[1], d), dot(axes[2], d)]
+    }
+
+    // An ECEF covariance in east, north, up terms
+    pub fn enu_covariance(&self, cov: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+        let axes = self.enu_axes();
+        let mut out = [[0.0; 3]; 3];
+        for (r, row) in out.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = (0..3)
+                    .flat_map(|k| (0..3).map(move |l| (k, l)))
+                    .map(|(k, l)| axes[r][k] * cov[k][l] * axes[c][l])
+                    .sum();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,101 @@
+// Small dense linear algebra for the solvers
+// Matrices are Vecs of rows. The systems here have a handful of
+// unknowns, so Gauss-Jordan elimination with partial pivoting and the
+// normal equations are plenty.
+
+pub type Matrix = Vec<Vec<f64>>;
+
+// Pivots this much smaller than the largest entry count as zero
+const SINGULAR: f64 = 1e-13;
+
+pub fn diag(values: &[f64]) -> Matrix {
+    let n = values.len();
+    let mut a = vec![vec![0.0; n]; n];
+    for (i, v) in values.iter().enumerate() {
+        a[i][i] = *v;
+    }
+    a
+}
+
+pub fn transpose(a: &[Vec<f64>]) -> Matrix {
+    let cols = a.first().map(|row| row.len()).unwrap_or(0);
+    (0..cols).map(|c| a.iter().map(|row| row[c]).collect()).collect()
+}
+
+pub fn mul(a: &[Vec<f64>], b: &[Vec<f64>]) -> Matrix {
+    let cols = b.first().map(|row| row.len()).unwrap_or(0);
+    a.iter()
+        .map(|row| (0..cols).map(|c| row.iter().zip(b.iter()).map(|(x, r)| x * r[c]).sum())
+            .collect())
+        .collect()
+}
+
+pub fn mul_vec(a: &[Vec<f64>], x: &[f64]) -> Vec<f64> {
+    a.iter().map(|row| row.iter().zip(x.iter()).map(|(a, x)| a * x).sum()).collect()
+}
+
+// Inverse of a square matrix, None if it is singular
+pub fn inverse(a: &[Vec<f64>]) -> Option<Matrix> {
+    let n = a.len();
+    let scale = a.iter().flatten().fold(0.0f64, |m, v| m.max(v.abs()));
+    if scale == 0.0 || !scale.is_finite() {
+        return None;
+    }
+
+    // Reduce [a | I] to [I | a^-1]
+    let mut work: Matrix = a.iter().enumerate()
+        .map(|(i, row)| {
+            let mut row = row.clone();
+            row.extend((0..n).map(|j| if i == j { 1.0 } else { 0.0 }));
+            row
+        })
+        .collect();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|x, y| work[*x][col].abs().partial_cmp(&work[*y][col].abs()).unwrap())
+            .unwrap();
+        if work[pivot][col].abs() <= SINGULAR * scale {
+            return None;
+        }
+        work.swap(col, pivot);
+        let p = work[col][col];
+        for v in work[col].iter_mut() {
+            *v /= p;
+        }
+        let pivot_row = work[col].clone();
+        for (r, row) in work.iter_mut().enumerate() {
+            let factor = row[col];
+            if r != col && factor != 0.0 {
+                for (v, p) in row.iter_mut().zip(pivot_row.iter()) {
+                    *v -= factor * p;
+                }
+            }
+        }
+    }
+    Some(work.into_iter().map(|row| row[n..].to_vec()).collect())
+}
+
+// Weighted least squares solution of g x = h, and its covariance
+// (g' w g)^-1 when w is the inverse covariance of h
+pub fn weighted_lstsq(g: &[Vec<f64>], w: &[Vec<f64>], h: &[f64]) -> Option<(Vec<f64>, Matrix)> {
+    let gtw = mul(&transpose(g), w);
+    let cov = inverse(&mul(&gtw, g))?;
+    let x = mul_vec(&cov, &mul_vec(&gtw, h));
+    Some((x, cov))
+}
+
+pub fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn norm(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+pub fn scale(a: [f64; 3], s: f64) -> [f64; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
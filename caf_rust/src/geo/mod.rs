@@ -0,0 +1,451 @@
+// Emitter geolocation from CAF measurements
+// A TDOA between receivers i and j is the arrival time at j minus the
+// arrival time at i, and an FDOA the received frequency at j minus
+// that at i, matching entry (i, j) of a CafMatrix. The emitter is
+// taken to be stationary; receivers may move, which is what gives
+// FDOA. A Locator starts from the closed-form Chan/Ho TDOA solution
+// and refines it by Levenberg-Marquardt damped Gauss-Newton over all
+// measurements, weighted by their standard deviations. The inverse of
+// the final normal matrix is the covariance of the position.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::caf::CafMatrix;
+
+mod chan;
+mod earth;
+mod linalg;
+
+pub use earth::{Geodetic, WGS84_A, WGS84_F};
+
+use chan::{chan_ho, RangeDifference};
+use linalg::{dot, inverse, mul_vec, norm, scale, sub, Matrix};
+
+// Metres per second
+pub const SPEED_OF_LIGHT: f64 = 299792458.0;
+
+// Refinement stops once a step moves less than this many metres
+const DEFAULT_TOLERANCE_M: f64 = 1e-3;
+const DEFAULT_MAX_ITERATIONS: usize = 50;
+// Damping beyond which no step lowers the cost
+const MAX_DAMPING: f64 = 1e12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Frame {
+    Flat2d, // x, y metres on a plane, z components are ignored
+    Flat3d, // x, y, z metres
+    Ecef, // WGS84 earth-centred earth-fixed metres
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Receiver {
+    pub position: [f64; 3], // metres
+    pub velocity: [f64; 3], // metres per second
+}
+
+impl Receiver {
+
+    // Constructor for a stationary receiver
+    pub fn new(position: [f64; 3]) -> Self {
+        Receiver { position, velocity: [0.0; 3] }
+    }
+
+    // Constructor for a moving receiver
+    pub fn moving(position: [f64; 3], velocity: [f64; 3]) -> Self {
+        Receiver { position, velocity }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Measurement {
+    Tdoa { i: usize, j: usize, value_s: f64, sigma_s: f64 }, // time at j - time at i
+    Fdoa { i: usize, j: usize, value_hz: f64, sigma_hz: f64 }, // frequency at j - frequency at i
+}
+
+impl Measurement {
+
+    // Refined TDOA of every pair a CafMatrix computed, and their FDOA
+    // if given its standard deviation
+    pub fn from_matrix(matrix: &CafMatrix, sigma_s: f64, sigma_hz: Option<f64>) -> Vec<Self> {
+        let mut measurements = Vec::new();
+        for (i, j) in matrix.pairs() {
+            let peak = matrix.get(i, j).unwrap();
+            measurements.push(Measurement::Tdoa { i, j, value_s: peak.refined_lag_s, sigma_s });
+            if let Some(sigma_hz) = sigma_hz {
+                measurements.push(Measurement::Fdoa { i, j, value_hz: peak.refined_freq_hz,
+                    sigma_hz });
+            }
+        }
+        measurements
+    }
+
+    // Receivers (i, j)
+    pub fn receivers(&self) -> (usize, usize) {
+        match *self {
+            Measurement::Tdoa { i, j, .. } | Measurement::Fdoa { i, j, .. } => (i, j),
+        }
+    }
+
+    fn value_sigma(&self) -> (f64, f64) {
+        match *self {
+            Measurement::Tdoa { value_s, sigma_s, .. } => (value_s, sigma_s),
+            Measurement::Fdoa { value_hz, sigma_hz, .. } => (value_hz, sigma_hz),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Solution {
+    pub position: [f64; 3], // in the locator's frame, z is 0 for Flat2d
+    pub covariance: [[f64; 3]; 3], // of position in m^2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geodetic: Option<Geodetic>, // Ecef only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub covariance_enu: Option<[[f64; 3]; 3]>, // Ecef only, east/north/up at position
+    pub initial: [f64; 3], // where refinement started
+    pub residuals: Vec<f64>, // measured - predicted, one per measurement in its units
+    pub chi_squared: f64, // sum of squared residuals over sigma, with any altitude
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl Solution {
+
+    // Root of the covariance's trace, one accuracy figure in metres
+    pub fn rms_error_m(&self) -> f64 {
+        (0..3).map(|k| self.covariance[k][k]).sum::<f64>().sqrt()
+    }
+}
+
+pub struct Locator {
+    frame: Frame,
+    receivers: Vec<Receiver>,
+    carrier_hz: Option<f64>, // needed for FDOA
+    altitude: Option<(f64, f64)>, // known emitter height and its sigma in metres
+    initial: Option<[f64; 3]>,
+    max_iterations: usize,
+    tolerance_m: f64,
+}
+
+impl Locator {
+
+    // Constructor, TDOA only until given a carrier frequency
+    pub fn new(frame: Frame, receivers: Vec<Receiver>) -> Self {
+        Locator {
+            frame,
+            receivers,
+            carrier_hz: None,
+            altitude: None,
+            initial: None,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            tolerance_m: DEFAULT_TOLERANCE_M,
+        }
+    }
+
+    // Carrier the FDOAs were measured at, they scale with it
+    pub fn with_carrier(mut self, carrier_hz: f64) -> Self {
+        self.carrier_hz = Some(carrier_hz);
+        self
+    }
+
+    // Hold the emitter near a known height: z for Flat3d, height above
+    // the ellipsoid for Ecef. Flat2d has no height to hold
+    pub fn with_altitude(mut self, alt_m: f64, sigma_m: f64) -> Self {
+        self.altitude = Some((alt_m, sigma_m));
+        self
+    }
+
+    // Start refinement here instead of from the closed-form solution
+    pub fn with_initial_guess(mut self, position: [f64; 3]) -> Self {
+        self.initial = Some(position);
+        self
+    }
+
+    // Limit on refinement iterations
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    // Step size in metres at which refinement stops
+    pub fn with_tolerance(mut self, tolerance_m: f64) -> Self {
+        self.tolerance_m = tolerance_m;
+        self
+    }
+
+    // Closed-form Chan/Ho position from the TDOAs to the receiver most
+    // of them share, the better fitting of its candidates over all
+    // measurements. None if too few TDOAs share a receiver
+    pub fn initial_estimate(&self, measurements: &[Measurement]) -> Option<[f64; 3]> {
+        let mut counts = vec![0; self.receivers.len()];
+        for m in measurements {
+            if let Measurement::Tdoa { i, j, .. } = *m {
+                counts[i] += 1;
+                counts[j] += 1;
+            }
+        }
+        let reference = (0..counts.len()).max_by_key(|k| (counts[*k], usize::MAX - k))?;
+
+        // Range differences to the reference, one per receiver
+        let mut used = vec![false; self.receivers.len()];
+        used[reference] = true;
+        let mut diffs = Vec::new();
+        for m in measurements {
+            if let Measurement::Tdoa { i, j, value_s, sigma_s } = *m {
+                let (other, d) = if i == reference {
+                    (j, value_s)
+                } else if j == reference {
+                    (i, -value_s)
+                } else {
+                    continue;
+                };
+                if !used[other] {
+                    used[other] = true;
+                    diffs.push(RangeDifference {
+                        position: self.position(other),
+                        d: d * SPEED_OF_LIGHT,
+                        sigma: sigma_s * SPEED_OF_LIGHT,
+                    });
+                }
+            }
+        }
+
+        chan_ho(self.position(reference), &diffs, self.dims()).into_iter()
+            .map(|e| (self.cost(measurements, e), e))
+            .filter(|(cost, _)| cost.is_finite())
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, e)| e)
+    }
+
+    // Locate the emitter. Refinement starts from the initial guess if
+    // given, else the closed-form solution, else the receivers' centroid
+    pub fn solve(&self, measurements: &[Measurement]) -> io::Result<Solution> {
+
+        // Sanity
+        self.check(measurements)?;
+
+        let initial = match self.initial {
+            Some(position) => self.flatten(position),
+            None => self.initial_estimate(measurements).unwrap_or_else(|| self.centroid()),
+        };
+
+        // Levenberg-Marquardt: the Gauss-Newton step, damped until it
+        // lowers the cost. When no step does we are at a minimum
+        let dims = self.dims();
+        let mut position = initial;
+        let mut cost = self.cost(measurements, position);
+        let mut damping = 1e-3;
+        let mut iterations = 0;
+        let mut converged = false;
+        while !converged && iterations < self.max_iterations {
+            iterations += 1;
+            let (normal, gradient) = self.normal_equations(measurements, position);
+            loop {
+                let mut damped = normal.clone();
+                for (k, row) in damped.iter_mut().enumerate() {
+                    row[k] *= 1.0 + damping;
+                }
+                if let Some(inv) = inverse(&damped) {
+                    let step = mul_vec(&inv, &gradient);
+                    let mut next = position;
+                    for (v, s) in next.iter_mut().zip(step.iter()) {
+                        *v += s;
+                    }
+                    let next_cost = self.cost(measurements, next);
+                    if next_cost <= cost {
+                        converged = norm(sub(next, position)) < self.tolerance_m;
+                        position = next;
+                        cost = next_cost;
+                        damping = (damping / 10.0).max(1e-12);
+                        break;
+                    }
+                }
+                damping *= 10.0;
+                if damping > MAX_DAMPING {
+                    converged = true;
+                    break;
+                }
+            }
+        }
+
+        // Covariance of the position
+        let (normal, _) = self.normal_equations(measurements, position);
+        let inv = inverse(&normal).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            "measurements do not fix the emitter position"))?;
+        let mut covariance = [[0.0; 3]; 3];
+        for r in 0..dims {
+            for c in 0..dims {
+                covariance[r][c] = inv[r][c];
+            }
+        }
+
+        let (geodetic, covariance_enu) = match self.frame {
+            Frame::Ecef => {
+                let geodetic = Geodetic::from_ecef(position);
+                (Some(geodetic), Some(geodetic.enu_covariance(&covariance)))
+            },
+            _ => (None, None),
+        };
+        let residuals = measurements.iter()
+            .map(|m| m.value_sigma().0 - self.predict(m, position).0)
+            .collect();
+        Ok(Solution {
+            position,
+            covariance,
+            geodetic,
+            covariance_enu,
+            initial,
+            residuals,
+            chi_squared: cost,
+            iterations,
+            converged,
+        })
+    }
+
+    fn check(&self, measurements: &[Measurement]) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        let n = self.receivers.len();
+        for m in measurements {
+            let (i, j) = m.receivers();
+            if i >= n || j >= n || i == j {
+                return invalid(format!("measurement between receivers {} and {} of {}", i, j, n));
+            }
+            let sigma = m.value_sigma().1;
+            if sigma.is_nan() || sigma <= 0.0 {
+                return invalid(format!("standard deviation {} is not positive", sigma));
+            }
+            if let (Measurement::Fdoa { .. }, None) = (m, self.carrier_hz) {
+                return invalid("FDOA needs the carrier frequency".to_string());
+            }
+        }
+        let held = if self.altitude.is_some() && self.frame != Frame::Flat2d { 1 } else { 0 };
+        let rows = measurements.len() + held;
+        if rows < self.dims() {
+            return invalid(format!("{} measurements cannot fix {} coordinates", rows, self.dims()));
+        }
+        Ok(())
+    }
+
+    fn dims(&self) -> usize {
+        match self.frame {
+            Frame::Flat2d => 2,
+            Frame::Flat3d | Frame::Ecef => 3,
+        }
+    }
+
+    // Drop z on the plane
+    fn flatten(&self, v: [f64; 3]) -> [f64; 3] {
+        match self.frame {
+            Frame::Flat2d => [v[0], v[1], 0.0],
+            Frame::Flat3d | Frame::Ecef => v,
+        }
+    }
+
+    fn position(&self, k: usize) -> [f64; 3] {
+        self.flatten(self.receivers[k].position)
+    }
+
+    // Mean receiver position, at the emitter's altitude if known
+    fn centroid(&self) -> [f64; 3] {
+        let mut c = [0.0; 3];
+        for k in 0..self.receivers.len() {
+            let p = self.position(k);
+            for (c, p) in c.iter_mut().zip(p.iter()) {
+                *c += p / self.receivers.len() as f64;
+            }
+        }
+        match (self.frame, self.altitude) {
+            (Frame::Flat3d, Some((alt_m, _))) => [c[0], c[1], alt_m],
+            (Frame::Ecef, Some((alt_m, _))) => {
+                let g = Geodetic::from_ecef(c);
+                Geodetic::new(g.lat_deg, g.lon_deg, alt_m).to_ecef()
+            },
+            _ => c,
+        }
+    }
+
+    // Range from receiver k to e and the unit vector towards e
+    fn range(&self, k: usize, e: [f64; 3]) -> (f64, [f64; 3]) {
+        let d = sub(e, self.position(k));
+        let r = norm(d);
+        (r, scale(d, 1.0 / r))
+    }
+
+    // Receiver k's velocity towards e, which is minus the range rate,
+    // and its gradient
+    fn closing_speed(&self, k: usize, e: [f64; 3]) -> (f64, [f64; 3]) {
+        let (r, u) = self.range(k, e);
+        let v = self.flatten(self.receivers[k].velocity);
+        let vu = dot(v, u);
+        (vu, scale(sub(v, scale(u, vu)), 1.0 / r))
+    }
+
+    // Predicted measurement at e and its gradient
+    fn predict(&self, m: &Measurement, e: [f64; 3]) -> (f64, [f64; 3]) {
+        match *m {
+            Measurement::Tdoa { i, j, .. } => {
+                let (ri, ui) = self.range(i, e);
+                let (rj, uj) = self.range(j, e);
+                ((rj - ri) / SPEED_OF_LIGHT, scale(sub(uj, ui), 1.0 / SPEED_OF_LIGHT))
+            },
+            Measurement::Fdoa { i, j, .. } => {
+                // Received frequency is carrier * (1 + closing speed / c)
+                let k = self.carrier_hz.unwrap() / SPEED_OF_LIGHT;
+                let (si, gi) = self.closing_speed(i, e);
+                let (sj, gj) = self.closing_speed(j, e);
+                (k * (sj - si), scale(sub(gj, gi), k))
+            },
+        }
+    }
+
+    // (predicted, measured, sigma, gradient) of the altitude constraint
+    fn altitude_row(&self, e: [f64; 3]) -> Option<(f64, f64, f64, [f64; 3])> {
+        let (alt_m, sigma_m) = self.altitude?;
+        match self.frame {
+            Frame::Flat2d => None,
+            Frame::Flat3d => Some((e[2], alt_m, sigma_m, [0.0, 0.0, 1.0])),
+            Frame::Ecef => {
+                let g = Geodetic::from_ecef(e);
+                Some((g.alt_m, alt_m, sigma_m, g.enu_axes()[2]))
+            },
+        }
+    }
+
+    // Residuals over sigma and their gradients, measurements then altitude
+    fn rows(&self, measurements: &[Measurement], e: [f64; 3]) -> Vec<(f64, [f64; 3])> {
+        let mut rows: Vec<(f64, [f64; 3])> = measurements.iter()
+            .map(|m| {
+                let (value, sigma) = m.value_sigma();
+                let (predicted, gradient) = self.predict(m, e);
+                ((value - predicted) / sigma, scale(gradient, 1.0 / sigma))
+            })
+            .collect();
+        if let Some((predicted, value, sigma, gradient)) = self.altitude_row(e) {
+            rows.push(((value - predicted) / sigma, scale(gradient, 1.0 / sigma)));
+        }
+        rows
+    }
+
+    // Sum of squared residuals over sigma
+    fn cost(&self, measurements: &[Measurement], e: [f64; 3]) -> f64 {
+        self.rows(measurements, e).iter().map(|(r, _)| r * r).sum()
+    }
+
+    // J'J and J'r over the frame's coordinates
+    fn normal_equations(&self, measurements: &[Measurement], e: [f64; 3]) -> (Matrix, Vec<f64>) {
+        let dims = self.dims();
+        let mut normal = vec![vec![0.0; dims]; dims];
+        let mut gradient = vec![0.0; dims];
+        for (r, g) in self.rows(measurements, e) {
+            for a in 0..dims {
+                gradient[a] += g[a] * r;
+                for b in 0..dims {
+                    normal[a][b] += g[a] * g[b];
+                }
+            }
+        }
+        (normal, gradient)
+    }
+}
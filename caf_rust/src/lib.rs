@@ -1,2 +1,4 @@
 pub mod caf;
+pub mod geo;
+pub mod render;
 pub mod utils;
@@ -1,32 +1,679 @@
-// TODO
-// Use CLAP to take in two c64 files as arguments
+// Compute the CAF of a needle against a haystack and report the peak
+// e.g. caf_rust needle.cu8 haystack.cu8 --format cu8 --fs 2400000
 
-mod caf;
-mod utils;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
 
-use caf::{CafSurface, CafRustFFTIterRayon};
-use utils::read_file_c64;
+use clap::{value_t, App, AppSettings, Arg};
+use num_complex::Complex64;
+
+use caf_rust::caf::{analytic_signal, Accumulation, CafMatrix, CafResult, CafStream, CafSurface,
+    CafSurfaceRow, CafRustFFTIterRayon, CafTiming, Ddc, Pairing, SegmentedCaf, Weighting};
+use caf_rust::render::{terminal_size, Colormap, Heatmap, Scale, TextStyle};
+use caf_rust::utils::{deinterleave, is_gr_meta, is_sigmf, read_channels, read_channels_range,
+    read_file, read_range, read_surface, read_surface_header, read_wav, sigmf_paths,
+    write_matrix, write_results, write_surface, ChunkedReader, DetectionWriter, Endian, GrMeta,
+    MappedCapture, MatlabIO, NumpyIO, OutputFormat, SampleFormat, SampleRange, SigMFMeta,
+    SocketSpec, SourceFile, SurfaceHeader, Timestamp, UdpHeader, Wav};
 
 fn main() {
+    let start = Instant::now();
+
+    // Parse the command line
+    let matches = App::new("caf_rust")
+        .about("Cross ambiguity function of two IQ recordings")
+        .setting(AppSettings::AllowNegativeNumbers)
+        .arg(Arg::with_name("needle")
+            .help("Reference signal to search for (raw IQ, SigMF, GNU Radio meta or WAV)")
+            .default_value("../data/chirp_0_raw.c64"))
+        .arg(Arg::with_name("haystack")
+            .help("Capture to search within (raw IQ, SigMF, GNU Radio meta or WAV), or a socket \
+                to stream from: tcp://host:port, tcp-listen://addr:port or udp://addr:port")
+            .default_value("../data/chirp_0_T+202samp_F+69.25Hz.c64"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .help("Sample format: ci8, cu8, ci16, cf32 or cf64")
+            .default_value("cf32"))
+        .arg(Arg::with_name("endian")
+            .long("endian")
+            .help("Byte order of multi-byte formats: le or be")
+            .default_value("le"))
+        .arg(Arg::with_name("fs")
+            .long("fs")
+            .help("Sample rate in Hz, taken from SigMF, GNU Radio meta or WAV headers if not \
+                given")
+            .default_value("48000"))
+        .arg(Arg::with_name("fmin")
+            .long("fmin")
+            .help("Lowest frequency offset to search in Hz")
+            .default_value("-100"))
+        .arg(Arg::with_name("fmax")
+            .long("fmax")
+            .help("Highest frequency offset to search in Hz (exclusive)")
+            .default_value("100"))
+        .arg(Arg::with_name("fstep")
+            .long("fstep")
+            .help("Frequency offset step in Hz")
+            .default_value("0.5"))
+        .arg(Arg::with_name("wav-channel")
+            .long("wav-channel")
+            .help("Use one channel of WAV inputs as a real signal, stereo is read as I/Q otherwise")
+            .takes_value(true))
+        .arg(Arg::with_name("ddc")
+            .long("ddc")
+            .help("Down-convert both inputs, centering this frequency in Hz at 0Hz")
+            .takes_value(true))
+        .arg(Arg::with_name("decimate")
+            .long("decimate")
+            .help("Decimate both inputs by this factor after down-conversion")
+            .takes_value(true))
+        .arg(Arg::with_name("channels")
+            .long("channels")
+            .help("Treat the needle as a capture of this many interleaved channels and \
+                estimate TDOA/FDOA between them")
+            .takes_value(true))
+        .arg(Arg::with_name("reference")
+            .long("reference")
+            .help("With --channels, compare every channel with this one rather than all pairs")
+            .requires("channels")
+            .takes_value(true))
+        .arg(Arg::with_name("stream")
+            .long("stream")
+            .help("Search the haystack (raw IQ or SigMF, a FIFO, - for stdin, or a socket) as it \
+                arrives, reporting each needle found"))
+        .arg(Arg::with_name("udp-header")
+            .long("udp-header")
+            .help("Header on each UDP datagram: none, or seqnum for gr-network's sequence numbers")
+            .default_value("none"))
+        .arg(Arg::with_name("threshold")
+            .long("threshold")
+            .help("With --stream, the normalized score from 0 to 1 a detection must reach")
+            .default_value("0.5"))
+        .arg(Arg::with_name("needle-range")
+            .long("needle-range")
+            .help("Part of the needle to use, e.g. 0.5s..0.6s or 1000+4096")
+            .takes_value(true))
+        .arg(Arg::with_name("haystack-range")
+            .long("haystack-range")
+            .help("Part of the haystack to search, defaults to the needle's length from the start")
+            .takes_value(true))
+        .arg(Arg::with_name("needle-time")
+            .long("needle-time")
+            .help("When the needle's first sample was recorded, ISO 8601 or Unix seconds. \
+                Taken from SigMF or GNU Radio meta if not given")
+            .takes_value(true))
+        .arg(Arg::with_name("haystack-time")
+            .long("haystack-time")
+            .help("When the haystack's first sample was recorded, as --needle-time. With both, \
+                the TDOA is reported as an absolute time difference")
+            .takes_value(true))
+        .arg(Arg::with_name("npy")
+            .long("npy")
+            .help("Save the |xcor|^2 surface as a .npy file")
+            .takes_value(true))
+        .arg(Arg::with_name("npz")
+            .long("npz")
+            .help("Save the surface with its frequency and lag axes as a .npz file")
+            .takes_value(true))
+        .arg(Arg::with_name("mat")
+            .long("mat")
+            .help("Save the surface, its axes and the peaks as a MATLAB .mat file")
+            .takes_value(true))
+        .arg(Arg::with_name("cache")
+            .long("cache")
+            .help("Reuse the surface saved in this file if it matches the inputs, else save it \
+                there")
+            .takes_value(true))
+        .arg(Arg::with_name("heatmap")
+            .long("heatmap")
+            .help("Plot the surface to a .png or .svg file")
+            .takes_value(true))
+        .arg(Arg::with_name("lag-slice")
+            .long("lag-slice")
+            .help("Plot the surface against lag at the peak frequency (.png or .svg)")
+            .takes_value(true))
+        .arg(Arg::with_name("freq-slice")
+            .long("freq-slice")
+            .help("Plot the surface against frequency at the peak lag (.png or .svg)")
+            .takes_value(true))
+        .arg(Arg::with_name("preview")
+            .long("preview")
+            .help("Draw the surface and the cuts through its peak in the terminal"))
+        .arg(Arg::with_name("preview-style")
+            .long("preview-style")
+            .help("Terminal preview drawing: blocks or braille")
+            .default_value("blocks"))
+        .arg(Arg::with_name("scale")
+            .long("scale")
+            .help("Plot scale: linear, db or db with a range, e.g. db40")
+            .default_value("db"))
+        .arg(Arg::with_name("colormap")
+            .long("colormap")
+            .help("Heatmap colours: viridis, magma, inferno, gray or jet")
+            .default_value("viridis"))
+        .arg(Arg::with_name("output")
+            .long("output")
+            .help("Result format: text, json, jsonl or csv")
+            .default_value("text"))
+        .arg(Arg::with_name("peaks")
+            .long("peaks")
+            .help("Number of peaks to report in json, jsonl and csv output")
+            .default_value("1"))
+        .arg(Arg::with_name("segment-len")
+            .long("segment-len")
+            .help("Integrate over segments of this many samples, reporting each segment's peak \
+                too")
+            .conflicts_with("cache")
+            .takes_value(true))
+        .arg(Arg::with_name("accumulate")
+            .long("accumulate")
+            .help("With --segment-len, how segments add up: coherent or noncoherent")
+            .default_value("coherent"))
+        .arg(Arg::with_name("annotate")
+            .long("annotate")
+            .help("Write the peak back to the haystack's SigMF metadata"))
+        .get_matches();
+    let format = value_t!(matches, "format", SampleFormat).unwrap_or_else(|e| e.exit());
+    let endian = value_t!(matches, "endian", Endian).unwrap_or_else(|e| e.exit());
+    let mut fs = value_t!(matches, "fs", u32).unwrap_or_else(|e| e.exit());
+    let fmin = value_t!(matches, "fmin", f64).unwrap_or_else(|e| e.exit());
+    let fmax = value_t!(matches, "fmax", f64).unwrap_or_else(|e| e.exit());
+    let fstep = value_t!(matches, "fstep", f64).unwrap_or_else(|e| e.exit());
+    let scale = value_t!(matches, "scale", Scale).unwrap_or_else(|e| e.exit());
+    let colormap = value_t!(matches, "colormap", Colormap).unwrap_or_else(|e| e.exit());
+    let preview_style = value_t!(matches, "preview-style", TextStyle).unwrap_or_else(|e| e.exit());
+    let output = value_t!(matches, "output", OutputFormat).unwrap_or_else(|e| e.exit());
+    let max_peaks = value_t!(matches, "peaks", usize).unwrap_or_else(|e| e.exit()).max(1);
+    let threshold = value_t!(matches, "threshold", f64).unwrap_or_else(|e| e.exit());
+    let accumulation = value_t!(matches, "accumulate", Accumulation).unwrap_or_else(|e| e.exit());
+    let udp_header = value_t!(matches, "udp-header", UdpHeader).unwrap_or_else(|e| e.exit());
+    let needle_range = optional_value::<SampleRange>(&matches, "needle-range");
+    let haystack_range = optional_value::<SampleRange>(&matches, "haystack-range");
+    let wav_channel = optional_value::<usize>(&matches, "wav-channel");
+    let ddc_center = optional_value::<f64>(&matches, "ddc");
+    let decimation = optional_value::<usize>(&matches, "decimate");
+    let n_channels = optional_value::<usize>(&matches, "channels");
+    let reference = optional_value::<usize>(&matches, "reference");
+    let segment_len = optional_value::<usize>(&matches, "segment-len");
+    let needle_time = optional_value::<Timestamp>(&matches, "needle-time");
+    let haystack_time = optional_value::<Timestamp>(&matches, "haystack-time");
+
+    // Pick up any SigMF metadata first, it may set the sample rate
+    let needle_filename = matches.value_of("needle").unwrap();
+    let haystack_filename = matches.value_of("haystack").unwrap();
+    let needle_meta = load_meta(needle_filename).unwrap();
+    let mut haystack_meta = load_meta(haystack_filename).unwrap();
+    let needle_wav = load_wav(needle_filename).unwrap();
+    let haystack_wav = load_wav(haystack_filename).unwrap();
+    let needle_gr = load_gr_meta(needle_filename).unwrap();
+    let haystack_gr = load_gr_meta(haystack_filename).unwrap();
+
+    // Prefer the recorded sample rate unless one was given
+    if matches.occurrences_of("fs") == 0 {
+        let recorded = haystack_meta.iter().chain(needle_meta.iter())
+            .find_map(|meta| meta.sample_rate())
+            .or_else(|| haystack_gr.iter().chain(needle_gr.iter())
+                .map(|gr| gr.sample_rate())
+                .next())
+            .or_else(|| haystack_wav.iter().chain(needle_wav.iter())
+                .map(|wav| wav.fs as f64)
+                .next());
+        if let Some(rate) = recorded {
+            fs = rate.round() as u32;
+        }
+    }
+
+    // fmin to fmax in fstep steps
+    if !(fstep.is_finite() && fstep > 0.0) {
+        invalid_value("fstep", &format!("the step must be a positive number of Hz, not {}",
+            fstep));
+    }
+    if !(fmin.is_finite() && fmax.is_finite() && fmin < fmax) {
+        invalid_value("fmax", &format!("the search needs finite frequencies with --fmin below \
+            --fmax, not {}Hz to {}Hz", fmin, fmax));
+    }
+    let n_shifts = ((fmax - fmin) / fstep).ceil() as u64;
+    let shifts: Vec<f64> = (0..n_shifts)
+        .map(|i| fmin + (i as f64) * fstep)
+        .take_while(|shift| *shift < fmax)
+        .collect();
+
+    // A coherent multi-channel capture is searched channel against channel
+    if let Some(n_channels) = n_channels {
+        if n_channels < 2 {
+            invalid_value("channels", "a multi-channel capture needs at least 2 channels");
+        }
+        if let Some(r) = reference.filter(|r| *r >= n_channels) {
+            invalid_value("reference", &format!("channel {} isn't one of the {} channels \
+                (0 to {})", r, n_channels, n_channels - 1));
+        }
+        let (path, format, endian) = data_source(needle_filename, needle_meta.as_ref(),
+            format, endian).unwrap();
+        let channels = match (needle_gr.as_ref(), needle_range) {
+            (Some(gr), Some(range)) => {
+                // Ranges count frames of n_channels interleaved samples
+                let (start, count) = range.resolve(fs);
+                gr.read_range(start.saturating_mul(n_channels as u64),
+                    count.saturating_mul(n_channels))
+                    .map(|samples| deinterleave(&samples, n_channels))
+            },
+            (Some(gr), None) => gr.read_samples()
+                .map(|samples| deinterleave(&samples, n_channels)),
+            (None, Some(range)) => {
+                let (start, count) = range.resolve(fs);
+                read_channels_range(&path, format, endian, n_channels, start, count)
+            },
+            (None, None) => read_channels(&path, format, endian, n_channels),
+        }.unwrap();
+        let matrix = match reference {
+            Some(r) => CafRustFFTIterRayon::caf_matrix(&channels, &shifts, fs,
+                Pairing::Reference(r)),
+            None => CafMatrix::pairwise(&channels, &shifts, fs, Weighting::None),
+        };
+        write_matrix(&mut io::stdout(), &matrix, output).unwrap();
+        return;
+    }
 
     // Get signals 1 and 2 to compute the caf of
-    let needle = read_file_c64("../data/chirp_0_raw.c64").unwrap();
-    let mut haystack = read_file_c64("../data/chirp_0_T+202samp_F+69.25Hz.c64").unwrap();
+    let mut needle = match (needle_wav.as_ref(), needle_gr.as_ref()) {
+        (Some(wav), _) => wav_samples(wav, wav_channel, needle_range, fs).unwrap(),
+        (None, Some(gr)) => gr_meta_samples(gr, needle_range, fs).unwrap(),
+        (None, None) => load(needle_filename, needle_meta.as_ref(), format, endian,
+            needle_range, fs).unwrap(),
+    };
+
+    // A live haystack is searched block by block, as a socket always is
+    let socket = haystack_filename.parse::<SocketSpec>().ok();
+    if matches.is_present("stream") || socket.is_some() {
+        let (path, format, endian) = data_source(haystack_filename, haystack_meta.as_ref(),
+            format, endian).unwrap();
+        let (reader, format, endian): (Box<dyn Read>, _, _) = match (socket, path.as_str()) {
+            (Some(socket), _) => (socket.open(udp_header).unwrap(), format, endian),
+            (None, _) if haystack_gr.is_some() => {
+                let gr = haystack_gr.as_ref().unwrap();
+                let (format, endian) = gr.sample_format().unwrap();
+                (gr.data_reader().unwrap(), format, endian)
+            },
+            (None, "-") => (Box::new(io::stdin()), format, endian),
+            (None, path) => (Box::new(File::open(path).unwrap()), format, endian),
+        };
+        let caf = CafStream::new(&needle, &shifts, fs).with_threshold(threshold);
+        stream(caf, reader, format, endian, needle.len(), output).unwrap();
+        return;
+    }
+    let mut haystack = match (haystack_wav.as_ref(), haystack_range) {
+        (Some(wav), _) => wav_samples(wav, wav_channel, haystack_range, fs).unwrap(),
+        (None, range) if haystack_gr.is_some() => {
+            let gr = haystack_gr.as_ref().unwrap();
+            match range {
+                Some(_) => gr_meta_samples(gr, range, fs).unwrap(),
+                None => gr.read_range(0, needle.len()).unwrap(),
+            }
+        },
+        (None, Some(_)) => load(haystack_filename, haystack_meta.as_ref(), format, endian,
+            haystack_range, fs).unwrap(),
+        (None, None) => {
+            // Only map in as much haystack as we need
+            let (path, format, endian) = data_source(haystack_filename,
+                haystack_meta.as_ref(), format, endian).unwrap();
+            MappedCapture::open(&path, format, endian).unwrap().window(0, needle.len())
+        },
+    };
     haystack.resize(needle.len(), Default::default());
+    let (input_fs, input_len) = (fs, needle.len());
 
-    // -100Hz to 100Hz, 0.5Hz step
-    let mut shifts = Vec::new();
-    for shift_millihz in (-100000..100000).step_by(500) {
-        let shift = (shift_millihz as f64) / 1e3;
-        shifts.push(shift);
+    // Optionally bring both down to baseband at a lower rate
+    let mut backend = "CafRustFFTIterRayon".to_string();
+    let mut input_per_sample = 1; // input samples per surface sample
+    if ddc_center.is_some() || decimation.is_some() {
+        if let Some(factor) = decimation.filter(|d| *d == 0 || !(fs as usize).is_multiple_of(*d)) {
+            invalid_value("decimate", &format!("{} isn't a factor of the {}Hz sample rate",
+                factor, fs));
+        }
+        let ddc = Ddc::with_decimation(ddc_center.unwrap_or(0.0), fs, decimation.unwrap_or(1));
+        needle = ddc.process(&needle);
+        haystack = ddc.process(&haystack);
+        fs = ddc.output_rate();
+        input_per_sample = ddc.decimation() as i64;
+        backend = format!("{} after DDC at {}Hz, decimated by {}", backend,
+            ddc_center.unwrap_or(0.0), ddc.decimation());
     }
 
     // Get the CAF surface
-    let surface = CafRustFFTIterRayon::caf_surface(&needle, &haystack, &shifts, 48000);
-    let (freq, samp_idx) = CafRustFFTIterRayon::find_peak(surface);
+    let surface_start = Instant::now();
+    let mut segments = Vec::new();
+    let surface = match (segment_len, matches.value_of("cache")) {
+        (Some(len), _) => {
+            if len == 0 || len > needle.len() {
+                invalid_value("segment-len", &format!("segments must be 1 to {} samples long, \
+                    the length of the needle", needle.len()));
+            }
+            let segmented = SegmentedCaf::new(len)
+                .with_accumulation(accumulation)
+                .caf_surface(&needle, &haystack, &shifts, fs);
+            backend = format!("{}, {} segments of {} accumulated {}", backend,
+                segmented.segments.len(), segmented.segment_len, accumulation);
+            segments = segmented.segments;
+            segmented.surface
+        },
+        (None, Some(filename)) => {
+            let source = |filename, meta, wav, gr, range| {
+                match container_source(filename, wav, wav_channel, gr, range).transpose() {
+                    Some(source) => source,
+                    None => source_file(filename, meta, format, endian, range),
+                }
+            };
+            let sources = [
+                source(needle_filename, needle_meta.as_ref(), needle_wav.as_ref(),
+                    needle_gr.as_ref(), needle_range.map(|range| range.resolve(input_fs))).unwrap(),
+                source(haystack_filename, haystack_meta.as_ref(), haystack_wav.as_ref(),
+                    haystack_gr.as_ref(), Some(haystack_range.map(|range| range.resolve(input_fs))
+                        .unwrap_or((0, input_len)))).unwrap(),
+            ];
+            cached_surface(filename, &sources, &backend, &needle, &haystack, &shifts, fs).unwrap()
+        },
+        (None, None) => CafRustFFTIterRayon::caf_surface(&needle, &haystack, &shifts, fs),
+    };
+    let surface_s = surface_start.elapsed().as_secs_f64();
+    if let Some(filename) = matches.value_of("npy") {
+        surface.write_npy(filename).unwrap();
+    }
+    if let Some(filename) = matches.value_of("npz") {
+        surface.write_npz(filename).unwrap();
+    }
+    plot(&surface, fs, scale, colormap, &matches).unwrap();
+    if matches.is_present("preview") {
+        let (columns, lines) = terminal_size();
+        let heatmap = Heatmap::new(&surface, fs).unwrap().with_scale(scale);
+        print!("{}", heatmap.to_text(columns, lines, preview_style));
+    }
+
+    // Peaks, and how we got them
+    let mut result = CafResult::from_surface(&surface, fs, max_peaks);
+    result.config.needle = Some(needle_filename.to_string());
+    result.config.haystack = Some(haystack_filename.to_string());
+    result.config.needle_len = Some(needle.len());
+    result.config.haystack_len = Some(haystack.len());
+    result.backend = Some(backend);
+    result.segments = segments;
+    result.timing = Some(CafTiming { surface_s, total_s: start.elapsed().as_secs_f64() });
+
+    // With both recordings' start times the TDOA is absolute. Each
+    // input starts where its range does
+    let needle_start = start_time(needle_time, needle_meta.as_ref(), needle_gr.as_ref())
+        .unwrap()
+        .map(|time| time.add_samples(range_start(needle_range, input_fs), input_fs));
+    let haystack_start = start_time(haystack_time, haystack_meta.as_ref(), haystack_gr.as_ref())
+        .unwrap()
+        .map(|time| time.add_samples(range_start(haystack_range, input_fs), input_fs));
+    if let (Some(needle_start), Some(haystack_start)) = (needle_start, haystack_start) {
+        result = result.with_start_times(needle_start, haystack_start);
+    }
+    let (freq, lag) = (result.peak().freq_hz, result.peak().lag_samples);
+    if let Some(filename) = matches.value_of("mat") {
+        surface.write_mat(filename, fs, Some(&result)).unwrap();
+    }
 
     // Print the results
-    println!("Frequency offset: {:.1}Hz", freq);
-    println!("Time offset: {} samples ({:.3}ms)",
-        samp_idx, (samp_idx as f64) / 48.0);
+    write_results(&mut io::stdout(), &[result], output).unwrap();
+
+    // Record the peak alongside the haystack recording
+    if matches.is_present("annotate") {
+        match haystack_meta.as_mut() {
+            Some(meta) => {
+                // Back to samples of the whole recording. The peak is
+                // only known to within a search step
+                let start = range_start(haystack_range, input_fs) + lag * input_per_sample;
+                if start < 0 {
+                    eprintln!("--annotate: the needle starts {} samples before the recording, \
+                        skipping", -start);
+                    return;
+                }
+                meta.annotate_caf_peak(start as u64, input_len as u64, freq, fstep, "caf_peak");
+                let (meta_path, _) = sigmf_paths(haystack_filename);
+                meta.write(&meta_path.to_string_lossy()).unwrap();
+            },
+            None => eprintln!("--annotate needs a SigMF haystack, skipping"),
+        }
+    }
+}
+
+// SigMF metadata for filename, if it is a SigMF recording
+fn load_meta(filename: &str) -> io::Result<Option<SigMFMeta>> {
+    if is_sigmf(filename) {
+        let (meta_path, _) = sigmf_paths(filename);
+        Ok(Some(SigMFMeta::read(&meta_path.to_string_lossy())?))
+    } else {
+        Ok(None)
+    }
+}
+
+// Decoded WAV file, if filename is one
+fn load_wav(filename: &str) -> io::Result<Option<Wav>> {
+    if filename.to_lowercase().ends_with(".wav") {
+        Ok(Some(read_wav(filename)?))
+    } else {
+        Ok(None)
+    }
+}
+
+// GNU Radio file meta headers for filename, if it has them
+fn load_gr_meta(filename: &str) -> io::Result<Option<GrMeta>> {
+    if is_gr_meta(filename) {
+        Ok(Some(GrMeta::read(filename)?))
+    } else {
+        Ok(None)
+    }
+}
+
+// Samples of a GNU Radio meta recording, all or just a range
+fn gr_meta_samples(gr: &GrMeta, range: Option<SampleRange>, fs: u32)
+    -> io::Result<Vec<Complex64>> {
+
+    match range {
+        Some(range) => {
+            let (start, count) = range.resolve(fs);
+            gr.read_range(start, count)
+        },
+        None => gr.read_samples(),
+    }
+}
+
+// When the first sample of a recording was taken: the time given,
+// else what its metadata says (only read when no time was given)
+fn start_time(given: Option<Timestamp>, meta: Option<&SigMFMeta>, gr: Option<&GrMeta>)
+    -> io::Result<Option<Timestamp>> {
+
+    if given.is_some() {
+        return Ok(given);
+    }
+    match (meta, gr) {
+        (Some(meta), _) => meta.start_time(),
+        (None, Some(gr)) => Ok(gr.start_time()),
+        (None, None) => Ok(None),
+    }
+}
+
+// First sample of a range, 0 without one
+fn range_start(range: Option<SampleRange>, fs: u32) -> i64 {
+    range.map(|range| range.resolve(fs).0 as i64).unwrap_or(0)
+}
+
+// Complex samples from a WAV file: one channel as a real signal when
+// asked for, stereo as I/Q, otherwise the first channel as a real signal
+fn wav_samples(wav: &Wav, channel: Option<usize>, range: Option<SampleRange>, fs: u32)
+    -> io::Result<Vec<Complex64>> {
+
+    let samples = match channel {
+        None if wav.channels.len() == 2 => wav.iq()?,
+        _ => {
+            let channel = channel.unwrap_or(0);
+            let real = wav.channels.get(channel).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput, format!("no WAV channel {}", channel)))?;
+            analytic_signal(real)
+        },
+    };
+    Ok(match range {
+        Some(range) => {
+            let (start, count) = range.resolve(fs);
+            let start = (start as usize).min(samples.len());
+            samples[start..(start + count).min(samples.len())].to_vec()
+        },
+        None => samples,
+    })
+}
+
+// Where the samples live and how they're stored. SigMF recordings
+// override the command line format
+fn data_source(filename: &str, meta: Option<&SigMFMeta>, format: SampleFormat,
+    endian: Endian) -> io::Result<(String, SampleFormat, Endian)> {
+
+    match meta {
+        Some(meta) => {
+            let (_, data_path) = sigmf_paths(filename);
+            let (format, endian) = meta.sample_format()?;
+            Ok((data_path.to_string_lossy().into_owned(), format, endian))
+        },
+        None => Ok((filename.to_string(), format, endian)),
+    }
+}
+
+// Read all of a capture, or just the requested range of it
+fn load(filename: &str, meta: Option<&SigMFMeta>, format: SampleFormat, endian: Endian,
+    range: Option<SampleRange>, fs: u32) -> io::Result<Vec<Complex64>> {
+
+    let (path, format, endian) = data_source(filename, meta, format, endian)?;
+    match range {
+        Some(range) => {
+            let (start, count) = range.resolve(fs);
+            read_range(&path, format, endian, start, count)
+        },
+        None => read_file(&path, format, endian),
+    }
+}
+
+// Search a haystack as it arrives, block_len samples at a time,
+// writing each detection as soon as it is found
+fn stream(mut caf: CafStream, reader: Box<dyn Read>, format: SampleFormat, endian: Endian,
+    block_len: usize, output: OutputFormat) -> io::Result<()> {
+
+    let mut writer = DetectionWriter::new(io::stdout(), output);
+    for block in ChunkedReader::new(reader, format, endian, block_len) {
+        for detection in caf.push(&block?) {
+            writer.write(&detection)?;
+        }
+    }
+    for detection in caf.finish() {
+        writer.write(&detection)?;
+    }
+    Ok(())
+}
+
+// Identify the samples a surface was computed from: the bytes of a
+// raw or SigMF range and how they were decoded
+fn source_file(filename: &str, meta: Option<&SigMFMeta>, format: SampleFormat, endian: Endian,
+    range: Option<(u64, usize)>) -> io::Result<SourceFile> {
+
+    let (path, format, endian) = data_source(filename, meta, format, endian)?;
+    let sample_bytes = format.sample_bytes() as u64;
+    let source = match range {
+        Some((start, count)) => SourceFile::from_bytes(&path, start * sample_bytes,
+            count as u64 * sample_bytes)?.with_range(start, count),
+        None => SourceFile::from_path(&path)?,
+    };
+    Ok(source.with_decoding(&decoding(format, endian)))
+}
+
+// As source_file for WAV and GNU Radio meta recordings, whose samples
+// are interleaved with other channels or headers so the whole file is
+// hashed. None for anything else
+fn container_source(filename: &str, wav: Option<&Wav>, wav_channel: Option<usize>,
+    gr: Option<&GrMeta>, range: Option<(u64, usize)>) -> io::Result<Option<SourceFile>> {
+
+    let decoding = match (wav, gr) {
+        (Some(wav), _) => match wav_channel {
+            None if wav.channels.len() == 2 => "wav iq".to_string(),
+            channel => format!("wav channel {}", channel.unwrap_or(0)),
+        },
+        (None, Some(gr)) => {
+            let (format, endian) = gr.sample_format()?;
+            format!("gr_meta {}", decoding(format, endian))
+        },
+        (None, None) => return Ok(None),
+    };
+    let source = SourceFile::from_path(filename)?.with_decoding(&decoding);
+    Ok(Some(match range {
+        Some((start, count)) => source.with_range(start, count),
+        None => source,
+    }))
+}
+
+// Sample format and byte order, e.g. "ci16 le"
+fn decoding(format: SampleFormat, endian: Endian) -> String {
+    let endian = match endian {
+        Endian::Little => "le",
+        Endian::Big => "be",
+    };
+    format!("{} {}", format, endian)
+}
+
+// Load the surface cached in filename if it was made from the same
+// inputs and search, otherwise compute it and cache it there
+fn cached_surface(filename: &str, sources: &[SourceFile], backend: &str, needle: &[Complex64],
+    haystack: &[Complex64], shifts: &[f64], fs: u32) -> io::Result<Vec<CafSurfaceRow>> {
+
+    if let Ok(header) = read_surface_header(filename) {
+        match header.check_search(fs, shifts).and_then(|_| header.check_sources(sources)) {
+            Ok(()) if header.backend.as_deref() == Some(backend) =>
+                return Ok(read_surface(filename)?.1),
+            Ok(()) => eprintln!("Recomputing cached surface: made by {}",
+                header.backend.as_deref().unwrap_or("an unknown backend")),
+            Err(e) => eprintln!("Recomputing cached surface: {}", e),
+        }
+    }
+    let surface = CafRustFFTIterRayon::caf_surface(needle, haystack, shifts, fs);
+    let header = sources.iter().fold(
+        SurfaceHeader::new(&surface, fs).with_backend(backend),
+        |header, source| header.with_source(source.clone()));
+    write_surface(filename, &surface, &header)?;
+    Ok(surface)
+}
+
+// Write whichever plots were asked for
+fn plot(surface: &[CafSurfaceRow], fs: u32, scale: Scale, colormap: Colormap,
+    matches: &clap::ArgMatches) -> io::Result<()> {
+
+    let wanted = ["heatmap", "lag-slice", "freq-slice"].iter().any(|name| matches.is_present(name));
+    if !wanted {
+        return Ok(());
+    }
+    let heatmap = Heatmap::new(surface, fs)?.with_scale(scale).with_colormap(colormap);
+    let (row, col) = heatmap.peak();
+    if let Some(filename) = matches.value_of("heatmap") {
+        heatmap.write(filename)?;
+    }
+    if let Some(filename) = matches.value_of("lag-slice") {
+        heatmap.row_slice(row).write(filename)?;
+    }
+    if let Some(filename) = matches.value_of("freq-slice") {
+        heatmap.column_slice(col).write(filename)?;
+    }
+    Ok(())
+}
+
+// Parse an optional argument, exiting with clap's error on bad input
+fn optional_value<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(value_t!(matches, name, T).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    }
+}
+
+// Exit with clap's error for an argument that parsed but can't be used
+fn invalid_value(name: &str, message: &str) -> ! {
+    clap::Error::with_description(&format!("Invalid value for '--{}': {}", name, message),
+        clap::ErrorKind::InvalidValue).exit()
 }
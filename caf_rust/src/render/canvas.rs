@@ -0,0 +1,212 @@
+// RGB raster with the few drawing primitives the plots need
+// Text uses a built-in 5x7 bitmap font covering digits, letters (drawn
+// upper case) and the punctuation that shows up in axis labels.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use super::encode_png;
+
+// Character cell of the bitmap font, 5x7 glyphs plus a column of spacing
+pub const GLYPH_WIDTH: usize = 6;
+pub const GLYPH_HEIGHT: usize = 7;
+
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>, // RGB, row-major
+}
+
+impl Canvas {
+
+    // Constructor, filled with color
+    pub fn new(width: usize, height: usize, color: [u8; 3]) -> Self {
+        let pixels = color.iter().copied().cycle().take(width * height * 3).collect();
+        Canvas { width, height, pixels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Raw RGB bytes
+    pub fn rgb(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let idx = (y * self.width + x) * 3;
+        [self.pixels[idx], self.pixels[idx + 1], self.pixels[idx + 2]]
+    }
+
+    // Set one pixel, anything off the canvas is ignored
+    pub fn set(&mut self, x: isize, y: isize, color: [u8; 3]) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let idx = (y as usize * self.width + x as usize) * 3;
+        self.pixels[idx..idx + 3].copy_from_slice(&color);
+    }
+
+    pub fn fill_rect(&mut self, x: isize, y: isize, width: usize, height: usize, color: [u8; 3]) {
+        for j in 0..height as isize {
+            for i in 0..width as isize {
+                self.set(x + i, y + j, color);
+            }
+        }
+    }
+
+    // Outline of a rectangle
+    pub fn rect(&mut self, x: isize, y: isize, width: usize, height: usize, color: [u8; 3]) {
+        let (right, bottom) = (x + width as isize - 1, y + height as isize - 1);
+        self.line(x, y, right, y, color);
+        self.line(x, bottom, right, bottom, color);
+        self.line(x, y, x, bottom, color);
+        self.line(right, y, right, bottom, color);
+    }
+
+    // Bresenham line, both ends included
+    pub fn line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: [u8; 3]) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        loop {
+            self.set(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // One pixel wide ring of radius r
+    pub fn circle(&mut self, cx: isize, cy: isize, r: isize, color: [u8; 3]) {
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if (dx * dx + dy * dy - r * r).abs() <= r {
+                    self.set(cx + dx, cy + dy, color);
+                }
+            }
+        }
+    }
+
+    // Copy another canvas in with its top left corner at (x, y)
+    pub fn blit(&mut self, other: &Canvas, x: isize, y: isize) {
+        for j in 0..other.height {
+            for i in 0..other.width {
+                self.set(x + i as isize, y + j as isize, other.pixel(i, j));
+            }
+        }
+    }
+
+    // Text with its top left corner at (x, y)
+    pub fn text(&mut self, x: isize, y: isize, text: &str, color: [u8; 3]) {
+        for (n, c) in text.chars().enumerate() {
+            let left = x + (n * GLYPH_WIDTH) as isize;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..5 {
+                    if bits & (0x10 >> col) != 0 {
+                        self.set(left + col, y + row as isize, color);
+                    }
+                }
+            }
+        }
+    }
+
+    // Text rotated a quarter turn anticlockwise, reading upwards from
+    // its bottom left corner at (x, y)
+    pub fn text_vertical(&mut self, x: isize, y: isize, text: &str, color: [u8; 3]) {
+        for (n, c) in text.chars().enumerate() {
+            let bottom = y - (n * GLYPH_WIDTH) as isize;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..5 {
+                    if bits & (0x10 >> col) != 0 {
+                        self.set(x + row as isize, bottom - col, color);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        encode_png(self.width, self.height, &self.pixels)
+    }
+
+    pub fn write_png(&self, filename: &str) -> io::Result<()> {
+        File::create(filename)?.write_all(&self.to_png())
+    }
+}
+
+// Width of text in pixels
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * GLYPH_WIDTH).saturating_sub(1)
+}
+
+// 5x7 glyph rows, top first, MSB of the low 5 bits on the left.
+// Unknown characters are blank
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '|' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        '^' => [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        _ => [0x00; 7],
+    }
+}
@@ -0,0 +1,92 @@
+// Colormaps for heatmaps
+// Each map is a handful of evenly spaced anchor colours, linearly
+// interpolated. The perceptual ones (viridis, magma, inferno) are
+// sampled from matplotlib's tables.
+
+use std::fmt;
+use std::str::FromStr;
+
+const VIRIDIS: [[u8; 3]; 10] = [
+    [68, 1, 84], [72, 40, 120], [62, 74, 137], [49, 104, 142], [38, 130, 142],
+    [31, 158, 137], [53, 183, 121], [109, 205, 89], [180, 222, 44], [253, 231, 37],
+];
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4], [28, 16, 68], [79, 18, 123], [129, 37, 129], [181, 54, 122],
+    [229, 80, 100], [251, 135, 97], [254, 194, 135], [252, 253, 191],
+];
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4], [31, 12, 72], [85, 15, 109], [136, 34, 106], [186, 54, 85],
+    [227, 89, 51], [249, 140, 10], [249, 201, 50], [252, 255, 164],
+];
+const GRAY: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+const JET: [[u8; 3]; 9] = [
+    [0, 0, 128], [0, 0, 255], [0, 128, 255], [0, 255, 255], [128, 255, 128],
+    [255, 255, 0], [255, 128, 0], [255, 0, 0], [128, 0, 0],
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Magma,
+    Inferno,
+    Gray,
+    Jet,
+}
+
+impl Colormap {
+
+    fn anchors(&self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Gray => &GRAY,
+            Colormap::Jet => &JET,
+        }
+    }
+
+    // Colour at t, 0 (low) to 1 (high). t is clamped and NaN maps low
+    pub fn color(&self, t: f64) -> [u8; 3] {
+        let anchors = self.anchors();
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let pos = t * (anchors.len() - 1) as f64;
+        let idx = (pos.floor() as usize).min(anchors.len() - 2);
+        let frac = pos - idx as f64;
+        let mut out = [0u8; 3];
+        for (c, out) in out.iter_mut().enumerate() {
+            let lo = anchors[idx][c] as f64;
+            let hi = anchors[idx + 1][c] as f64;
+            *out = (lo + (hi - lo) * frac).round() as u8;
+        }
+        out
+    }
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "viridis" => Ok(Colormap::Viridis),
+            "magma" => Ok(Colormap::Magma),
+            "inferno" => Ok(Colormap::Inferno),
+            "gray" | "grey" => Ok(Colormap::Gray),
+            "jet" => Ok(Colormap::Jet),
+            _ => Err(format!("unknown colormap '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Colormap::Viridis => "viridis",
+            Colormap::Magma => "magma",
+            Colormap::Inferno => "inferno",
+            Colormap::Gray => "gray",
+            Colormap::Jet => "jet",
+        };
+        write!(f, "{}", name)
+    }
+}
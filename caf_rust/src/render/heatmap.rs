@@ -0,0 +1,340 @@
+// Heatmap of a CAF surface
+// Frequency runs up the y axis and lag (in samples, with milliseconds
+// underneath) along the x axis, negative lags on the left. Surfaces
+// bigger than the plot are reduced by taking the largest value in
+// each pixel so narrow peaks survive, smaller ones are stretched.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use crate::caf::{signed_lag, CafSurfaceRow};
+use super::{bin, nice_ticks, format_tick, is_svg, svg, Axes, Canvas, Colormap, LinePlot, Scale,
+    FOREGROUND, TICK_LEN, Y_TICKS};
+
+// Space around the plot for ticks, labels, title and colour bar
+const MARGIN_LEFT: usize = 64;
+const MARGIN_TOP: usize = 28;
+const MARGIN_BOTTOM: usize = 48;
+const MARGIN_RIGHT: usize = 90;
+const COLORBAR_GAP: usize = 14;
+const COLORBAR_WIDTH: usize = 12;
+// Peak marker, drawn white on black so it shows on any colormap
+const MARKER_RADIUS: isize = 6;
+const MARKER_COLOR: [u8; 3] = [255, 255, 255];
+
+pub struct Heatmap {
+    values: Vec<Vec<f64>>, // [freq][lag], both ascending
+    freqs: Vec<f64>,
+    lags: Vec<i64>,
+    fs: u32,
+    scale: Scale,
+    colormap: Colormap,
+    width: usize, // plot area, pixels
+    height: usize,
+}
+
+impl Heatmap {
+
+    // Constructor, 800x480 plot in dB with viridis. Rows may come in
+    // any frequency order (the threaded backends don't sort them), but
+    // there must be some and they must all have the same, non-zero length
+    pub fn new(surface: &[CafSurfaceRow], fs: u32) -> io::Result<Self> {
+
+        // Sanity
+        let row_len = surface.first().map(|row| row.xcor_mag.len()).unwrap_or(0);
+        if row_len == 0 || surface.iter().any(|row| row.xcor_mag.len() != row_len) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "cannot plot an empty or ragged surface"));
+        }
+
+        // Rows by frequency, columns from most negative to most positive lag
+        let mut rows: Vec<&CafSurfaceRow> = surface.iter().collect();
+        rows.sort_by(|a, b| a.freq.partial_cmp(&b.freq).unwrap());
+        let mut order: Vec<usize> = (0..row_len).collect();
+        order.sort_by_key(|idx| signed_lag(*idx, row_len));
+
+        Ok(Heatmap {
+            values: rows.iter()
+                .map(|row| order.iter().map(|idx| row.xcor_mag[*idx]).collect())
+                .collect(),
+            freqs: rows.iter().map(|row| row.freq).collect(),
+            lags: order.iter().map(|idx| signed_lag(*idx, row_len)).collect(),
+            fs,
+            scale: Scale::default(),
+            colormap: Colormap::default(),
+            width: 800,
+            height: 480,
+        })
+    }
+
+    pub fn with_scale(mut self, scale: Scale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_colormap(mut self, colormap: Colormap) -> Self {
+        self.colormap = colormap;
+        self
+    }
+
+    // Size of the plot area in pixels, margins come on top
+    pub fn with_size(mut self, width: usize, height: usize) -> Self {
+        assert!(width > 0 && height > 0);
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    // Only show lags from min to max samples (inclusive)
+    pub fn with_lag_range(mut self, min: i64, max: i64) -> Self {
+        let keep: Vec<usize> = (0..self.lags.len())
+            .filter(|idx| self.lags[*idx] >= min && self.lags[*idx] <= max)
+            .collect();
+        assert!(!keep.is_empty(), "no lags between {} and {}", min, max);
+        for row in self.values.iter_mut() {
+            *row = keep.iter().map(|idx| row[*idx]).collect();
+        }
+        self.lags = keep.iter().map(|idx| self.lags[*idx]).collect();
+        self
+    }
+
+    // Frequency axis in Hz, ascending
+    pub fn freqs(&self) -> &[f64] {
+        &self.freqs
+    }
+
+    // Lag axis in samples, ascending
+    pub fn lags(&self) -> &[i64] {
+        &self.lags
+    }
+
+    // (row, column) of the largest value
+    pub fn peak(&self) -> (usize, usize) {
+        let mut best = (0, 0);
+        for (r, row) in self.values.iter().enumerate() {
+            for (c, v) in row.iter().enumerate() {
+                if *v > self.values[best.0][best.1] {
+                    best = (r, c);
+                }
+            }
+        }
+        best
+    }
+
+    fn peak_value(&self) -> f64 {
+        let (r, c) = self.peak();
+        self.values[r][c]
+    }
+
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    pub fn fs(&self) -> u32 {
+        self.fs
+    }
+
+    // Display values of one frequency row, in lag order
+    pub fn row_values(&self, row: usize) -> Vec<f64> {
+        let peak = self.peak_value();
+        self.values[row].iter().map(|v| self.scale.apply(*v, peak)).collect()
+    }
+
+    // Display values of one lag column, in frequency order
+    pub fn column_values(&self, col: usize) -> Vec<f64> {
+        let peak = self.peak_value();
+        self.values.iter().map(|row| self.scale.apply(row[col], peak)).collect()
+    }
+
+    // Plot of one frequency row against lag
+    pub fn row_slice(&self, row: usize) -> LinePlot {
+        let y = self.row_values(row);
+        let marker = argmax(&y);
+        let (lo, hi) = self.scale.limits();
+        LinePlot::new(self.lags.iter().map(|l| *l as f64).collect(), y)
+            .with_labels(&format!("Lag slice at {:.2} Hz", self.freqs[row]),
+                "Lag (samples)", self.scale.label())
+            .with_y_limits(lo, hi)
+            .with_marker(marker)
+    }
+
+    // Plot of one lag column against frequency
+    pub fn column_slice(&self, col: usize) -> LinePlot {
+        let y = self.column_values(col);
+        let marker = argmax(&y);
+        let (lo, hi) = self.scale.limits();
+        LinePlot::new(self.freqs.clone(), y)
+            .with_labels(&format!("Frequency slice at {} samples", self.lags[col]),
+                "Frequency (Hz)", self.scale.label())
+            .with_y_limits(lo, hi)
+            .with_marker(marker)
+    }
+
+    // Axes with cells centred on their lag and frequency
+    fn axes(&self) -> Axes {
+        let (row, col) = self.peak();
+        let df = if self.freqs.len() > 1 {
+            (self.freqs[self.freqs.len() - 1] - self.freqs[0]) / (self.freqs.len() - 1) as f64
+        } else {
+            1.0
+        };
+        Axes {
+            left: MARGIN_LEFT,
+            top: MARGIN_TOP,
+            width: self.width,
+            height: self.height,
+            x: (self.lags[0] as f64 - 0.5, self.lags[self.lags.len() - 1] as f64 + 0.5),
+            y: (self.freqs[0] - df / 2.0, self.freqs[self.freqs.len() - 1] + df / 2.0),
+            title: format!("CAF surface, peak at {:.2} Hz, {} samples",
+                self.freqs[row], self.lags[col]),
+            x_label: "Lag (samples / ms)".to_string(),
+            y_label: "Frequency (Hz)".to_string(),
+            x_secondary: Some(1e3 / self.fs as f64),
+        }
+    }
+
+    // Reduce (or stretch) the surface to width x height pixels, each
+    // the colormap position (0 to 1) of the largest value under it.
+    // The top row is the highest frequency
+    pub fn levels(&self, width: usize, height: usize) -> Vec<Vec<f64>> {
+        let peak = self.peak_value();
+        let (rows, cols) = (self.values.len(), self.lags.len());
+
+        // Cells under each pixel column / row
+        let col_bins: Vec<(usize, usize)> = (0..width).map(|px| bin(px, width, cols)).collect();
+        let row_bins: Vec<(usize, usize)> = (0..height)
+            .map(|py| {
+                let (a, b) = bin(py, height, rows);
+                (rows - b, rows - a)
+            })
+            .collect();
+
+        row_bins.iter().map(|(r0, r1)| {
+            col_bins.iter().map(|(c0, c1)| {
+                let v = self.values[*r0..*r1].iter()
+                    .flat_map(|row| row[*c0..*c1].iter())
+                    .fold(0.0f64, |a, b| a.max(*b));
+                self.scale.normalize(self.scale.apply(v, peak))
+            }).collect()
+        }).collect()
+    }
+
+    // Just the plot area, one colour per pixel
+    fn raster(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width, self.height, FOREGROUND);
+        for (py, row) in self.levels(self.width, self.height).iter().enumerate() {
+            for (px, t) in row.iter().enumerate() {
+                canvas.set(px as isize, py as isize, self.colormap.color(*t));
+            }
+        }
+        canvas
+    }
+
+    // Draw the whole figure
+    pub fn render(&self) -> Canvas {
+        let axes = self.axes();
+        let mut canvas = Canvas::new(MARGIN_LEFT + self.width + MARGIN_RIGHT,
+            MARGIN_TOP + self.height + MARGIN_BOTTOM, [255, 255, 255]);
+        canvas.blit(&self.raster(), MARGIN_LEFT as isize, MARGIN_TOP as isize);
+        axes.draw(&mut canvas);
+
+        // Peak marker
+        let (row, col) = self.peak();
+        let x = axes.x_px(self.lags[col] as f64).round() as isize;
+        let y = axes.y_px(self.freqs[row]).round() as isize;
+        canvas.circle(x, y, MARKER_RADIUS + 1, FOREGROUND);
+        canvas.circle(x, y, MARKER_RADIUS, MARKER_COLOR);
+
+        // Colour bar, low at the bottom
+        let bar_left = (MARGIN_LEFT + self.width + COLORBAR_GAP) as isize;
+        for py in 0..self.height {
+            let t = 1.0 - (py as f64 + 0.5) / self.height as f64;
+            canvas.fill_rect(bar_left, (MARGIN_TOP + py) as isize, COLORBAR_WIDTH, 1,
+                self.colormap.color(t));
+        }
+        canvas.rect(bar_left - 1, MARGIN_TOP as isize - 1, COLORBAR_WIDTH + 2, self.height + 2,
+            FOREGROUND);
+        let bar_right = bar_left + COLORBAR_WIDTH as isize;
+        let (lo, hi) = self.scale.limits();
+        let (ticks, step) = nice_ticks(lo, hi, Y_TICKS);
+        for v in ticks {
+            let y = self.colorbar_y(v).round() as isize;
+            canvas.line(bar_right, y, bar_right + TICK_LEN, y, FOREGROUND);
+            canvas.text(bar_right + TICK_LEN + 3, y - 3, &format_tick(v, step), FOREGROUND);
+        }
+        canvas
+    }
+
+    // Pixel row of a display value on the colour bar
+    fn colorbar_y(&self, v: f64) -> f64 {
+        (MARGIN_TOP + self.height) as f64 - self.scale.normalize(v) * self.height as f64
+    }
+
+    // Same figure as SVG, the plot area embedded as a PNG
+    pub fn to_svg(&self) -> String {
+        let axes = self.axes();
+        let (width, height) = (MARGIN_LEFT + self.width + MARGIN_RIGHT,
+            MARGIN_TOP + self.height + MARGIN_BOTTOM);
+        let mut out = String::new();
+        svg::header(&mut out, width, height);
+        out.push_str(&format!("<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+            preserveAspectRatio=\"none\" style=\"image-rendering: pixelated\" \
+            xlink:href=\"data:image/png;base64,{}\"/>\n",
+            MARGIN_LEFT, MARGIN_TOP, self.width, self.height, svg::base64(&self.raster().to_png())));
+        axes.svg(&mut out);
+
+        // Peak marker
+        let (row, col) = self.peak();
+        out.push_str(&format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" fill=\"none\" \
+            stroke=\"{}\" stroke-width=\"1.5\"/>\n", axes.x_px(self.lags[col] as f64),
+            axes.y_px(self.freqs[row]), MARKER_RADIUS, svg::hex(MARKER_COLOR)));
+
+        // Colour bar as a gradient, low at the bottom
+        let bar_left = (MARGIN_LEFT + self.width + COLORBAR_GAP) as f64;
+        out.push_str("<defs><linearGradient id=\"colormap\" x1=\"0\" y1=\"1\" x2=\"0\" y2=\"0\">\n");
+        for i in 0..=16 {
+            let t = i as f64 / 16.0;
+            out.push_str(&format!("<stop offset=\"{:.4}\" stop-color=\"{}\"/>\n",
+                t, svg::hex(self.colormap.color(t))));
+        }
+        out.push_str("</linearGradient></defs>\n");
+        out.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+            fill=\"url(#colormap)\" stroke=\"{}\"/>\n", bar_left, MARGIN_TOP, COLORBAR_WIDTH,
+            self.height, svg::hex(FOREGROUND)));
+        let bar_right = bar_left + COLORBAR_WIDTH as f64;
+        let (lo, hi) = self.scale.limits();
+        let (ticks, step) = nice_ticks(lo, hi, Y_TICKS);
+        for v in ticks {
+            let y = self.colorbar_y(v);
+            svg::line(&mut out, bar_right, y, bar_right + TICK_LEN as f64, y, FOREGROUND);
+            svg::text(&mut out, bar_right + TICK_LEN as f64 + 3.0, y + 4.0, "start", FOREGROUND,
+                &format_tick(v, step));
+        }
+        svg::footer(&mut out);
+        out
+    }
+
+    pub fn write_png(&self, filename: &str) -> io::Result<()> {
+        self.render().write_png(filename)
+    }
+
+    pub fn write_svg(&self, filename: &str) -> io::Result<()> {
+        File::create(filename)?.write_all(self.to_svg().as_bytes())
+    }
+
+    // PNG or SVG depending on the file extension
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        if is_svg(filename) {
+            self.write_svg(filename)
+        } else {
+            self.write_png(filename)
+        }
+    }
+}
+
+// Index of the largest value
+fn argmax(values: &[f64]) -> usize {
+    values.iter().enumerate()
+        .fold(0, |best, (i, v)| if *v > values[best] { i } else { best })
+}
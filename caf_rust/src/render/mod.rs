@@ -0,0 +1,261 @@
+// Pictures of CAF surfaces
+// Heatmaps of the whole surface and line plots of slices through it,
+// written as PNG or SVG, plus text previews for the terminal.
+// Everything is drawn in-crate: figures are rasterized onto an RGB
+// canvas with a built-in bitmap font, or for SVG the heatmap is
+// embedded as a PNG image under vector axes.
+
+use std::fmt;
+use std::str::FromStr;
+
+mod canvas;
+mod colormap;
+mod heatmap;
+mod plot;
+mod png;
+mod svg;
+mod terminal;
+
+pub use canvas::Canvas;
+pub use colormap::Colormap;
+pub use heatmap::Heatmap;
+pub use plot::LinePlot;
+pub use png::encode_png;
+pub use terminal::{terminal_size, TextStyle};
+
+use canvas::{text_width, GLYPH_HEIGHT};
+
+// Dynamic range shown by Scale::Db unless told otherwise
+const DEFAULT_DB_RANGE: f64 = 60.0;
+
+const FOREGROUND: [u8; 3] = [0, 0, 0];
+const SECONDARY: [u8; 3] = [96, 96, 96];
+const TICK_LEN: isize = 4;
+// Roughly how many ticks to put along each axis
+const X_TICKS: usize = 8;
+const Y_TICKS: usize = 6;
+
+// How surface values map onto colours (or heights in slice plots)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scale {
+    Linear, // fraction of the peak, 0 to 1
+    Db(f64), // dB relative to the peak, floored at -range dB
+}
+
+impl Scale {
+
+    // Display value of v given the surface peak
+    pub fn apply(&self, v: f64, peak: f64) -> f64 {
+        let ratio = if peak > 0.0 { v / peak } else { 0.0 };
+        match *self {
+            Scale::Linear => ratio,
+            Scale::Db(range) => (10.0 * ratio.log10()).max(-range),
+        }
+    }
+
+    // (lowest, highest) display value
+    pub fn limits(&self) -> (f64, f64) {
+        match *self {
+            Scale::Linear => (0.0, 1.0),
+            Scale::Db(range) => (-range, 0.0),
+        }
+    }
+
+    // Display value to colormap position, 0 to 1
+    pub fn normalize(&self, v: f64) -> f64 {
+        let (lo, hi) = self.limits();
+        (v - lo) / (hi - lo)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Scale::Linear => "|xcor|^2 (linear)",
+            Scale::Db(_) => "|xcor|^2 (dB)",
+        }
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::Db(DEFAULT_DB_RANGE)
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    // "linear", "db" or "db" with a range, e.g. "db40"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        match s.as_str() {
+            "linear" | "lin" => Ok(Scale::Linear),
+            "db" => Ok(Scale::Db(DEFAULT_DB_RANGE)),
+            _ => match s.strip_prefix("db").map(|r| r.parse::<f64>()) {
+                Some(Ok(range)) if range > 0.0 => Ok(Scale::Db(range)),
+                _ => Err(format!("unknown scale '{}', expected linear, db or e.g. db40", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scale::Linear => write!(f, "linear"),
+            Scale::Db(range) => write!(f, "db{}", range),
+        }
+    }
+}
+
+// True if filename should be written as SVG rather than PNG
+fn is_svg(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".svg")
+}
+
+// Half-open range of the n cells covered by pixel i of pixels, at
+// least one cell wide
+fn bin(i: usize, pixels: usize, n: usize) -> (usize, usize) {
+    let start = (i * n / pixels).min(n - 1);
+    let end = ((i + 1) * n / pixels).max(start + 1);
+    (start, end)
+}
+
+// Round-numbered ticks (1, 2 or 5 times a power of ten apart) within
+// [lo, hi], and their spacing
+fn nice_ticks(lo: f64, hi: f64, target: usize) -> (Vec<f64>, f64) {
+    if hi <= lo || !hi.is_finite() || !lo.is_finite() {
+        return (vec![lo], 1.0);
+    }
+    let raw = (hi - lo) / target as f64;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap();
+    let first = (lo / step).ceil() as i64;
+    let ticks = (first..)
+        .map(|i| i as f64 * step)
+        .take_while(|v| *v <= hi + step * 1e-9)
+        .collect();
+    (ticks, step)
+}
+
+// Tick label with just enough decimals for the spacing, which may not
+// be round after a change of units (62.5ms ticks at 8kHz)
+fn format_tick(v: f64, step: f64) -> String {
+    let mut decimals = (-step.log10().floor()).max(0.0) as usize;
+    while decimals < 6 {
+        let scaled = step * 10f64.powi(decimals as i32);
+        if (scaled - scaled.round()).abs() < 1e-6 {
+            break;
+        }
+        decimals += 1;
+    }
+    let v = if v.abs() < step * 1e-9 { 0.0 } else { v };
+    format!("{:.*}", decimals, v)
+}
+
+// Plot area of a figure in pixels, the data range it spans and its
+// labels. Shared by the heatmap and slice plots
+struct Axes {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+    x: (f64, f64),
+    y: (f64, f64),
+    title: String,
+    x_label: String,
+    y_label: String,
+    x_secondary: Option<f64>, // scale for a second row of x tick labels
+}
+
+impl Axes {
+
+    fn x_px(&self, v: f64) -> f64 {
+        self.left as f64 + (v - self.x.0) / (self.x.1 - self.x.0) * self.width as f64
+    }
+
+    fn y_px(&self, v: f64) -> f64 {
+        (self.top + self.height) as f64 - (v - self.y.0) / (self.y.1 - self.y.0) * self.height as f64
+    }
+
+    fn bottom(&self) -> usize {
+        self.top + self.height
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        let (left, top) = (self.left as isize, self.top as isize);
+        let bottom = self.bottom() as isize;
+        canvas.rect(left - 1, top - 1, self.width + 2, self.height + 2, FOREGROUND);
+
+        // Ticks and labels under the plot
+        let (ticks, step) = nice_ticks(self.x.0, self.x.1, X_TICKS);
+        for v in ticks {
+            let x = self.x_px(v).round() as isize;
+            canvas.line(x, bottom, x, bottom + TICK_LEN, FOREGROUND);
+            let label = format_tick(v, step);
+            canvas.text(x - text_width(&label) as isize / 2, bottom + 7, &label, FOREGROUND);
+            if let Some(scale) = self.x_secondary {
+                let label = format_tick(v * scale, step * scale);
+                canvas.text(x - text_width(&label) as isize / 2, bottom + 17, &label, SECONDARY);
+            }
+        }
+
+        // Ticks and labels left of the plot
+        let (ticks, step) = nice_ticks(self.y.0, self.y.1, Y_TICKS);
+        for v in ticks {
+            let y = self.y_px(v).round() as isize;
+            canvas.line(left - 1 - TICK_LEN, y, left - 1, y, FOREGROUND);
+            let label = format_tick(v, step);
+            canvas.text(left - 7 - text_width(&label) as isize, y - 3, &label, FOREGROUND);
+        }
+
+        // Axis labels and title
+        let x_mid = left + self.width as isize / 2;
+        let y_mid = top + self.height as isize / 2;
+        let label_y = bottom + if self.x_secondary.is_some() { 31 } else { 21 };
+        canvas.text(x_mid - text_width(&self.x_label) as isize / 2, label_y, &self.x_label, FOREGROUND);
+        canvas.text_vertical(6, y_mid + text_width(&self.y_label) as isize / 2, &self.y_label, FOREGROUND);
+        canvas.text(x_mid - text_width(&self.title) as isize / 2,
+            top - 8 - GLYPH_HEIGHT as isize, &self.title, FOREGROUND);
+    }
+
+    fn svg(&self, out: &mut String) {
+        let (left, top) = (self.left as f64, self.top as f64);
+        let bottom = self.bottom() as f64;
+        out.push_str(&format!("<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{}\" height=\"{}\" \
+            fill=\"none\" stroke=\"{}\"/>\n", left - 0.5, top - 0.5, self.width + 1,
+            self.height + 1, svg::hex(FOREGROUND)));
+
+        // Ticks and labels under the plot
+        let (ticks, step) = nice_ticks(self.x.0, self.x.1, X_TICKS);
+        for v in ticks {
+            let x = self.x_px(v);
+            svg::line(out, x, bottom, x, bottom + TICK_LEN as f64, FOREGROUND);
+            svg::text(out, x, bottom + 15.0, "middle", FOREGROUND, &format_tick(v, step));
+            if let Some(scale) = self.x_secondary {
+                svg::text(out, x, bottom + 27.0, "middle", SECONDARY,
+                    &format_tick(v * scale, step * scale));
+            }
+        }
+
+        // Ticks and labels left of the plot
+        let (ticks, step) = nice_ticks(self.y.0, self.y.1, Y_TICKS);
+        for v in ticks {
+            let y = self.y_px(v);
+            svg::line(out, left - TICK_LEN as f64, y, left, y, FOREGROUND);
+            svg::text(out, left - 7.0, y + 4.0, "end", FOREGROUND, &format_tick(v, step));
+        }
+
+        // Axis labels and title
+        let x_mid = left + self.width as f64 / 2.0;
+        let y_mid = top + self.height as f64 / 2.0;
+        let label_y = bottom + if self.x_secondary.is_some() { 40.0 } else { 30.0 };
+        svg::text(out, x_mid, label_y, "middle", FOREGROUND, &self.x_label);
+        out.push_str(&format!("<text transform=\"translate(14 {:.1}) rotate(-90)\" \
+            text-anchor=\"middle\" fill=\"{}\">{}</text>\n",
+            y_mid, svg::hex(FOREGROUND), svg::escape(&self.y_label)));
+        svg::text(out, x_mid, top - 9.0, "middle", FOREGROUND, &self.title);
+    }
+}
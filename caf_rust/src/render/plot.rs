@@ -0,0 +1,171 @@
+// Line plots, used for slices through a CAF surface
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use super::{is_svg, svg, Axes, Canvas};
+
+const MARGIN_LEFT: usize = 64;
+const MARGIN_TOP: usize = 28;
+const MARGIN_BOTTOM: usize = 38;
+const MARGIN_RIGHT: usize = 24;
+const LINE_COLOR: [u8; 3] = [31, 119, 180];
+const MARKER_COLOR: [u8; 3] = [214, 39, 40];
+const MARKER_RADIUS: isize = 4;
+
+pub struct LinePlot {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    title: String,
+    x_label: String,
+    y_label: String,
+    y_limits: Option<(f64, f64)>, // None to fit the data
+    marker: Option<usize>, // index of a point to circle
+    width: usize, // plot area, pixels
+    height: usize,
+}
+
+impl LinePlot {
+
+    // Constructor, 640x320 plot of y against x
+    pub fn new(x: Vec<f64>, y: Vec<f64>) -> Self {
+        assert!(!x.is_empty() && x.len() == y.len());
+        LinePlot {
+            x,
+            y,
+            title: String::new(),
+            x_label: String::new(),
+            y_label: String::new(),
+            y_limits: None,
+            marker: None,
+            width: 640,
+            height: 320,
+        }
+    }
+
+    pub fn with_labels(mut self, title: &str, x_label: &str, y_label: &str) -> Self {
+        self.title = title.to_string();
+        self.x_label = x_label.to_string();
+        self.y_label = y_label.to_string();
+        self
+    }
+
+    // Fix the y axis instead of fitting it to the data
+    pub fn with_y_limits(mut self, lo: f64, hi: f64) -> Self {
+        assert!(hi > lo);
+        self.y_limits = Some((lo, hi));
+        self
+    }
+
+    // Circle point idx, e.g. the peak
+    pub fn with_marker(mut self, idx: usize) -> Self {
+        assert!(idx < self.x.len());
+        self.marker = Some(idx);
+        self
+    }
+
+    // Size of the plot area in pixels, margins come on top
+    pub fn with_size(mut self, width: usize, height: usize) -> Self {
+        assert!(width > 0 && height > 0);
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    fn axes(&self) -> Axes {
+        let x = span(&self.x, 0.0);
+        let y = self.y_limits.unwrap_or_else(|| span(&self.y, 0.05));
+        Axes {
+            left: MARGIN_LEFT,
+            top: MARGIN_TOP,
+            width: self.width,
+            height: self.height,
+            x,
+            y,
+            title: self.title.clone(),
+            x_label: self.x_label.clone(),
+            y_label: self.y_label.clone(),
+            x_secondary: None,
+        }
+    }
+
+    // Pixel coordinates of every point, clamped to the plot area
+    fn points(&self, axes: &Axes) -> Vec<(f64, f64)> {
+        let top = axes.top as f64;
+        let bottom = axes.bottom() as f64;
+        self.x.iter().zip(self.y.iter())
+            .map(|(x, y)| (axes.x_px(*x), axes.y_px(*y).max(top).min(bottom)))
+            .collect()
+    }
+
+    // Draw the whole figure
+    pub fn render(&self) -> Canvas {
+        let axes = self.axes();
+        let mut canvas = Canvas::new(MARGIN_LEFT + self.width + MARGIN_RIGHT,
+            MARGIN_TOP + self.height + MARGIN_BOTTOM, [255, 255, 255]);
+        axes.draw(&mut canvas);
+        let points: Vec<(isize, isize)> = self.points(&axes).iter()
+            .map(|(x, y)| (x.round() as isize, y.round() as isize))
+            .collect();
+        for pair in points.windows(2) {
+            canvas.line(pair[0].0, pair[0].1, pair[1].0, pair[1].1, LINE_COLOR);
+        }
+        if points.len() == 1 {
+            canvas.set(points[0].0, points[0].1, LINE_COLOR);
+        }
+        if let Some(idx) = self.marker {
+            canvas.circle(points[idx].0, points[idx].1, MARKER_RADIUS, MARKER_COLOR);
+        }
+        canvas
+    }
+
+    // Same figure as SVG
+    pub fn to_svg(&self) -> String {
+        let axes = self.axes();
+        let mut out = String::new();
+        svg::header(&mut out, MARGIN_LEFT + self.width + MARGIN_RIGHT,
+            MARGIN_TOP + self.height + MARGIN_BOTTOM);
+        axes.svg(&mut out);
+        let points = self.points(&axes);
+        let coords: Vec<String> = points.iter().map(|(x, y)| format!("{:.1},{:.1}", x, y)).collect();
+        out.push_str(&format!("<polyline points=\"{}\" fill=\"none\" stroke=\"{}\"/>\n",
+            coords.join(" "), svg::hex(LINE_COLOR)));
+        if let Some(idx) = self.marker {
+            out.push_str(&format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" fill=\"none\" \
+                stroke=\"{}\"/>\n", points[idx].0, points[idx].1, MARKER_RADIUS,
+                svg::hex(MARKER_COLOR)));
+        }
+        svg::footer(&mut out);
+        out
+    }
+
+    pub fn write_png(&self, filename: &str) -> io::Result<()> {
+        self.render().write_png(filename)
+    }
+
+    pub fn write_svg(&self, filename: &str) -> io::Result<()> {
+        File::create(filename)?.write_all(self.to_svg().as_bytes())
+    }
+
+    // PNG or SVG depending on the file extension
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        if is_svg(filename) {
+            self.write_svg(filename)
+        } else {
+            self.write_png(filename)
+        }
+    }
+}
+
+// (min, max) of values widened by pad of the span, and never empty
+fn span(values: &[f64], pad: f64) -> (f64, f64) {
+    let lo = values.iter().fold(f64::INFINITY, |a, b| a.min(*b));
+    let hi = values.iter().fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+    if hi > lo {
+        let margin = (hi - lo) * pad;
+        (lo - margin, hi + margin)
+    } else {
+        (lo - 0.5, lo + 0.5)
+    }
+}
@@ -0,0 +1,75 @@
+// Minimal PNG encoder for 8 bit RGB images
+// Scanlines are left unfiltered and wrapped in a zlib stream of stored
+// (uncompressed) deflate blocks, so no compressor is needed. Files are
+// bigger than they could be but any viewer reads them.
+// https://www.w3.org/TR/png/
+
+use crate::utils::crc32;
+
+// Largest stored deflate block
+const STORED_BLOCK_LEN: usize = 65535;
+
+// Encode width x height RGB pixels (row-major, 3 bytes each) as PNG
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+
+    // Sanity
+    assert!(width > 0 && height > 0);
+    assert!(rgb.len() == width * height * 3);
+
+    // Scanlines, each prefixed with filter type 0 (none)
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for line in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    // zlib header, stored blocks and Adler-32 of the raw data
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(STORED_BLOCK_LEN).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    // 8 bit truecolour, no interlacing
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Signature and chunks
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &zlib);
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+// Length, type, data and CRC of the type and data
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Adler-32 checksum, as used by zlib
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b overflows
+    for chunk in data.chunks(5552) {
+        for byte in chunk.iter() {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,54 @@
+// Small helpers for writing SVG by hand
+
+use std::fmt::Write;
+
+pub fn header(out: &mut String, width: usize, height: usize) {
+    writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+        xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+        width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">", w = width, h = height).unwrap();
+    out.push_str("<style>text { font-family: sans-serif; font-size: 11px; }</style>\n");
+    writeln!(out, "<rect width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>", width, height).unwrap();
+}
+
+pub fn footer(out: &mut String) {
+    out.push_str("</svg>\n");
+}
+
+// Text with its baseline at y, anchored start, middle or end at x
+pub fn text(out: &mut String, x: f64, y: f64, anchor: &str, color: [u8; 3], text: &str) {
+    writeln!(out, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\" fill=\"{}\">{}</text>",
+        x, y, anchor, hex(color), escape(text)).unwrap();
+}
+
+pub fn line(out: &mut String, x0: f64, y0: f64, x1: f64, y1: f64, color: [u8; 3]) {
+    writeln!(out, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\"/>",
+        x0, y0, x1, y1, hex(color)).unwrap();
+}
+
+// "#rrggbb"
+pub fn hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Standard base64 with padding, for embedding images as data URIs
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,226 @@
+// Text previews of a CAF surface, for a quick look over SSH
+// The surface is reduced to fit the terminal and drawn either with
+// block shading (five levels per character) or braille (2x4 dots per
+// character, ordered dithering standing in for the levels), followed
+// by the lag and Doppler cuts through the peak as braille area plots.
+
+use std::env;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::{bin, Heatmap};
+
+// Used when COLUMNS / LINES aren't set and there's no terminal to ask
+const DEFAULT_COLUMNS: usize = 80;
+const DEFAULT_LINES: usize = 24;
+// Width of the frequency labels left of the map
+const GUTTER: usize = 11;
+// Height of each cut
+const CUT_LINES: usize = 4;
+// Title, lag axis and labels, cut titles and cuts, and the shell prompt
+const FIXED_LINES: usize = 6 + 2 * CUT_LINES;
+const MIN_MAP_LINES: usize = 4;
+const MIN_MAP_COLUMNS: usize = 8;
+
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+// Braille dot bit for (x, y) within a 2x4 cell
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+// Ordered dither thresholds for the same cell, in eighths
+const DITHER: [[u32; 2]; 4] = [[0, 4], [6, 2], [1, 5], [7, 3]];
+const PEAK_MARKER: char = '+';
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextStyle {
+    Blocks, // one shaded block per cell
+    Braille, // 2x4 dithered dots per cell
+}
+
+impl FromStr for TextStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blocks" | "block" => Ok(TextStyle::Blocks),
+            "braille" => Ok(TextStyle::Braille),
+            _ => Err(format!("unknown preview style '{}', expected blocks or braille", s)),
+        }
+    }
+}
+
+// Terminal size in characters. COLUMNS and LINES win when set, as
+// they are to force a size, otherwise the terminal on stdout, stderr
+// or stdin is asked
+pub fn terminal_size() -> (usize, usize) {
+    let tty = tty_size();
+    let get = |name: &str, queried: Option<usize>, default: usize| env::var(name).ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .or(queried)
+        .unwrap_or(default);
+    (get("COLUMNS", tty.map(|(columns, _)| columns), DEFAULT_COLUMNS),
+        get("LINES", tty.map(|(_, lines)| lines), DEFAULT_LINES))
+}
+
+// (columns, lines) from ioctl(TIOCGWINSZ) on the first standard stream
+// that is a terminal
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos",
+    target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd"))]
+fn tty_size() -> Option<(usize, usize)> {
+    use std::os::raw::{c_int, c_ulong};
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Winsize {
+        ws_row: u16,
+        ws_col: u16,
+        ws_xpixel: u16,
+        ws_ypixel: u16,
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const TIOCGWINSZ: c_ulong = 0x4008_7468;
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    [1, 2, 0].iter().find_map(|fd| {
+        let mut size = Winsize::default();
+        // Only writes a winsize into size, and fails harmlessly on
+        // anything that isn't a terminal
+        let ok = unsafe { ioctl(*fd, TIOCGWINSZ, &mut size as *mut Winsize) } == 0;
+        Some((size.ws_col as usize, size.ws_row as usize))
+            .filter(|(columns, lines)| ok && *columns > 0 && *lines > 0)
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos",
+    target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd")))]
+fn tty_size() -> Option<(usize, usize)> {
+    None
+}
+
+impl Heatmap {
+
+    // Preview fitting in columns x lines characters
+    pub fn to_text(&self, columns: usize, lines: usize, style: TextStyle) -> String {
+        let width = columns.saturating_sub(GUTTER + 1).max(MIN_MAP_COLUMNS);
+        let height = lines.saturating_sub(FIXED_LINES).max(MIN_MAP_LINES);
+        let (freqs, lags) = (self.freqs(), self.lags());
+        let (row, col) = self.peak();
+        let mut out = String::new();
+
+        writeln!(out, "CAF surface, peak at {:.2} Hz, {} samples ({:.3} ms)", freqs[row],
+            lags[col], lags[col] as f64 * 1e3 / self.fs() as f64).unwrap();
+
+        // Map, highest frequency at the top, peak marked
+        let mut cells = match style {
+            TextStyle::Blocks => shade(&self.levels(width, height)),
+            TextStyle::Braille => braille(&self.levels(width * 2, height * 4)),
+        };
+        let rows = freqs.len();
+        let peak_x = (0..width).find(|x| covers(*x, width, lags.len(), col)).unwrap();
+        let peak_y = (0..height).find(|y| covers(*y, height, rows, rows - 1 - row)).unwrap();
+        cells[peak_y][peak_x] = PEAK_MARKER;
+        for (y, line) in cells.iter().enumerate() {
+            let (label, tick) = if y == 0 {
+                (format!("{:.1} Hz", freqs[rows - 1]), '┤')
+            } else if y == height - 1 {
+                (format!("{:.1} Hz", freqs[0]), '┤')
+            } else {
+                (String::new(), '│')
+            };
+            writeln!(out, "{:>w$}{}{}", label, tick, line.iter().collect::<String>(),
+                w = GUTTER).unwrap();
+        }
+
+        // Lag axis, ends labelled
+        writeln!(out, "{}└{}", " ".repeat(GUTTER), "─".repeat(width)).unwrap();
+        writeln!(out, "{}{}", " ".repeat(GUTTER + 1), spread(&lags[0].to_string(),
+            "lag (samples)", &lags[lags.len() - 1].to_string(), width)).unwrap();
+
+        // Cuts through the peak, lined up with the map
+        let scale = self.scale();
+        let lag_cut: Vec<f64> = self.row_values(row).iter().map(|v| scale.normalize(*v)).collect();
+        writeln!(out, "Lag cut at {:.2} Hz", freqs[row]).unwrap();
+        for line in area_plot(&lag_cut, width) {
+            writeln!(out, "{}{}", " ".repeat(GUTTER + 1), line).unwrap();
+        }
+        let doppler_cut: Vec<f64> = self.column_values(col).iter()
+            .map(|v| scale.normalize(*v))
+            .collect();
+        writeln!(out, "Doppler cut at {} samples, {:.1} to {:.1} Hz", lags[col], freqs[0],
+            freqs[rows - 1]).unwrap();
+        for line in area_plot(&doppler_cut, width) {
+            writeln!(out, "{}{}", " ".repeat(GUTTER + 1), line).unwrap();
+        }
+        out
+    }
+}
+
+// True if pixel i of pixels covers cell idx of n
+fn covers(i: usize, pixels: usize, n: usize, idx: usize) -> bool {
+    let (start, end) = bin(i, pixels, n);
+    idx >= start && idx < end
+}
+
+// One shade per level
+fn shade(levels: &[Vec<f64>]) -> Vec<Vec<char>> {
+    levels.iter()
+        .map(|row| row.iter()
+            .map(|t| SHADES[((t.max(0.0) * SHADES.len() as f64) as usize).min(SHADES.len() - 1)])
+            .collect())
+        .collect()
+}
+
+// One braille character per 2x4 levels, dithered
+fn braille(levels: &[Vec<f64>]) -> Vec<Vec<char>> {
+    let (height, width) = (levels.len() / 4, levels[0].len() / 2);
+    (0..height).map(|y| (0..width).map(|x| {
+        let mut bits = 0;
+        for (dy, (dots, thresholds)) in BRAILLE_DOTS.iter().zip(DITHER.iter()).enumerate() {
+            for dx in 0..2 {
+                if levels[y * 4 + dy][x * 2 + dx] * 8.0 > thresholds[dx] as f64 + 0.5 {
+                    bits |= dots[dx];
+                }
+            }
+        }
+        std::char::from_u32(0x2800 + bits).unwrap()
+    }).collect()).collect()
+}
+
+// Braille area plot of levels (0 to 1), width x CUT_LINES characters
+fn area_plot(levels: &[f64], width: usize) -> Vec<String> {
+    let (dots_wide, dots_high) = (width * 2, CUT_LINES * 4);
+    let heights: Vec<usize> = (0..dots_wide).map(|x| {
+        let (start, end) = bin(x, dots_wide, levels.len());
+        let t = levels[start..end].iter().fold(0.0f64, |a, b| a.max(*b));
+        (t.min(1.0) * dots_high as f64).round() as usize
+    }).collect();
+    (0..CUT_LINES).map(|line| (0..width).map(|x| {
+        let mut bits = 0;
+        for (dy, dots) in BRAILLE_DOTS.iter().enumerate() {
+            let from_bottom = dots_high - 1 - (line * 4 + dy);
+            for dx in 0..2 {
+                if heights[x * 2 + dx] > from_bottom {
+                    bits |= dots[dx];
+                }
+            }
+        }
+        std::char::from_u32(0x2800 + bits).unwrap()
+    }).collect()).collect()
+}
+
+// left, middle and right spread across width characters, the middle
+// dropped if it doesn't fit
+fn spread(left: &str, middle: &str, right: &str, width: usize) -> String {
+    let used = left.len() + right.len();
+    if used + middle.len() + 2 <= width {
+        let gap = width - used - middle.len();
+        format!("{}{}{}{}{}", left, " ".repeat(gap / 2), middle, " ".repeat(gap - gap / 2), right)
+    } else {
+        format!("{}{}{}", left, " ".repeat(width.saturating_sub(used).max(1)), right)
+    }
+}
@@ -0,0 +1,43 @@
+// Multi-channel captures, N channels of IQ interleaved sample by sample
+// (ch0[0], ch1[0], ... chN-1[0], ch0[1], ...) in one file. Sample
+// positions count per channel, so a range covers the same instants
+// in every channel. A trailing partial frame is ignored.
+
+use std::io;
+
+use num_complex::Complex64;
+
+use super::{read_file, read_range, Endian, SampleFormat};
+
+// Split interleaved samples into one buffer per channel
+pub fn deinterleave(samples: &[Complex64], n_channels: usize) -> Vec<Vec<Complex64>> {
+    assert!(n_channels > 0);
+    let frames = samples.len() / n_channels;
+    let mut channels = vec![Vec::with_capacity(frames); n_channels];
+    for frame in samples.chunks_exact(n_channels) {
+        for (channel, samp) in channels.iter_mut().zip(frame.iter()) {
+            channel.push(*samp);
+        }
+    }
+    channels
+}
+
+// Read every channel of a whole capture
+pub fn read_channels(filename: &str, format: SampleFormat, endian: Endian, n_channels: usize)
+    -> io::Result<Vec<Vec<Complex64>>> {
+
+    Ok(deinterleave(&read_file(filename, format, endian)?, n_channels))
+}
+
+// Read count samples of every channel starting at per-channel sample start
+pub fn read_channels_range(filename: &str, format: SampleFormat, endian: Endian,
+    n_channels: usize, start: u64, count: usize) -> io::Result<Vec<Vec<Complex64>>> {
+
+    let (start, count) = start.checked_mul(n_channels as u64)
+        .zip(count.checked_mul(n_channels))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} frames of {} channels from frame {} is too many", count, n_channels,
+                start)))?;
+    let samples = read_range(filename, format, endian, start, count)?;
+    Ok(deinterleave(&samples, n_channels))
+}
@@ -0,0 +1,181 @@
+// Interleaved IQ sample formats
+// Integer formats are scaled to roughly [-1, 1):
+//   ci8  (HackRF)     x / 128
+//   cu8  (RTL-SDR)    (x - 127.5) / 128
+//   ci16 (USRP sc16)  x / 32768
+// Float formats are passed through unscaled. Writers apply the
+// inverse scaling, rounding and saturating to the integer range.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use num_complex::Complex64;
+
+use super::ChunkedReader;
+
+// Samples decoded per read when loading a whole file
+const READ_BLOCK_LEN: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    Ci8, // signed 8 bit I/Q
+    Cu8, // unsigned 8 bit I/Q, offset binary
+    Ci16, // signed 16 bit I/Q
+    Cf32, // 32 bit float I/Q, numpy complex64
+    Cf64, // 64 bit float I/Q, numpy complex128
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl SampleFormat {
+
+    // Size of one component (I or Q) in bytes
+    pub fn component_bytes(&self) -> usize {
+        match self {
+            SampleFormat::Ci8 | SampleFormat::Cu8 => 1,
+            SampleFormat::Ci16 => 2,
+            SampleFormat::Cf32 => 4,
+            SampleFormat::Cf64 => 8,
+        }
+    }
+
+    // Size of one complex sample in bytes
+    pub fn sample_bytes(&self) -> usize {
+        2 * self.component_bytes()
+    }
+
+    // Convert one raw component to a float
+    fn decode(&self, bytes: &[u8], endian: Endian) -> f64 {
+        macro_rules! from_bytes {
+            ($t:ty, $n:expr) => {{
+                let mut raw = [0u8; $n];
+                raw.copy_from_slice(&bytes[..$n]);
+                match endian {
+                    Endian::Little => <$t>::from_le_bytes(raw),
+                    Endian::Big => <$t>::from_be_bytes(raw),
+                }
+            }};
+        }
+        match self {
+            SampleFormat::Ci8 => (bytes[0] as i8 as f64) / 128.0,
+            SampleFormat::Cu8 => (bytes[0] as f64 - 127.5) / 128.0,
+            SampleFormat::Ci16 => (from_bytes!(i16, 2) as f64) / 32768.0,
+            SampleFormat::Cf32 => from_bytes!(f32, 4) as f64,
+            SampleFormat::Cf64 => from_bytes!(f64, 8),
+        }
+    }
+
+    // Convert one float component to raw bytes
+    fn encode(&self, value: f64, endian: Endian, out: &mut Vec<u8>) {
+        macro_rules! to_bytes {
+            ($v:expr) => {
+                match endian {
+                    Endian::Little => out.extend_from_slice(&$v.to_le_bytes()),
+                    Endian::Big => out.extend_from_slice(&$v.to_be_bytes()),
+                }
+            };
+        }
+        match self {
+            SampleFormat::Ci8 => out.push((value * 128.0).round().clamp(-128.0, 127.0) as i8 as u8),
+            SampleFormat::Cu8 => out.push((value * 128.0 + 127.5).round().clamp(0.0, 255.0) as u8),
+            SampleFormat::Ci16 => to_bytes!(((value * 32768.0).round().clamp(-32768.0, 32767.0) as i16)),
+            SampleFormat::Cf32 => to_bytes!((value as f32)),
+            SampleFormat::Cf64 => to_bytes!(value),
+        }
+    }
+}
+
+// Parse the usual names (and SDR-specific aliases) for each format
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ci8" | "sc8" | "i8" => Ok(SampleFormat::Ci8),
+            "cu8" | "u8" => Ok(SampleFormat::Cu8),
+            "ci16" | "sc16" | "i16" => Ok(SampleFormat::Ci16),
+            "cf32" | "fc32" | "c64" | "complex64" => Ok(SampleFormat::Cf32),
+            "cf64" | "fc64" | "c128" | "complex128" => Ok(SampleFormat::Cf64),
+            _ => Err(format!("unknown sample format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SampleFormat::Ci8 => "ci8",
+            SampleFormat::Cu8 => "cu8",
+            SampleFormat::Ci16 => "ci16",
+            SampleFormat::Cf32 => "cf32",
+            SampleFormat::Cf64 => "cf64",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "le" | "little" => Ok(Endian::Little),
+            "be" | "big" => Ok(Endian::Big),
+            _ => Err(format!("unknown endianness '{}'", s)),
+        }
+    }
+}
+
+// Decode a buffer of interleaved I/Q into samples, appending to out.
+// Any trailing partial sample is ignored
+pub fn decode_samples(bytes: &[u8], format: SampleFormat, endian: Endian,
+    out: &mut Vec<Complex64>) {
+
+    let n = format.component_bytes();
+    out.reserve(bytes.len() / format.sample_bytes());
+    for samp in bytes.chunks_exact(format.sample_bytes()) {
+        let real = format.decode(&samp[..n], endian);
+        let imag = format.decode(&samp[n..], endian);
+        out.push(Complex64::new(real, imag));
+    }
+}
+
+// Encode samples as interleaved I/Q, appending to out
+pub fn encode_samples(samples: &[Complex64], format: SampleFormat, endian: Endian,
+    out: &mut Vec<u8>) {
+
+    out.reserve(samples.len() * format.sample_bytes());
+    for samp in samples.iter() {
+        format.encode(samp.re, endian, out);
+        format.encode(samp.im, endian, out);
+    }
+}
+
+// Read a whole file of interleaved I/Q in any supported format.
+// Decoded a block at a time so the raw bytes are never all in memory
+pub fn read_file(filename: &str, format: SampleFormat, endian: Endian)
+    -> io::Result<Vec<Complex64>> {
+
+    let f = File::open(filename)?;
+    let mut samples = Vec::with_capacity(f.metadata()?.len() as usize / format.sample_bytes());
+    for block in ChunkedReader::new(f, format, endian, READ_BLOCK_LEN) {
+        samples.extend(block?);
+    }
+    Ok(samples)
+}
+
+// Write samples as interleaved I/Q in any supported format
+pub fn write_file(filename: &str, samples: &[Complex64], format: SampleFormat,
+    endian: Endian) -> io::Result<()> {
+
+    let mut buffer = Vec::new();
+    encode_samples(samples, format, endian, &mut buffer);
+    File::create(filename)?.write_all(&buffer)
+}
@@ -0,0 +1,600 @@
+// GNU Radio file metadata, as written by the File Meta Sink
+// A recording is a run of segments, each described by a header: a
+// serialized PMT dictionary with the sample rate (rx_rate), the time
+// of its first sample (rx_time), the item size, type and complexity,
+// the header length including any extra dictionary of stream tags
+// (strt) and the segment's data length (bytes). Attached headers sit
+// in the data file right before their segment's samples. Detached
+// headers follow one another in a separate .hdr file and the data
+// file is only samples. PMTs are big-endian, the samples themselves
+// are in the writer's byte order, little-endian on any common host.
+// https://wiki.gnuradio.org/index.php/File_Meta_Sink
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use num_complex::Complex64;
+
+use super::{decode_samples, Endian, SampleFormat, Timestamp};
+
+// PMT serialization tags
+const PST_TRUE: u8 = 0x00;
+const PST_FALSE: u8 = 0x01;
+const PST_SYMBOL: u8 = 0x02;
+const PST_INT32: u8 = 0x03;
+const PST_DOUBLE: u8 = 0x04;
+const PST_COMPLEX: u8 = 0x05;
+const PST_NULL: u8 = 0x06;
+const PST_PAIR: u8 = 0x07;
+const PST_VECTOR: u8 = 0x08;
+const PST_UNIFORM_VECTOR: u8 = 0x0a;
+const PST_UINT64: u8 = 0x0b;
+const PST_TUPLE: u8 = 0x0c;
+const PST_INT64: u8 = 0x0d;
+
+// First read for an attached header, the standard one is 149 bytes
+const HEADER_READ_LEN: u64 = 512;
+// Largest header and extra dictionary accepted
+const MAX_HEADER_LEN: u64 = 1 << 20;
+
+// Item types (gr_file_types)
+const GR_FILE_BYTE: i64 = 0;
+const GR_FILE_SHORT: i64 = 1;
+const GR_FILE_FLOAT: i64 = 5;
+const GR_FILE_DOUBLE: i64 = 6;
+
+// A deserialized PMT. Dictionaries are serialized as lists of
+// (key . value) pairs and come back that way
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pmt {
+    Bool(bool),
+    Symbol(String),
+    Int(i64), // int32 or int64
+    UInt64(u64),
+    Double(f64),
+    Complex(Complex64),
+    Null,
+    Pair(Box<Pmt>, Box<Pmt>),
+    Vector(Vec<Pmt>),
+    Tuple(Vec<Pmt>),
+    Uniform { kind: u8, bytes: Vec<u8> }, // element type tag and raw big-endian elements
+}
+
+impl Pmt {
+
+    // Parse one PMT, returning it and the bytes it took
+    pub fn parse(bytes: &[u8]) -> io::Result<(Pmt, usize)> {
+        let mut pos = 0;
+        let pmt = parse_at(bytes, &mut pos, 0)?;
+        Ok((pmt, pos))
+    }
+
+    // Serialize as GNU Radio does
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+
+    // A dictionary of symbol keys, as a list of pairs
+    pub fn dict(items: &[(&str, Pmt)]) -> Pmt {
+        items.iter().rev().fold(Pmt::Null, |rest, (key, value)| {
+            let item = Pmt::Pair(Box::new(Pmt::Symbol(key.to_string())), Box::new(value.clone()));
+            Pmt::Pair(Box::new(item), Box::new(rest))
+        })
+    }
+
+    // (key, value) items of a dictionary, empty for anything else
+    pub fn dict_items(&self) -> Vec<(String, &Pmt)> {
+        let mut items = Vec::new();
+        let mut rest = self;
+        while let Pmt::Pair(item, next) = rest {
+            if let Pmt::Pair(key, value) = item.as_ref() {
+                if let Pmt::Symbol(key) = key.as_ref() {
+                    items.push((key.clone(), value.as_ref()));
+                }
+            }
+            rest = next;
+        }
+        items
+    }
+
+    // Value of a dictionary key
+    pub fn get(&self, key: &str) -> Option<&Pmt> {
+        self.dict_items().into_iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Pmt::Double(v) => Some(v),
+            Pmt::Int(v) => Some(v as f64),
+            Pmt::UInt64(v) => Some(v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Pmt::Int(v) => Some(v),
+            Pmt::UInt64(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    // A count or length, never negative
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Pmt::UInt64(v) => Some(v),
+            Pmt::Int(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Pmt::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    // A UHD style time, (whole seconds, fractional seconds)
+    pub fn as_time(&self) -> Option<(u64, f64)> {
+        match self {
+            Pmt::Tuple(items) if items.len() == 2 => {
+                let whole = match items[0] {
+                    Pmt::UInt64(v) => v,
+                    Pmt::Int(v) if v >= 0 => v as u64,
+                    _ => return None,
+                };
+                Some((whole, items[1].as_f64()?))
+            },
+            _ => None,
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Pmt::Bool(true) => out.push(PST_TRUE),
+            Pmt::Bool(false) => out.push(PST_FALSE),
+            Pmt::Symbol(s) => {
+                out.push(PST_SYMBOL);
+                out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+                out.extend_from_slice(s.as_bytes());
+            },
+            Pmt::Int(v) => match i32::try_from(*v) {
+                Ok(v) => {
+                    out.push(PST_INT32);
+                    out.extend_from_slice(&v.to_be_bytes());
+                },
+                Err(_) => {
+                    out.push(PST_INT64);
+                    out.extend_from_slice(&v.to_be_bytes());
+                },
+            },
+            Pmt::UInt64(v) => {
+                out.push(PST_UINT64);
+                out.extend_from_slice(&v.to_be_bytes());
+            },
+            Pmt::Double(v) => {
+                out.push(PST_DOUBLE);
+                out.extend_from_slice(&v.to_be_bytes());
+            },
+            Pmt::Complex(v) => {
+                out.push(PST_COMPLEX);
+                out.extend_from_slice(&v.re.to_be_bytes());
+                out.extend_from_slice(&v.im.to_be_bytes());
+            },
+            Pmt::Null => out.push(PST_NULL),
+            Pmt::Pair(car, cdr) => {
+                out.push(PST_PAIR);
+                car.write(out);
+                cdr.write(out);
+            },
+            Pmt::Vector(items) | Pmt::Tuple(items) => {
+                out.push(if let Pmt::Vector(_) = self { PST_VECTOR } else { PST_TUPLE });
+                out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+                for item in items {
+                    item.write(out);
+                }
+            },
+            Pmt::Uniform { kind, bytes } => {
+                out.push(PST_UNIFORM_VECTOR);
+                out.push(*kind);
+                out.extend_from_slice(&((bytes.len() / uniform_size(*kind).unwrap_or(1)) as u32)
+                    .to_be_bytes());
+                out.push(0); // no padding
+                out.extend_from_slice(bytes);
+            },
+        }
+    }
+}
+
+// One segment's header
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrMetaSegment {
+    pub rx_rate: f64,
+    pub rx_time: Option<(u64, f64)>, // whole and fractional seconds
+    pub item_size: usize,
+    pub item_type: i64, // gr_file_types
+    pub complex: bool,
+    pub data_offset: u64, // of its samples in the data file
+    pub bytes: u64,
+    pub extras: Vec<(String, Pmt)>, // the extra dictionary, stream tags
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrMeta {
+    pub data_path: PathBuf,
+    pub detached: bool,
+    pub segments: Vec<GrMetaSegment>,
+}
+
+impl GrMeta {
+
+    // Read the headers of a recording given its data file, or for
+    // detached headers either file
+    pub fn read(filename: &str) -> io::Result<Self> {
+        let (header_path, data_path, detached) = gr_meta_paths(filename);
+        let mut file = File::open(&header_path)?;
+        let segments = if detached {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            parse_gr_meta(&bytes, true)?
+        } else {
+            read_attached(&mut file)?
+        };
+        Ok(GrMeta { data_path, detached, segments })
+    }
+
+    // Sample rate of the first segment
+    pub fn sample_rate(&self) -> f64 {
+        self.segments[0].rx_rate
+    }
+
+    // Time of the first sample, if recorded
+    pub fn start_time(&self) -> Option<Timestamp> {
+        self.segments[0].rx_time.map(|(whole, frac)| Timestamp::from_gr_time(whole, frac))
+    }
+
+    // Sample format from the item type, complex items only
+    pub fn sample_format(&self) -> io::Result<(SampleFormat, Endian)> {
+        let segment = &self.segments[0];
+        let format = match (segment.complex, segment.item_type) {
+            (true, GR_FILE_BYTE) => SampleFormat::Ci8,
+            (true, GR_FILE_SHORT) => SampleFormat::Ci16,
+            (true, GR_FILE_FLOAT) => SampleFormat::Cf32,
+            (true, GR_FILE_DOUBLE) => SampleFormat::Cf64,
+            (complex, item_type) => return Err(invalid(format!(
+                "unsupported gr_meta items, type {} {}", item_type,
+                if complex { "complex" } else { "real" }))),
+        };
+        if segment.item_size == 0 || !segment.item_size.is_multiple_of(format.sample_bytes()) {
+            return Err(invalid(format!("item size {} does not hold {} samples",
+                segment.item_size, format)));
+        }
+        Ok((format, Endian::Little))
+    }
+
+    // Samples in all segments
+    pub fn len(&self) -> u64 {
+        let bytes: u64 = self.segments.iter().map(|s| s.bytes).sum();
+        match self.sample_format() {
+            Ok((format, _)) => bytes / format.sample_bytes() as u64,
+            Err(_) => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Every sample, segments joined up
+    pub fn read_samples(&self) -> io::Result<Vec<Complex64>> {
+        self.read_range(0, self.len() as usize)
+    }
+
+    // count samples from sample start on, across segments. Short if
+    // the data ends early, and clamped to what the headers describe
+    // before anything is allocated
+    pub fn read_range(&self, start: u64, count: usize) -> io::Result<Vec<Complex64>> {
+        let (format, endian) = self.sample_format()?;
+        let sample_bytes = format.sample_bytes() as u64;
+        let start = start.min(self.len());
+        let count = (count as u64).min(self.len() - start) as usize;
+        let mut file = File::open(&self.data_path)?;
+        let mut samples = Vec::with_capacity(count);
+        for (offset, len) in self.byte_ranges(start * sample_bytes, count as u64 * sample_bytes) {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut bytes = Vec::with_capacity(len as usize);
+            (&mut file).take(len).read_to_end(&mut bytes)?;
+            decode_samples(&bytes, format, endian, &mut samples);
+            if (bytes.len() as u64) < len {
+                break;
+            }
+        }
+        Ok(samples)
+    }
+
+    // The samples as one byte stream, for ChunkedReader
+    pub fn data_reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+        for segment in self.segments.iter() {
+            let mut file = File::open(&self.data_path)?;
+            file.seek(SeekFrom::Start(segment.data_offset))?;
+            reader = Box::new(reader.chain(file.take(segment.bytes)));
+        }
+        Ok(reader)
+    }
+
+    // (file offset, length) of the data bytes from byte start of the
+    // joined segments
+    fn byte_ranges(&self, start: u64, len: u64) -> Vec<(u64, u64)> {
+        let mut ranges = Vec::new();
+        let mut joined = 0; // segment start in the joined data
+        let end = start + len;
+        for segment in self.segments.iter() {
+            let from = start.max(joined);
+            let to = end.min(joined + segment.bytes);
+            if from < to {
+                ranges.push((segment.data_offset + from - joined, to - from));
+            }
+            joined += segment.bytes;
+        }
+        ranges
+    }
+}
+
+// Parse every header in a header file (detached) or a recording
+// (attached), without reading the samples
+pub fn parse_gr_meta(bytes: &[u8], detached: bool) -> io::Result<Vec<GrMetaSegment>> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    let mut data_offset: u64 = 0;
+    while pos < bytes.len() {
+        let (mut segment, strt) = parse_header(&bytes[pos..])?;
+        let header_end = pos + strt as usize; // parse_header checked it's in bytes
+
+        // Next header follows this one, or this one's samples
+        if detached {
+            segment.data_offset = data_offset;
+            data_offset = data_offset.checked_add(segment.bytes)
+                .ok_or_else(|| invalid("gr_meta segments overflow".to_string()))?;
+            pos = header_end;
+        } else {
+            segment.data_offset = header_end as u64;
+            pos = usize::try_from(segment.bytes).ok()
+                .and_then(|len| header_end.checked_add(len))
+                .filter(|end| *end <= bytes.len())
+                .ok_or_else(|| invalid(format!("gr_meta segment of {} bytes runs past the end",
+                    segment.bytes)))?;
+        }
+        segments.push(segment);
+    }
+    if segments.is_empty() {
+        return Err(invalid("no gr_meta headers".to_string()));
+    }
+    Ok(segments)
+}
+
+// Attached headers, read one at a time and skipping each segment's
+// samples so a long recording is never read in whole
+fn read_attached(file: &mut File) -> io::Result<Vec<GrMetaSegment>> {
+    let file_len = file.metadata()?.len();
+    let mut segments = Vec::new();
+    let mut pos: u64 = 0;
+    while pos < file_len {
+        let available = file_len - pos;
+
+        // Enough for the header dictionary, then all of strt
+        let mut want = HEADER_READ_LEN;
+        let mut bytes = read_at(file, pos, want.min(available))?;
+        let header = loop {
+            match Pmt::parse(&bytes) {
+                Ok((header, _)) => break header,
+                Err(_) if want < available && want < MAX_HEADER_LEN => {
+                    want *= 4;
+                    bytes = read_at(file, pos, want.min(available))?;
+                },
+                Err(e) => return Err(e),
+            }
+        };
+        let strt = header.get("strt").and_then(|strt| strt.as_u64())
+            .filter(|strt| *strt <= MAX_HEADER_LEN.min(available))
+            .ok_or_else(|| invalid("bad gr_meta strt".to_string()))?;
+        if strt > bytes.len() as u64 {
+            bytes = read_at(file, pos, strt)?;
+        }
+        let (mut segment, strt) = parse_header(&bytes[..strt as usize])?;
+
+        segment.data_offset = pos + strt;
+        pos = segment.data_offset.checked_add(segment.bytes)
+            .filter(|end| *end <= file_len)
+            .ok_or_else(|| invalid(format!("gr_meta segment of {} bytes runs past the end",
+                segment.bytes)))?;
+        segments.push(segment);
+    }
+    if segments.is_empty() {
+        return Err(invalid("no gr_meta headers".to_string()));
+    }
+    Ok(segments)
+}
+
+// len bytes from offset on, fewer at the end of the file
+fn read_at(file: &mut File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::with_capacity(len as usize);
+    file.take(len).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// The header at the start of bytes, with its extra dictionary, and its
+// length (strt). The segment's data_offset is left for the caller
+fn parse_header(bytes: &[u8]) -> io::Result<(GrMetaSegment, u64)> {
+    let (header, header_len) = Pmt::parse(bytes)?;
+    let field = |key: &str| header.get(key)
+        .ok_or_else(|| invalid(format!("gr_meta header has no {}", key)));
+    let count = |key: &str| field(key)?.as_u64()
+        .ok_or_else(|| invalid(format!("bad gr_meta {}", key)));
+    let strt = count("strt")?;
+    if strt < header_len as u64 || strt > bytes.len() as u64 {
+        return Err(invalid(format!("gr_meta header length {} is out of range", strt)));
+    }
+
+    // Stream tags in the extra dictionary
+    let strt_len = strt as usize;
+    let extras = if strt_len > header_len {
+        let (extra, _) = Pmt::parse(&bytes[header_len..strt_len])?;
+        extra.dict_items().into_iter().map(|(k, v)| (k, v.clone())).collect()
+    } else {
+        Vec::new()
+    };
+
+    let segment = GrMetaSegment {
+        rx_rate: field("rx_rate")?.as_f64()
+            .filter(|rate| rate.is_finite() && *rate > 0.0)
+            .ok_or_else(|| invalid("bad gr_meta rx_rate".to_string()))?,
+        rx_time: header.get("rx_time").and_then(|t| t.as_time()),
+        item_size: usize::try_from(count("size")?)
+            .map_err(|_| invalid("bad gr_meta size".to_string()))?,
+        item_type: field("type")?.as_i64()
+            .ok_or_else(|| invalid("bad gr_meta type".to_string()))?,
+        complex: field("cplx")?.as_bool()
+            .ok_or_else(|| invalid("bad gr_meta cplx".to_string()))?,
+        data_offset: 0,
+        bytes: count("bytes")?,
+        extras,
+    };
+    Ok((segment, strt))
+}
+
+// (header file, data file, detached) for a recording named by its data
+// file or its .hdr file. Without a .hdr alongside, headers are attached
+pub fn gr_meta_paths(filename: &str) -> (PathBuf, PathBuf, bool) {
+    if let Some(data) = filename.strip_suffix(".hdr") {
+        return (PathBuf::from(filename), PathBuf::from(data), true);
+    }
+    let header = PathBuf::from(format!("{}.hdr", filename));
+    if header.exists() {
+        (header, PathBuf::from(filename), true)
+    } else {
+        (PathBuf::from(filename), PathBuf::from(filename), false)
+    }
+}
+
+// True if filename is a gr_meta recording: a .hdr file or one
+// alongside, or a regular file starting with a header. Pipes, stdin
+// ("-") and devices are never read from here, that would eat live
+// samples or block
+pub fn is_gr_meta(filename: &str) -> bool {
+    let (header, _, detached) = gr_meta_paths(filename);
+    if detached {
+        return header.exists();
+    }
+    let regular = filename != "-" && header.metadata().map(|m| m.is_file()).unwrap_or(false);
+    regular && first_header(&header).is_some()
+}
+
+// The first header of a file, if it starts with one
+fn first_header(path: &Path) -> Option<Pmt> {
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.take(4096).read_to_end(&mut bytes).ok()?;
+    if bytes.len() < 2 || bytes[0] != PST_PAIR || bytes[1] != PST_PAIR {
+        return None;
+    }
+    let (header, _) = Pmt::parse(&bytes).ok()?;
+    ["rx_rate", "size", "type", "cplx", "strt", "bytes"].iter()
+        .all(|key| header.get(key).is_some())
+        .then_some(header)
+}
+
+// Nesting allowed before a PMT is taken to be garbage
+const MAX_DEPTH: usize = 64;
+
+fn parse_at(bytes: &[u8], pos: &mut usize, depth: usize) -> io::Result<Pmt> {
+    if depth > MAX_DEPTH {
+        return Err(invalid("PMT nested too deeply".to_string()));
+    }
+    let tag = take(bytes, pos, 1)?[0];
+    Ok(match tag {
+        PST_TRUE => Pmt::Bool(true),
+        PST_FALSE => Pmt::Bool(false),
+        PST_SYMBOL => {
+            let len = u16::from_be_bytes(array(take(bytes, pos, 2)?)) as usize;
+            Pmt::Symbol(String::from_utf8_lossy(take(bytes, pos, len)?).into_owned())
+        },
+        PST_INT32 => Pmt::Int(i32::from_be_bytes(array(take(bytes, pos, 4)?)) as i64),
+        PST_INT64 => Pmt::Int(i64::from_be_bytes(array(take(bytes, pos, 8)?))),
+        PST_UINT64 => Pmt::UInt64(u64::from_be_bytes(array(take(bytes, pos, 8)?))),
+        PST_DOUBLE => Pmt::Double(f64::from_be_bytes(array(take(bytes, pos, 8)?))),
+        PST_COMPLEX => {
+            let re = f64::from_be_bytes(array(take(bytes, pos, 8)?));
+            let im = f64::from_be_bytes(array(take(bytes, pos, 8)?));
+            Pmt::Complex(Complex64::new(re, im))
+        },
+        PST_NULL => Pmt::Null,
+        PST_PAIR => {
+            // Lists nest in the cdr, walk them rather than recursing
+            let car = parse_at(bytes, pos, depth + 1)?;
+            let mut cars = vec![car];
+            while bytes.get(*pos) == Some(&PST_PAIR) {
+                *pos += 1;
+                cars.push(parse_at(bytes, pos, depth + 1)?);
+            }
+            let tail = parse_at(bytes, pos, depth + 1)?;
+            cars.into_iter().rev().fold(tail, |cdr, car| Pmt::Pair(Box::new(car), Box::new(cdr)))
+        },
+        PST_VECTOR | PST_TUPLE => {
+            let len = u32::from_be_bytes(array(take(bytes, pos, 4)?)) as usize;
+            let items = (0..len)
+                .map(|_| parse_at(bytes, pos, depth + 1))
+                .collect::<io::Result<Vec<Pmt>>>()?;
+            if tag == PST_VECTOR { Pmt::Vector(items) } else { Pmt::Tuple(items) }
+        },
+        PST_UNIFORM_VECTOR => {
+            let kind = take(bytes, pos, 1)?[0];
+            let len = u32::from_be_bytes(array(take(bytes, pos, 4)?)) as usize;
+            let pad = take(bytes, pos, 1)?[0] as usize;
+            take(bytes, pos, pad)?;
+            let size = uniform_size(kind)
+                .ok_or_else(|| invalid(format!("unknown PMT uniform vector type {}", kind)))?;
+            let data = take(bytes, pos, len.checked_mul(size)
+                .ok_or_else(|| invalid("PMT uniform vector too long".to_string()))?)?;
+            Pmt::Uniform { kind, bytes: data.to_vec() }
+        },
+        _ => return Err(invalid(format!("unsupported PMT tag {:#04x}", tag))),
+    })
+}
+
+// Element size of a uniform vector type
+fn uniform_size(kind: u8) -> Option<usize> {
+    match kind {
+        0x00 | 0x01 => Some(1), // u8, s8
+        0x02 | 0x03 => Some(2), // u16, s16
+        0x04 | 0x05 | 0x08 => Some(4), // u32, s32, f32
+        0x06 | 0x07 | 0x09 | 0x0a => Some(8), // u64, s64, f64, c32
+        0x0b => Some(16), // c64
+        _ => None,
+    }
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> io::Result<&'a [u8]> {
+    let end = pos.checked_add(len).filter(|end| *end <= bytes.len())
+        .ok_or_else(|| invalid("PMT runs past the end of the header".to_string()))?;
+    let out = &bytes[*pos..end];
+    *pos = end;
+    Ok(out)
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut out = [0; N];
+    out.copy_from_slice(bytes);
+    out
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
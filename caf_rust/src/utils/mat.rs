@@ -0,0 +1,233 @@
+// MATLAB Level 5 .mat output
+// A 128 byte header (description, version 0x0100, "IM" endianness
+// marker) followed by one miMATRIX element per variable. Elements are
+// a (type, byte count) tag and data padded to 8 bytes. Arrays are
+// column-major, as MATLAB stores them. Only what CAF output needs is
+// supported: real double matrices, char strings and 1x1 structs.
+// https://www.mathworks.com/help/pdf_doc/matlab/matfile_format.pdf
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use crate::caf::{signed_lag, CafResult, CafSurfaceRow};
+
+// Data types
+const MI_INT8: u32 = 1;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+
+// Array classes
+const MX_STRUCT_CLASS: u32 = 2;
+const MX_CHAR_CLASS: u32 = 4;
+const MX_DOUBLE_CLASS: u32 = 6;
+
+// Longest variable or field name MATLAB accepts
+const MAX_NAME_LEN: usize = 31;
+const DESCRIPTION: &str = "MATLAB 5.0 MAT-file, written by caf_rust";
+
+// A MATLAB variable
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatVar {
+    Double { rows: usize, cols: usize, data: Vec<f64> }, // column-major
+    Text(String), // 1xN char
+    Struct(Vec<(String, MatVar)>), // 1x1 struct, fields in order
+}
+
+impl MatVar {
+
+    pub fn scalar(value: f64) -> Self {
+        MatVar::Double { rows: 1, cols: 1, data: vec![value] }
+    }
+
+    pub fn row(values: &[f64]) -> Self {
+        MatVar::Double { rows: 1, cols: values.len(), data: values.to_vec() }
+    }
+
+    pub fn column(values: &[f64]) -> Self {
+        MatVar::Double { rows: values.len(), cols: 1, data: values.to_vec() }
+    }
+
+    // rows x cols matrix from row-major values
+    pub fn matrix(rows: usize, cols: usize, row_major: &[f64]) -> Self {
+        assert!(row_major.len() == rows * cols);
+        let data = (0..rows * cols).map(|i| row_major[(i % rows) * cols + i / rows]).collect();
+        MatVar::Double { rows, cols, data }
+    }
+}
+
+// Serialize named variables as a .mat file
+pub fn mat_bytes(vars: &[(&str, MatVar)]) -> io::Result<Vec<u8>> {
+
+    // Header text padded with spaces, no subsystem data
+    let mut out = Vec::new();
+    out.extend_from_slice(DESCRIPTION.as_bytes());
+    out.resize(116, b' ');
+    out.extend_from_slice(&[0; 8]);
+    out.extend_from_slice(&0x0100u16.to_le_bytes());
+    out.extend_from_slice(b"IM");
+
+    for (name, var) in vars.iter() {
+        check_name(name)?;
+        out.extend(matrix_element(name, var)?);
+    }
+    Ok(out)
+}
+
+// Write named variables as a .mat file
+pub fn write_mat(filename: &str, vars: &[(&str, MatVar)]) -> io::Result<()> {
+    File::create(filename)?.write_all(&mat_bytes(vars)?)
+}
+
+// One miMATRIX element
+fn matrix_element(name: &str, var: &MatVar) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    match var {
+        MatVar::Double { rows, cols, data } => {
+            assert!(data.len() == rows * cols);
+            header_elements(&mut body, MX_DOUBLE_CLASS, *rows, *cols, name)?;
+            let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+            element(&mut body, MI_DOUBLE, &bytes)?;
+        },
+        MatVar::Text(text) => {
+            let units: Vec<u16> = text.encode_utf16().collect();
+            header_elements(&mut body, MX_CHAR_CLASS, 1, units.len(), name)?;
+            let bytes: Vec<u8> = units.iter().flat_map(|u| u.to_le_bytes().to_vec()).collect();
+            element(&mut body, MI_UINT16, &bytes)?;
+        },
+        MatVar::Struct(fields) => {
+            header_elements(&mut body, MX_STRUCT_CLASS, 1, 1, name)?;
+
+            // Field names, each null padded to the same length
+            let name_len = MAX_NAME_LEN + 1;
+            small_element(&mut body, MI_INT32, &(name_len as i32).to_le_bytes());
+            let mut names = Vec::with_capacity(fields.len() * name_len);
+            for (field, _) in fields.iter() {
+                check_name(field)?;
+                names.extend_from_slice(field.as_bytes());
+                names.resize(names.len() + name_len - field.len(), 0);
+            }
+            element(&mut body, MI_INT8, &names)?;
+
+            // Then the fields themselves, unnamed
+            for (_, value) in fields.iter() {
+                body.extend(matrix_element("", value)?);
+            }
+        },
+    }
+    let mut out = Vec::with_capacity(body.len() + 8);
+    element(&mut out, MI_MATRIX, &body)?;
+    Ok(out)
+}
+
+// Array flags, dimensions and name
+fn header_elements(out: &mut Vec<u8>, class: u32, rows: usize, cols: usize, name: &str)
+    -> io::Result<()> {
+    let mut flags = class.to_le_bytes().to_vec();
+    flags.extend_from_slice(&0u32.to_le_bytes());
+    element(out, MI_UINT32, &flags)?;
+    let mut dims = fits::<i32>(rows, "row count")?.to_le_bytes().to_vec();
+    dims.extend_from_slice(&fits::<i32>(cols, "column count")?.to_le_bytes());
+    element(out, MI_INT32, &dims)?;
+    element(out, MI_INT8, name.as_bytes())
+}
+
+// Tag and data, padded to 8 bytes
+fn element(out: &mut Vec<u8>, data_type: u32, data: &[u8]) -> io::Result<()> {
+    out.extend_from_slice(&data_type.to_le_bytes());
+    out.extend_from_slice(&fits::<u32>(data.len(), "element size")?.to_le_bytes());
+    out.extend_from_slice(data);
+    out.resize(out.len() + (8 - data.len() % 8) % 8, 0);
+    Ok(())
+}
+
+// A size as a MAT v5 tag or dimension field, which are 32 bits
+fn fits<T: TryFrom<usize>>(value: usize, what: &str) -> io::Result<T> {
+    T::try_from(value).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+        format!("{} {} is too big for a MAT v5 file", what, value)))
+}
+
+// Up to 4 bytes of data packed into the tag
+fn small_element(out: &mut Vec<u8>, data_type: u32, data: &[u8]) {
+    assert!(data.len() <= 4);
+    out.extend_from_slice(&(data_type as u16).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    out.extend_from_slice(data);
+    out.resize(out.len() + 4 - data.len(), 0);
+}
+
+// MATLAB names start with a letter and hold letters, digits and _
+fn check_name(name: &str) -> io::Result<()> {
+    let valid = name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid MATLAB name", name)));
+    }
+    Ok(())
+}
+
+// Variables for a CAF surface: "surface" (freqs x lags |xcor|^2),
+// "freqs" (Hz, column) and "lags" (signed samples, row), so that
+// surface(i, j) is at freqs(i), lags(j). Rows are sorted by frequency
+pub fn surface_mat_vars(surface: &[CafSurfaceRow]) -> Vec<(&'static str, MatVar)> {
+    let mut rows: Vec<&CafSurfaceRow> = surface.iter().collect();
+    rows.sort_by(|a, b| a.freq.partial_cmp(&b.freq).unwrap());
+    let row_len = rows.first().map(|r| r.xcor_mag.len()).unwrap_or(0);
+    let mut values = Vec::with_capacity(rows.len() * row_len);
+    for row in rows.iter() {
+        assert!(row.xcor_mag.len() == row_len);
+        values.extend_from_slice(&row.xcor_mag);
+    }
+    let freqs: Vec<f64> = rows.iter().map(|r| r.freq).collect();
+    let lags: Vec<f64> = (0..row_len).map(|i| signed_lag(i, row_len) as f64).collect();
+    vec![
+        ("surface", MatVar::matrix(rows.len(), row_len, &values)),
+        ("freqs", MatVar::column(&freqs)),
+        ("lags", MatVar::row(&lags)),
+    ]
+}
+
+// A struct of column vectors, one entry per peak, strongest first
+pub fn peaks_mat_var(result: &CafResult) -> MatVar {
+    let column = |f: &dyn Fn(usize) -> f64| MatVar::column(
+        &(0..result.peaks.len()).map(f).collect::<Vec<f64>>());
+    let peaks = &result.peaks;
+    MatVar::Struct(vec![
+        ("freq_hz".to_string(), column(&|i| peaks[i].freq_hz)),
+        ("lag_samples".to_string(), column(&|i| peaks[i].lag_samples as f64)),
+        ("lag_s".to_string(), column(&|i| peaks[i].lag_s)),
+        ("value".to_string(), column(&|i| peaks[i].value)),
+        ("relative_db".to_string(), column(&|i| peaks[i].relative_db)),
+        ("refined_freq_hz".to_string(), column(&|i| peaks[i].refined_freq_hz)),
+        ("refined_lag_samples".to_string(), column(&|i| peaks[i].refined_lag_samples)),
+        ("refined_lag_s".to_string(), column(&|i| peaks[i].refined_lag_s)),
+    ])
+}
+
+// Write a CAF surface for MATLAB
+pub trait MatlabIO {
+    // .mat with "surface", "freqs", "lags" and "fs", plus "peaks" and
+    // "backend" from result when given
+    fn write_mat(&self, filename: &str, fs: u32, result: Option<&CafResult>) -> io::Result<()>;
+}
+
+impl MatlabIO for Vec<CafSurfaceRow> {
+
+    fn write_mat(&self, filename: &str, fs: u32, result: Option<&CafResult>) -> io::Result<()> {
+        let mut vars = surface_mat_vars(self);
+        vars.push(("fs", MatVar::scalar(fs as f64)));
+        if let Some(result) = result {
+            vars.push(("peaks", peaks_mat_var(result)));
+            if let Some(backend) = result.backend.as_ref() {
+                vars.push(("backend", MatVar::Text(backend.clone())));
+            }
+        }
+        write_mat(filename, &vars)
+    }
+}
@@ -0,0 +1,142 @@
+// Readers for captures too big to load whole
+// MappedCapture memory-maps a file and decodes only the windows that
+// are asked for, so a 20GB capture costs address space rather than
+// RAM. ChunkedReader decodes fixed-size blocks from any io::Read
+// (pipes, sockets, compressed streams) where mapping isn't possible.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use memmap::Mmap;
+use num_complex::Complex64;
+
+use super::{decode_samples, Endian, SampleFormat};
+
+pub struct MappedCapture {
+    mmap: Option<Mmap>, // None for empty files, which can't be mapped
+    format: SampleFormat,
+    endian: Endian,
+}
+
+impl MappedCapture {
+
+    // Map a raw IQ file
+    pub fn open(filename: &str, format: SampleFormat, endian: Endian)
+        -> io::Result<Self> {
+
+        let f = File::open(filename)?;
+        let mmap = if f.metadata()?.len() > 0 {
+            // Safety: the capture must not be truncated while mapped
+            Some(unsafe { Mmap::map(&f)? })
+        } else {
+            None
+        };
+        Ok(MappedCapture { mmap, format, endian })
+    }
+
+    // Raw bytes of the capture
+    fn bytes(&self) -> &[u8] {
+        match &self.mmap {
+            Some(mmap) => &mmap[..],
+            None => &[],
+        }
+    }
+
+    // Number of complete samples in the capture
+    pub fn len(&self) -> usize {
+        self.bytes().len() / self.format.sample_bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Decode up to len samples starting at sample offset. The window
+    // is cut short at the end of the capture
+    pub fn window(&self, offset: usize, len: usize) -> Vec<Complex64> {
+        let start = offset.min(self.len());
+        let end = offset.saturating_add(len).min(self.len());
+        let size = self.format.sample_bytes();
+        let mut samples = Vec::with_capacity(end - start);
+        decode_samples(&self.bytes()[start * size..end * size],
+            self.format, self.endian, &mut samples);
+        samples
+    }
+
+    // Iterate over block_len sample windows, each starting
+    // block_len - overlap samples after the last
+    pub fn blocks(&self, block_len: usize, overlap: usize) -> MappedBlocks<'_> {
+        assert!(overlap < block_len);
+        MappedBlocks { capture: self, offset: 0, block_len, step: block_len - overlap }
+    }
+}
+
+pub struct MappedBlocks<'a> {
+    capture: &'a MappedCapture,
+    offset: usize,
+    block_len: usize,
+    step: usize,
+}
+
+impl<'a> Iterator for MappedBlocks<'a> {
+    // (sample offset, samples)
+    type Item = (usize, Vec<Complex64>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.capture.len() {
+            return None;
+        }
+        let offset = self.offset;
+        self.offset += self.step;
+        Some((offset, self.capture.window(offset, self.block_len)))
+    }
+}
+
+pub struct ChunkedReader<R: Read> {
+    reader: R,
+    format: SampleFormat,
+    endian: Endian,
+    buffer: Vec<u8>, // one block of raw bytes
+}
+
+impl<R: Read> ChunkedReader<R> {
+
+    // Constructor, yielding block_len samples at a time
+    pub fn new(reader: R, format: SampleFormat, endian: Endian, block_len: usize) -> Self {
+        assert!(block_len > 0);
+        ChunkedReader {
+            reader,
+            format,
+            endian,
+            buffer: vec![0; block_len * format.sample_bytes()],
+        }
+    }
+}
+
+impl<R: Read> Iterator for ChunkedReader<R> {
+    type Item = io::Result<Vec<Complex64>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+
+        // Fill the block, tolerating short reads, until EOF
+        let mut filled = 0;
+        while filled < self.buffer.len() {
+            match self.reader.read(&mut self.buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        // Decode what we got, the last block may be short
+        let usable = filled - filled % self.format.sample_bytes();
+        if usable == 0 {
+            return None;
+        }
+        let mut samples = Vec::with_capacity(usable / self.format.sample_bytes());
+        decode_samples(&self.buffer[..usable], self.format, self.endian, &mut samples);
+        Some(Ok(samples))
+    }
+}
@@ -0,0 +1,54 @@
+use std::io;
+
+use num_complex::Complex64;
+
+mod channels;
+mod formats;
+mod gr_meta;
+mod mat;
+mod mmap;
+mod npy;
+mod output;
+mod range;
+mod sigmf;
+mod socket;
+mod surface;
+mod timestamp;
+mod wav;
+
+pub use channels::{deinterleave, read_channels, read_channels_range};
+pub use formats::{decode_samples, encode_samples, read_file, write_file,
+    Endian, SampleFormat};
+pub use gr_meta::{gr_meta_paths, is_gr_meta, parse_gr_meta, GrMeta, GrMetaSegment, Pmt};
+pub use mat::{mat_bytes, peaks_mat_var, surface_mat_vars, write_mat, MatVar, MatlabIO};
+pub use mmap::{ChunkedReader, MappedBlocks, MappedCapture};
+pub use npy::{crc32, f64_bytes, i64_bytes, npy_bytes, write_npz, NpyDtype, NumpyIO};
+pub use output::{write_matrix, write_results, DetectionWriter, OutputFormat};
+pub use range::{read_range, read_time_range, Position, SampleRange};
+pub use sigmf::{is_sigmf, parse_datatype, read_sigmf, sigmf_paths,
+    SigMFAnnotation, SigMFCapture, SigMFGlobal, SigMFMeta};
+pub use socket::{SocketSpec, TcpSource, UdpHeader, UdpSource};
+pub use surface::{fnv1a64, read_surface, read_surface_header, write_surface, SourceFile,
+    SurfaceDtype, SurfaceHeader, SURFACE_FORMAT_VERSION};
+pub use timestamp::Timestamp;
+pub use wav::{parse_wav, read_wav, Wav};
+
+
+// Reads a file of packed 32 bit floats and returns
+// a Vec of its contents as Complex64 (2x 64 bit floats)
+pub fn read_file_c64(filename: &str) -> io::Result<Vec<Complex64>> {
+    read_file(filename, SampleFormat::Cf32, Endian::Little)
+}
+
+// Read/write a slice of Complex64's to/from a file
+// compatible with numpy's fromfile function
+pub trait BinaryIO {
+    fn write_file_binary(&self, filename: &str) -> io::Result<()>;
+}
+// numpy dtype=np.complex128
+impl BinaryIO for Vec<Complex64> {
+
+    fn write_file_binary(&self, filename: &str) -> io::Result<()> {
+        write_file(filename, self, SampleFormat::Cf64, Endian::Little)
+    }
+}
@@ -0,0 +1,194 @@
+// NumPy .npy and .npz output
+// .npy is a small text header (dtype, order, shape) followed by the
+// raw little-endian data. .npz is a zip of .npy files, written here
+// uncompressed ("stored"), which numpy.load reads just the same.
+// https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use crate::caf::{signed_lag, CafSurfaceRow};
+
+// Array element types we write
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NpyDtype {
+    F64, // '<f8'
+    I64, // '<i8'
+}
+
+impl NpyDtype {
+    fn descr(&self) -> &'static str {
+        match self {
+            NpyDtype::F64 => "<f8",
+            NpyDtype::I64 => "<i8",
+        }
+    }
+}
+
+// Serialize one array as .npy bytes. data holds the raw little-endian
+// elements in C (row-major) order
+pub fn npy_bytes(dtype: NpyDtype, shape: &[usize], data: &[u8]) -> Vec<u8> {
+
+    // Header dict, with the trailing comma numpy uses for 1-tuples
+    let shape_str = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!("({})", shape.iter().map(|d| d.to_string())
+            .collect::<Vec<String>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        dtype.descr(), shape_str);
+
+    // Pad with spaces and a newline so the data starts 64-byte aligned
+    let preamble = 10; // magic(6) + version(2) + header length(2)
+    let total = (preamble + header.len() + 1).div_ceil(64) * 64;
+    while preamble + header.len() + 1 < total {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    // Magic, version 1.0, header and data
+    let mut out = Vec::with_capacity(total + data.len());
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+// Raw little-endian bytes of a slice of f64
+pub fn f64_bytes(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+}
+
+// Raw little-endian bytes of a slice of i64
+pub fn i64_bytes(values: &[i64]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+}
+
+// Write a set of named .npy arrays as an uncompressed .npz (zip).
+// Without zip64, members and the archive must stay under 4 GiB and
+// there can be at most 65535 members
+pub fn write_npz(filename: &str, arrays: &[(&str, Vec<u8>)]) -> io::Result<()> {
+    let n_arrays = u16::try_from(arrays.len())
+        .map_err(|_| too_big(format!("{} arrays is too many for an npz", arrays.len())))?;
+    let mut out: Vec<u8> = Vec::new();
+    let mut central: Vec<u8> = Vec::new();
+    for (name, npy) in arrays.iter() {
+        let name = format!("{}.npy", name);
+        let size = zip32(npy.len(), &name)?;
+        let offset = zip32(out.len(), &name)?;
+        let crc = crc32(npy);
+
+        // Local file header and data
+        out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        zip_entry_fields(&mut out, &name, crc, size);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(npy);
+
+        // Central directory record pointing back at it
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        zip_entry_fields(&mut central, &name, crc, size);
+        central.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        central.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        central.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    // End of central directory
+    let central_offset = zip32(out.len(), "the central directory")?;
+    let central_len = zip32(central.len(), "the central directory")?;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // this disk
+    out.extend_from_slice(&0u16.to_le_bytes()); // central directory disk
+    out.extend_from_slice(&n_arrays.to_le_bytes());
+    out.extend_from_slice(&n_arrays.to_le_bytes());
+    out.extend_from_slice(&central_len.to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+    File::create(filename)?.write_all(&out)
+}
+
+// A size or offset as a zip's 32 bit field, what it is for the error
+fn zip32(value: usize, what: &str) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| too_big(format!("{} is past the 4 GiB an npz can hold \
+        without zip64", what)))
+}
+
+fn too_big(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// Fields shared by local and central zip headers, up to the name
+fn zip_entry_fields(out: &mut Vec<u8>, name: &str, crc: u32, size: u32) {
+    out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+    out.extend_from_slice(&0u16.to_le_bytes()); // flags
+    out.extend_from_slice(&0u16.to_le_bytes()); // stored, no compression
+    out.extend_from_slice(&0u16.to_le_bytes()); // mod time
+    out.extend_from_slice(&0x0021u16.to_le_bytes()); // mod date, 1980-01-01
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes()); // compressed
+    out.extend_from_slice(&size.to_le_bytes()); // uncompressed
+    out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // extra length
+}
+
+// CRC-32 (IEEE 802.3), as used by zip
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// Write a CAF surface for numpy. Rows are sorted by frequency (the
+// threaded backends return them in completion order)
+pub trait NumpyIO {
+    // |xcor|^2 surface as a 2D (freqs x lags) .npy
+    fn write_npy(&self, filename: &str) -> io::Result<()>;
+    // .npz with "surface", "freqs" (Hz) and "lags" (signed samples)
+    fn write_npz(&self, filename: &str) -> io::Result<()>;
+}
+
+impl NumpyIO for Vec<CafSurfaceRow> {
+
+    fn write_npy(&self, filename: &str) -> io::Result<()> {
+        let (shape, surface, _, _) = surface_arrays(self);
+        File::create(filename)?.write_all(&npy_bytes(NpyDtype::F64, &shape, &surface))
+    }
+
+    fn write_npz(&self, filename: &str) -> io::Result<()> {
+        let (shape, surface, freqs, lags) = surface_arrays(self);
+        write_npz(filename, &[
+            ("surface", npy_bytes(NpyDtype::F64, &shape, &surface)),
+            ("freqs", npy_bytes(NpyDtype::F64, &[shape[0]], &f64_bytes(&freqs))),
+            ("lags", npy_bytes(NpyDtype::I64, &[shape[1]], &i64_bytes(&lags))),
+        ])
+    }
+}
+
+// Shape, surface bytes, frequency axis and lag axis of a surface
+fn surface_arrays(surface: &[CafSurfaceRow]) -> (Vec<usize>, Vec<u8>, Vec<f64>, Vec<i64>) {
+    let mut rows: Vec<&CafSurfaceRow> = surface.iter().collect();
+    rows.sort_by(|a, b| a.freq.partial_cmp(&b.freq).unwrap());
+    let row_len = rows.first().map(|r| r.xcor_mag.len()).unwrap_or(0);
+    let mut data = Vec::with_capacity(rows.len() * row_len * 8);
+    for row in rows.iter() {
+        assert!(row.xcor_mag.len() == row_len);
+        data.extend(f64_bytes(&row.xcor_mag));
+    }
+    let freqs = rows.iter().map(|r| r.freq).collect();
+    let lags = (0..row_len).map(|i| signed_lag(i, row_len)).collect();
+    (vec![rows.len(), row_len], data, freqs, lags)
+}
@@ -0,0 +1,211 @@
+// Writing CAF results for people and for pipelines
+//   text   the two lines the CLI has always printed, per result, and
+//          the absolute TDOA when start times are known, then a
+//          line per segment of a segmented search
+//   json   one pretty-printed object, or an array of them
+//   jsonl  one compact object per line
+//   csv    a header, then one line per peak of every result
+// Stream detections are written as they come, so json is one compact
+// object per line there too: a stream has no end to close an array at.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use crate::caf::{CafMatrix, CafResult, StreamDetection};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+const CSV_HEADER: &str = "rank,freq_hz,lag_idx,lag_samples,lag_s,value,relative_db,\
+    refined_freq_hz,refined_lag_samples,refined_lag_s,peak_to_mean_db,peak_to_second_db,\
+    fs,backend,needle,haystack,surface_s,total_s,tdoa_s,refined_tdoa_s";
+
+// Write results in the given format
+pub fn write_results<W: Write>(out: &mut W, results: &[CafResult], format: OutputFormat)
+    -> io::Result<()> {
+
+    let json_error = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    match format {
+        OutputFormat::Text => {
+            for result in results.iter() {
+                let peak = result.peak();
+                writeln!(out, "Frequency offset: {:.1}Hz", peak.freq_hz)?;
+                writeln!(out, "Time offset: {} samples ({:.3}ms)",
+                    peak.lag_idx, (peak.lag_idx as f64) * 1e3 / (result.config.fs as f64))?;
+                if let (Some(tdoa_s), Some(start)) = (peak.tdoa_s, result.start_times.as_ref()) {
+                    writeln!(out, "Absolute TDOA: {:.9}s ({:.9}s between start times)",
+                        tdoa_s, start.offset_ns as f64 * 1e-9)?;
+                }
+                for segment in result.segments.iter() {
+                    writeln!(out, "Segment {} at sample {}: {:.1}Hz, {} samples ({:.3}ms)",
+                        segment.segment, segment.start_sample, segment.freq_hz,
+                        segment.lag_samples, segment.lag_s * 1e3)?;
+                }
+            }
+        },
+        OutputFormat::Json => {
+            if results.len() == 1 {
+                serde_json::to_writer_pretty(&mut *out, &results[0]).map_err(json_error)?;
+            } else {
+                serde_json::to_writer_pretty(&mut *out, results).map_err(json_error)?;
+            }
+            writeln!(out)?;
+        },
+        OutputFormat::Jsonl => {
+            for result in results.iter() {
+                serde_json::to_writer(&mut *out, result).map_err(json_error)?;
+                writeln!(out)?;
+            }
+        },
+        OutputFormat::Csv => {
+            writeln!(out, "{}", CSV_HEADER)?;
+            for result in results.iter() {
+                write_csv_rows(out, result)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+const MATRIX_CSV_HEADER: &str = "needle_channel,haystack_channel,freq_hz,lag_samples,lag_s,\
+    value,refined_freq_hz,refined_lag_samples,refined_lag_s";
+
+// Write a channel CAF matrix in the given format. Text and CSV list
+// the pairs computed, JSON and JSON Lines hold the whole matrix
+pub fn write_matrix<W: Write>(out: &mut W, matrix: &CafMatrix, format: OutputFormat)
+    -> io::Result<()> {
+
+    let json_error = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let pairs = matrix.pairs().into_iter()
+        .filter_map(|(i, j)| matrix.get(i, j).map(|peak| (i, j, peak)));
+    match format {
+        OutputFormat::Text => {
+            for (i, j, peak) in pairs {
+                writeln!(out, "Channel {} relative to {}: {:.1}Hz, {} samples ({:.3}ms)",
+                    j, i, peak.freq_hz, peak.lag_samples, peak.lag_s * 1e3)?;
+            }
+            if !matrix.closures.is_empty() {
+                let (tdoa_s, fdoa_hz) = matrix.max_closure_error();
+                writeln!(out, "Worst closure error: {:.3}us, {:.3}Hz", tdoa_s * 1e6, fdoa_hz)?;
+            }
+        },
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, matrix).map_err(json_error)?;
+            writeln!(out)?;
+        },
+        OutputFormat::Jsonl => {
+            serde_json::to_writer(&mut *out, matrix).map_err(json_error)?;
+            writeln!(out)?;
+        },
+        OutputFormat::Csv => {
+            writeln!(out, "{}", MATRIX_CSV_HEADER)?;
+            for (i, j, peak) in pairs {
+                writeln!(out, "{},{},{},{},{},{},{},{},{}", i, j, peak.freq_hz,
+                    peak.lag_samples, peak.lag_s, peak.value, peak.refined_freq_hz,
+                    peak.refined_lag_samples, peak.refined_lag_s)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+const STREAM_CSV_HEADER: &str = "sample,time_s,freq_hz,value,score";
+
+// Writes stream detections one at a time, flushing each
+pub struct DetectionWriter<W: Write> {
+    out: W,
+    format: OutputFormat,
+    started: bool, // CSV header written
+}
+
+impl<W: Write> DetectionWriter<W> {
+
+    // Constructor
+    pub fn new(out: W, format: OutputFormat) -> Self {
+        DetectionWriter { out, format, started: false }
+    }
+
+    // Write one detection
+    pub fn write(&mut self, detection: &StreamDetection) -> io::Result<()> {
+        let json_error = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self.format {
+            OutputFormat::Text => {
+                writeln!(self.out, "Needle at sample {} ({:.6}s): {:.1}Hz, score {:.3}",
+                    detection.sample, detection.time_s, detection.freq_hz, detection.score)?;
+            },
+            OutputFormat::Json | OutputFormat::Jsonl => {
+                serde_json::to_writer(&mut self.out, detection).map_err(json_error)?;
+                writeln!(self.out)?;
+            },
+            OutputFormat::Csv => {
+                if !self.started {
+                    writeln!(self.out, "{}", STREAM_CSV_HEADER)?;
+                    self.started = true;
+                }
+                writeln!(self.out, "{},{},{},{},{}", detection.sample, detection.time_s,
+                    detection.freq_hz, detection.value, detection.score)?;
+            },
+        }
+        self.out.flush()
+    }
+}
+
+// One CSV line per peak, the result-wide columns repeated
+fn write_csv_rows<W: Write>(out: &mut W, result: &CafResult) -> io::Result<()> {
+    let optional = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    let text = |v: &Option<String>| v.as_deref().map(csv_field).unwrap_or_default();
+    for (rank, peak) in result.peaks.iter().enumerate() {
+        writeln!(out, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            rank + 1, peak.freq_hz, peak.lag_idx, peak.lag_samples, peak.lag_s, peak.value,
+            peak.relative_db, peak.refined_freq_hz, peak.refined_lag_samples,
+            peak.refined_lag_s, result.quality.peak_to_mean_db,
+            optional(result.quality.peak_to_second_db), result.config.fs,
+            text(&result.backend), text(&result.config.needle), text(&result.config.haystack),
+            optional(result.timing.as_ref().map(|t| t.surface_s)),
+            optional(result.timing.as_ref().map(|t| t.total_s)),
+            optional(peak.tdoa_s), optional(peak.refined_tdoa_s))?;
+    }
+    Ok(())
+}
+
+// Quote a field if it holds a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
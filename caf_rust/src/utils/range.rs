@@ -0,0 +1,144 @@
+// Reading just a window of a capture
+// Windows are given in samples or in seconds (converted with the
+// sample rate), and are read by seeking straight to the first byte,
+// so pulling 0.1s out of a huge file costs 0.1s worth of I/O.
+// Range strings look like "1000..5096", "0.5s..0.6s" or "0.5s+0.1s".
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::str::FromStr;
+
+use num_complex::Complex64;
+
+use super::{decode_samples, Endian, SampleFormat};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    Sample(u64),
+    Seconds(f64),
+}
+
+impl Position {
+
+    // Sample index of this position at sample rate fs
+    pub fn to_sample(&self, fs: u32) -> u64 {
+        match *self {
+            Position::Sample(n) => n,
+            Position::Seconds(t) => (t * (fs as f64)).round() as u64,
+        }
+    }
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("invalid position '{}'", s);
+        match s.strip_suffix('s') {
+            Some(t) => {
+                let t = t.parse::<f64>().map_err(|_| invalid())?;
+                if t < 0.0 || !t.is_finite() {
+                    return Err(invalid());
+                }
+                Ok(Position::Seconds(t))
+            },
+            None => Ok(Position::Sample(s.parse::<u64>().map_err(|_| invalid())?)),
+        }
+    }
+}
+
+// Half-open [start, end) window of a capture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleRange {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl SampleRange {
+
+    // (first sample, number of samples) at sample rate fs
+    pub fn resolve(&self, fs: u32) -> (u64, usize) {
+        let start = self.start.to_sample(fs);
+        let end = self.end.to_sample(fs);
+        (start, end.saturating_sub(start) as usize)
+    }
+}
+
+impl FromStr for SampleRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(idx) = s.find("..") {
+            // start..end, both in the same units and end after start
+            let start = s[..idx].parse()?;
+            let end = s[idx + 2..].parse()?;
+            let forward = match (start, end) {
+                (Position::Sample(a), Position::Sample(b)) => a < b,
+                (Position::Seconds(a), Position::Seconds(b)) => a < b,
+                _ => return Err(format!("mixed units in range '{}'", s)),
+            };
+            if !forward {
+                return Err(format!("range '{}' is empty or ends before it starts", s));
+            }
+            Ok(SampleRange { start, end })
+        } else if let Some(idx) = s.find('+') {
+            // start+length, both in the same units
+            let start = s[..idx].parse()?;
+            let end = match (start, s[idx + 1..].parse()?) {
+                (Position::Sample(a), Position::Sample(n)) => Position::Sample(a.checked_add(n)
+                    .ok_or_else(|| format!("range '{}' runs past the last sample", s))?),
+                (Position::Seconds(a), Position::Seconds(n)) if (a + n).is_finite() =>
+                    Position::Seconds(a + n),
+                (Position::Seconds(_), Position::Seconds(_)) =>
+                    return Err(format!("range '{}' is too long", s)),
+                _ => return Err(format!("mixed units in range '{}'", s)),
+            };
+            Ok(SampleRange { start, end })
+        } else {
+            Err(format!("invalid range '{}', expected start..end or start+length", s))
+        }
+    }
+}
+
+// Read exactly count samples starting at sample start
+pub fn read_range(filename: &str, format: SampleFormat, endian: Endian,
+    start: u64, count: usize) -> io::Result<Vec<Complex64>> {
+
+    // Check the window lies in the file before allocating for it
+    let mut f = File::open(filename)?;
+    let file_len = f.metadata()?.len();
+    let (offset, len) = window_bytes(start, count, format)
+        .filter(|(offset, len)| offset.checked_add(*len as u64).is_some_and(|end| end <= file_len))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} samples from sample {} run past the end of {}", count, start, filename)))?;
+
+    // Seek to the window and read it in one go
+    f.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0; len];
+    f.read_exact(&mut buffer)?;
+
+    // Decode and return
+    let mut samples = Vec::with_capacity(count);
+    decode_samples(&buffer, format, endian, &mut samples);
+    Ok(samples)
+}
+
+// (byte offset, byte length) of count samples from sample start, None
+// if either overflows
+fn window_bytes(start: u64, count: usize, format: SampleFormat) -> Option<(u64, usize)> {
+    let offset = start.checked_mul(format.sample_bytes() as u64)?;
+    let len = count.checked_mul(format.sample_bytes())?;
+    Some((offset, len))
+}
+
+// Read duration_s seconds of samples starting start_s seconds in
+pub fn read_time_range(filename: &str, format: SampleFormat, endian: Endian,
+    fs: u32, start_s: f64, duration_s: f64) -> io::Result<Vec<Complex64>> {
+
+    let start = Position::Seconds(start_s).to_sample(fs);
+    let count = Position::Seconds(duration_s).to_sample(fs) as usize;
+    read_range(filename, format, endian, start, count)
+}
@@ -0,0 +1,209 @@
+// SigMF recordings (.sigmf-meta JSON + .sigmf-data samples)
+// Only the core namespace fields we use are typed, everything else
+// is carried through untouched so rewriting a metadata file (e.g.
+// to add CAF peak annotations) doesn't lose anything.
+// https://github.com/gnuradio/SigMF/blob/master/sigmf-spec.md
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use num_complex::Complex64;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{read_file, Endian, SampleFormat, Timestamp};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SigMFMeta {
+    pub global: SigMFGlobal,
+    #[serde(default)]
+    pub captures: Vec<SigMFCapture>,
+    #[serde(default)]
+    pub annotations: Vec<SigMFAnnotation>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SigMFGlobal {
+    #[serde(rename = "core:datatype")]
+    pub datatype: String,
+    #[serde(rename = "core:sample_rate", default, skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+    #[serde(rename = "core:version", default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(rename = "core:description", default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SigMFCapture {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    #[serde(rename = "core:frequency", default, skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    #[serde(rename = "core:datetime", default, skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SigMFAnnotation {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    #[serde(rename = "core:sample_count", default, skip_serializing_if = "Option::is_none")]
+    pub sample_count: Option<u64>,
+    #[serde(rename = "core:freq_lower_edge", default, skip_serializing_if = "Option::is_none")]
+    pub freq_lower_edge: Option<f64>,
+    #[serde(rename = "core:freq_upper_edge", default, skip_serializing_if = "Option::is_none")]
+    pub freq_upper_edge: Option<f64>,
+    #[serde(rename = "core:label", default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(rename = "core:comment", default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl SigMFMeta {
+
+    // Parse a .sigmf-meta file
+    pub fn read(filename: &str) -> io::Result<Self> {
+        let f = File::open(filename)?;
+        serde_json::from_reader(io::BufReader::new(f))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Write out as a .sigmf-meta file
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        let f = File::create(filename)?;
+        serde_json::to_writer_pretty(f, self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Sample format of the dataset, e.g. "cf32_le"
+    pub fn sample_format(&self) -> io::Result<(SampleFormat, Endian)> {
+        parse_datatype(&self.global.datatype)
+    }
+
+    // Sample rate in Hz, if recorded
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.global.sample_rate
+    }
+
+    // Center frequency of the first capture segment, if recorded
+    pub fn frequency(&self) -> Option<f64> {
+        self.captures.first().and_then(|c| c.frequency)
+    }
+
+    // Start time of the first capture segment, if recorded
+    pub fn datetime(&self) -> Option<&str> {
+        self.captures.first().and_then(|c| c.datetime.as_deref())
+    }
+
+    // Time of sample 0, from the first capture segment's datetime
+    // taken back to the start of the dataset
+    pub fn start_time(&self) -> io::Result<Option<Timestamp>> {
+        let capture = match self.captures.first() {
+            Some(capture) => capture,
+            None => return Ok(None),
+        };
+        let datetime = match capture.datetime.as_deref() {
+            Some(datetime) => datetime,
+            None => return Ok(None),
+        };
+        let time: Timestamp = datetime.parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let rate = self.sample_rate().map(|rate| rate.round() as u32).filter(|rate| *rate > 0);
+        Ok(Some(match rate {
+            Some(rate) if capture.sample_start > 0 => {
+                let start = i64::try_from(capture.sample_start)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                time.add_samples(-start, rate)
+            },
+            _ => time,
+        }))
+    }
+
+    // Record a CAF peak found in this recording. The lag is where the
+    // needle starts in this recording and freq_offset is relative to the
+    // capture's center frequency (when known). The frequency edges span
+    // bandwidth_hz around it
+    pub fn annotate_caf_peak(&mut self, sample_start: u64, sample_count: u64,
+        freq_offset: f64, bandwidth_hz: f64, label: &str) {
+
+        let center = self.frequency().map(|f| f + freq_offset);
+        let mut extra = Map::new();
+        extra.insert("caf:freq_offset".to_string(), freq_offset.into());
+        self.annotations.push(SigMFAnnotation {
+            sample_start,
+            sample_count: Some(sample_count),
+            freq_lower_edge: center.map(|f| f - 0.5 * bandwidth_hz),
+            freq_upper_edge: center.map(|f| f + 0.5 * bandwidth_hz),
+            label: Some(label.to_string()),
+            comment: Some(format!("CAF peak at {} samples, {:.3}Hz offset",
+                sample_start, freq_offset)),
+            extra,
+        });
+
+        // Annotations are kept ordered by sample_start
+        self.annotations.sort_by_key(|a| a.sample_start);
+    }
+}
+
+// Map a SigMF datatype string to our sample format. Only the spec's
+// own names are accepted, not the command line's aliases: 8 bit types
+// have no byte order and wider ones must give theirs
+pub fn parse_datatype(datatype: &str) -> io::Result<(SampleFormat, Endian)> {
+    let (format, endian) = match datatype.split_once('_') {
+        Some((format, "le")) => (format, Some(Endian::Little)),
+        Some((format, "be")) => (format, Some(Endian::Big)),
+        Some(_) => ("", None),
+        None => (datatype, None),
+    };
+    let format = match format {
+        "ci8" => SampleFormat::Ci8,
+        "cu8" => SampleFormat::Cu8,
+        "ci16" => SampleFormat::Ci16,
+        "cf32" => SampleFormat::Cf32,
+        "cf64" => SampleFormat::Cf64,
+        _ => return Err(unsupported_datatype(datatype)),
+    };
+    match (format.component_bytes(), endian) {
+        (1, None) => Ok((format, Endian::Little)),
+        (bytes, Some(endian)) if bytes > 1 => Ok((format, endian)),
+        _ => Err(unsupported_datatype(datatype)),
+    }
+}
+
+fn unsupported_datatype(datatype: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unsupported SigMF datatype '{}'",
+        datatype))
+}
+
+// Map a SigMF base name, .sigmf-meta or .sigmf-data path to the
+// (meta, data) file pair
+pub fn sigmf_paths(filename: &str) -> (PathBuf, PathBuf) {
+    let base = [".sigmf-meta", ".sigmf-data", ".sigmf"].iter()
+        .find_map(|ext| filename.strip_suffix(ext))
+        .unwrap_or(filename);
+    (PathBuf::from(format!("{}.sigmf-meta", base)),
+        PathBuf::from(format!("{}.sigmf-data", base)))
+}
+
+// True if filename names one half of a SigMF recording
+pub fn is_sigmf(filename: &str) -> bool {
+    filename.ends_with(".sigmf-meta") || filename.ends_with(".sigmf-data")
+}
+
+// Read a SigMF recording's metadata and samples
+pub fn read_sigmf(filename: &str) -> io::Result<(SigMFMeta, Vec<Complex64>)> {
+    let (meta_path, data_path) = sigmf_paths(filename);
+    let meta = SigMFMeta::read(&meta_path.to_string_lossy())?;
+    let (format, endian) = meta.sample_format()?;
+    let samples = read_file(&data_path.to_string_lossy(), format, endian)?;
+    Ok((meta, samples))
+}
@@ -0,0 +1,239 @@
+// Live samples over local sockets, as GNU Radio's network sinks send them
+//   tcp://host:port          connect to a TCP Sink in server mode
+//   tcp-listen://addr:port   accept one TCP Sink in client mode
+//   udp://addr:port          receive from a UDP Sink
+// TCP carries a plain byte stream of samples. UDP carries one payload
+// per datagram, optionally behind the 64 bit sequence number header
+// of gr-network's UDP Sink, and an empty datagram marks the end of
+// the stream (the sink's "send EOF" option). With sequence numbers,
+// lost datagrams are replaced by zeros so later samples keep their
+// place in the stream, up to MAX_GAP datagrams. A sequence number
+// further ahead than that, or further behind, means the sender
+// restarted or the numbers are garbage, so the stream resyncs there
+// without filling in. Every source is a Read, so ChunkedReader turns
+// it into sample blocks for CafStream.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+
+// Largest datagram accepted
+const MAX_DATAGRAM: usize = 65536;
+// Size of the sequence number header
+const SEQNUM_BYTES: usize = 8;
+// Most lost datagrams filled in with zeros
+const MAX_GAP: u64 = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SocketSpec {
+    TcpConnect(String),
+    TcpListen(String),
+    Udp(String),
+}
+
+impl SocketSpec {
+
+    // Recognise a socket in place of a file name
+    pub fn is_socket(name: &str) -> bool {
+        name.parse::<SocketSpec>().is_ok()
+    }
+
+    // Open the socket, waiting for the sender where we are the server
+    pub fn open(&self, header: UdpHeader) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            SocketSpec::TcpConnect(addr) => Box::new(TcpStream::connect(addr.as_str())?),
+            SocketSpec::TcpListen(addr) => Box::new(TcpSource::listen(addr)?.accept()?),
+            SocketSpec::Udp(addr) => Box::new(UdpSource::bind(addr, header)?),
+        })
+    }
+}
+
+impl FromStr for SocketSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, addr) = s.split_once("://")
+            .ok_or_else(|| format!("'{}' is not a socket", s))?;
+        if addr.is_empty() {
+            return Err(format!("no address in '{}'", s));
+        }
+        match scheme.to_lowercase().as_str() {
+            "tcp" => Ok(SocketSpec::TcpConnect(addr.to_string())),
+            "tcp-listen" => Ok(SocketSpec::TcpListen(addr.to_string())),
+            "udp" => Ok(SocketSpec::Udp(addr.to_string())),
+            _ => Err(format!("unknown socket type '{}'", scheme)),
+        }
+    }
+}
+
+impl fmt::Display for SocketSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SocketSpec::TcpConnect(addr) => write!(f, "tcp://{}", addr),
+            SocketSpec::TcpListen(addr) => write!(f, "tcp-listen://{}", addr),
+            SocketSpec::Udp(addr) => write!(f, "udp://{}", addr),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UdpHeader {
+    None, // payload only
+    SeqNum, // 64 bit little-endian sequence number, then payload
+}
+
+impl FromStr for UdpHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(UdpHeader::None),
+            "seqnum" | "seq" => Ok(UdpHeader::SeqNum),
+            _ => Err(format!("unknown UDP header '{}'", s)),
+        }
+    }
+}
+
+// A listening TCP socket for a sender that connects to us
+pub struct TcpSource {
+    listener: TcpListener,
+}
+
+impl TcpSource {
+
+    // Listen on addr, port 0 picks a free port
+    pub fn listen(addr: &str) -> io::Result<Self> {
+        Ok(TcpSource { listener: TcpListener::bind(addr)? })
+    }
+
+    // Where we are listening
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Wait for the sender and read from it
+    pub fn accept(self) -> io::Result<TcpStream> {
+        Ok(self.listener.accept()?.0)
+    }
+}
+
+// Datagrams as one byte stream
+pub struct UdpSource {
+    socket: UdpSocket,
+    header: UdpHeader,
+    packet: Vec<u8>,
+    start: usize, // unread part of packet
+    end: usize,
+    zeros: usize, // bytes of lost datagrams still to fill in
+    next_seq: Option<u64>,
+    lost: u64,
+    resyncs: u64,
+    done: bool,
+}
+
+impl UdpSource {
+
+    // Bind to addr, port 0 picks a free port
+    pub fn bind(addr: &str, header: UdpHeader) -> io::Result<Self> {
+        Ok(Self::from_socket(UdpSocket::bind(addr)?, header))
+    }
+
+    // Read from a socket already set up
+    pub fn from_socket(socket: UdpSocket, header: UdpHeader) -> Self {
+        UdpSource {
+            socket,
+            header,
+            packet: vec![0; MAX_DATAGRAM],
+            start: 0,
+            end: 0,
+            zeros: 0,
+            next_seq: None,
+            lost: 0,
+            resyncs: 0,
+            done: false,
+        }
+    }
+
+    // Where we are receiving
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Datagrams missing from the sequence so far
+    pub fn lost_datagrams(&self) -> u64 {
+        self.lost
+    }
+
+    // Times the sequence numbers jumped too far to fill in
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    // Wait for the next datagram with a payload, noting any gap before
+    // it. False at the end of the stream
+    fn receive(&mut self) -> io::Result<bool> {
+        loop {
+            let len = self.socket.recv(&mut self.packet)?;
+            if len == 0 {
+                return Ok(false);
+            }
+            self.start = 0;
+            self.end = len;
+            if self.header == UdpHeader::SeqNum {
+                if len < SEQNUM_BYTES {
+                    continue;
+                }
+                let mut seq = [0; SEQNUM_BYTES];
+                seq.copy_from_slice(&self.packet[..SEQNUM_BYTES]);
+                let seq = u64::from_le_bytes(seq);
+                self.start = SEQNUM_BYTES;
+
+                // Late or repeated datagrams are dropped, a few missing
+                // ones become payloads of zeros and a big jump either
+                // way resyncs
+                if let Some(next) = self.next_seq {
+                    if seq < next && next - seq <= MAX_GAP {
+                        continue;
+                    }
+                    let missing = seq.wrapping_sub(next);
+                    if missing <= MAX_GAP {
+                        self.lost += missing;
+                        self.zeros = missing as usize * (len - SEQNUM_BYTES);
+                    } else {
+                        self.resyncs += 1;
+                    }
+                }
+                self.next_seq = Some(seq.wrapping_add(1));
+            }
+            if self.start < self.end {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Read for UdpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.done {
+            return Ok(0);
+        }
+        if self.start == self.end && self.zeros == 0 && !self.receive()? {
+            self.done = true;
+            return Ok(0);
+        }
+
+        // Any gap first, then the payload
+        if self.zeros > 0 {
+            let n = self.zeros.min(buf.len());
+            buf[..n].iter_mut().for_each(|b| *b = 0);
+            self.zeros -= n;
+            return Ok(n);
+        }
+        let n = (self.end - self.start).min(buf.len());
+        buf[..n].copy_from_slice(&self.packet[self.start..self.start + n]);
+        self.start += n;
+        Ok(n)
+    }
+}
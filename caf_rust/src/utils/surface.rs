@@ -0,0 +1,320 @@
+// Native CAF surface files, for caching surfaces between runs
+// Layout (little-endian):
+//   magic        8 bytes  "CAFSURF\0"
+//   version      u16      SURFACE_FORMAT_VERSION
+//   reserved     u16      0
+//   header_len   u32      length of the JSON header
+//   header       JSON     SurfaceHeader, space padded to 8 byte alignment
+//   data         n_freqs rows of n_lags values of dtype, in header freq order
+// Rows hold every circular lag, as the backends return them, so the
+// reader rebuilds CafSurfaceRows exactly. Readers refuse files from
+// newer format versions. Source files are identified by size, how
+// their samples were decoded and a 64 bit FNV-1a hash of the bytes
+// used, so a cache can tell when its inputs changed.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use serde::{Deserialize, Serialize};
+
+use crate::caf::CafSurfaceRow;
+
+pub const SURFACE_FORMAT_VERSION: u16 = 1;
+const MAGIC: &[u8; 8] = b"CAFSURF\0";
+const PREAMBLE_LEN: usize = 16;
+const HASH_BLOCK_LEN: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SurfaceDtype {
+    #[serde(rename = "f8")]
+    F64,
+    #[serde(rename = "f4")]
+    F32, // half the size, ~7 significant digits
+}
+
+impl SurfaceDtype {
+    fn bytes(&self) -> usize {
+        match self {
+            SurfaceDtype::F64 => 8,
+            SurfaceDtype::F32 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceFile {
+    pub path: String,
+    pub bytes: u64, // file size
+    pub fnv1a64: String, // hex hash of the bytes used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<(u64, usize)>, // (first sample, count) when only part was used
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub decoding: String, // how the bytes were read as samples
+}
+
+impl SourceFile {
+
+    // Size and hash a whole file on disk
+    pub fn from_path(path: &str) -> io::Result<Self> {
+        Self::from_bytes(path, 0, u64::MAX)
+    }
+
+    // Size a file on disk and hash only len bytes of it from offset
+    pub fn from_bytes(path: &str, offset: u64, len: u64) -> io::Result<Self> {
+        let mut f = File::open(path)?;
+        let bytes = f.metadata()?.len();
+        f.seek(SeekFrom::Start(offset.min(bytes)))?;
+        let mut f = f.take(len);
+        let mut hash = FNV_OFFSET;
+        let mut buffer = vec![0; HASH_BLOCK_LEN];
+        loop {
+            let n = match f.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hash = fnv1a64_update(hash, &buffer[..n]);
+        }
+        Ok(SourceFile {
+            path: path.to_string(),
+            bytes,
+            fnv1a64: format!("{:016x}", hash),
+            range: None,
+            decoding: String::new(),
+        })
+    }
+
+    // Record that only count samples from start were used
+    pub fn with_range(mut self, start: u64, count: usize) -> Self {
+        self.range = Some((start, count));
+        self
+    }
+
+    // Record how the samples were decoded, e.g. "ci16 le" or "wav channel 1"
+    pub fn with_decoding(mut self, decoding: &str) -> Self {
+        self.decoding = decoding.to_string();
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SurfaceHeader {
+    pub fs: u32,
+    pub freqs: Vec<f64>, // Hz, one per row, ascending
+    pub n_lags: usize, // values per row
+    pub lags: (i64, i64), // signed lag range covered, inclusive
+    pub dtype: SurfaceDtype,
+    #[serde(default)]
+    pub sources: Vec<SourceFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+}
+
+impl SurfaceHeader {
+
+    // Describe a surface, stored as f64 with no sources or backend
+    pub fn new(surface: &[CafSurfaceRow], fs: u32) -> Self {
+        let mut freqs: Vec<f64> = surface.iter().map(|row| row.freq).collect();
+        freqs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n_lags = surface.first().map(|row| row.xcor_mag.len()).unwrap_or(0);
+        SurfaceHeader {
+            fs,
+            freqs,
+            n_lags,
+            lags: lag_range(n_lags),
+            dtype: SurfaceDtype::F64,
+            sources: Vec::new(),
+            backend: None,
+        }
+    }
+
+    pub fn with_dtype(mut self, dtype: SurfaceDtype) -> Self {
+        self.dtype = dtype;
+        self
+    }
+
+    pub fn with_source(mut self, source: SourceFile) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: &str) -> Self {
+        self.backend = Some(backend.to_string());
+        self
+    }
+
+    // Error unless this surface was computed at fs over freqs_hz (in
+    // any order)
+    pub fn check_search(&self, fs: u32, freqs_hz: &[f64]) -> io::Result<()> {
+        if self.fs != fs {
+            return Err(incompatible(format!("surface is at {}Hz, not {}Hz", self.fs, fs)));
+        }
+        let mut freqs = freqs_hz.to_vec();
+        freqs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        if freqs != self.freqs {
+            return Err(incompatible(format!("surface covers {} frequencies from {}Hz to {}Hz, \
+                not the {} requested", self.freqs.len(), self.freqs.first().unwrap_or(&0.0),
+                self.freqs.last().unwrap_or(&0.0), freqs.len())));
+        }
+        Ok(())
+    }
+
+    // Error unless sources match the recorded ones (same files, same
+    // contents, same ranges, in the same order)
+    pub fn check_sources(&self, sources: &[SourceFile]) -> io::Result<()> {
+        if sources.len() != self.sources.len() {
+            return Err(incompatible(format!("surface was made from {} sources, not {}",
+                self.sources.len(), sources.len())));
+        }
+        for (recorded, source) in self.sources.iter().zip(sources.iter()) {
+            if recorded != source {
+                return Err(incompatible(format!("source '{}' doesn't match the surface's '{}'",
+                    source.path, recorded.path)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Write a surface, rows in frequency order. header.freqs and n_lags
+// must describe it (SurfaceHeader::new does)
+pub fn write_surface(filename: &str, surface: &[CafSurfaceRow], header: &SurfaceHeader)
+    -> io::Result<()> {
+
+    // Rows by frequency, checked against the header
+    let mut rows: Vec<&CafSurfaceRow> = surface.iter().collect();
+    rows.sort_by(|a, b| a.freq.partial_cmp(&b.freq).unwrap());
+    let freqs: Vec<f64> = rows.iter().map(|row| row.freq).collect();
+    if freqs != header.freqs || rows.iter().any(|row| row.xcor_mag.len() != header.n_lags) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "surface doesn't match its header"));
+    }
+
+    // Header, padded so the data is aligned
+    let mut json = serde_json::to_vec(header)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let padding = (8 - (PREAMBLE_LEN + json.len()) % 8) % 8;
+    json.resize(json.len() + padding, b' ');
+
+    let mut out = Vec::with_capacity(PREAMBLE_LEN + json.len()
+        + rows.len() * header.n_lags * header.dtype.bytes());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&SURFACE_FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(&json);
+    for row in rows.iter() {
+        for v in row.xcor_mag.iter() {
+            match header.dtype {
+                SurfaceDtype::F64 => out.extend_from_slice(&v.to_le_bytes()),
+                SurfaceDtype::F32 => out.extend_from_slice(&(*v as f32).to_le_bytes()),
+            }
+        }
+    }
+    File::create(filename)?.write_all(&out)
+}
+
+// Read just the header of a surface file
+pub fn read_surface_header(filename: &str) -> io::Result<SurfaceHeader> {
+    let mut f = File::open(filename)?;
+    read_header(&mut f)
+}
+
+// Read a surface file back into rows, in frequency order
+pub fn read_surface(filename: &str) -> io::Result<(SurfaceHeader, Vec<CafSurfaceRow>)> {
+    let mut f = File::open(filename)?;
+    let header = read_header(&mut f)?;
+
+    // Exactly the data the header promises
+    let row_bytes = header.n_lags * header.dtype.bytes();
+    let mut data = Vec::with_capacity(header.freqs.len() * row_bytes);
+    f.read_to_end(&mut data)?;
+    if data.len() != header.freqs.len() * row_bytes {
+        return Err(invalid(format!("expected {} bytes of surface data, found {}",
+            header.freqs.len() * row_bytes, data.len())));
+    }
+
+    let rows = header.freqs.iter().zip(data.chunks(row_bytes.max(1)))
+        .map(|(freq, bytes)| {
+            let xcor_mag: Vec<f64> = match header.dtype {
+                SurfaceDtype::F64 => bytes.chunks(8)
+                    .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                    .collect(),
+                SurfaceDtype::F32 => bytes.chunks(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                    .collect(),
+            };
+
+            // First largest value, as the backends pick it
+            let mut max = 0.0;
+            let mut argmax = 0;
+            for (i, v) in xcor_mag.iter().enumerate() {
+                if *v > max {
+                    max = *v;
+                    argmax = i;
+                }
+            }
+            CafSurfaceRow { freq: *freq, xcor_mag, xcor_peak_idx: argmax, xcor_peak_val: max }
+        })
+        .collect();
+    Ok((header, rows))
+}
+
+// Check the preamble and parse the header, leaving f at the data
+fn read_header<R: Read>(f: &mut R) -> io::Result<SurfaceHeader> {
+    let mut preamble = [0u8; PREAMBLE_LEN];
+    f.read_exact(&mut preamble)?;
+    if &preamble[..8] != MAGIC {
+        return Err(invalid("not a CAF surface file".to_string()));
+    }
+    let version = u16::from_le_bytes([preamble[8], preamble[9]]);
+    if version == 0 || version > SURFACE_FORMAT_VERSION {
+        return Err(invalid(format!("surface file format version {} is not supported \
+            (up to {})", version, SURFACE_FORMAT_VERSION)));
+    }
+    let header_len = u32::from_le_bytes([preamble[12], preamble[13], preamble[14], preamble[15]]);
+    let mut json = vec![0; header_len as usize];
+    f.read_exact(&mut json)?;
+    let header: SurfaceHeader = serde_json::from_slice(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if header.lags != lag_range(header.n_lags) {
+        return Err(invalid(format!("lag range {:?} doesn't match {} lags",
+            header.lags, header.n_lags)));
+    }
+    Ok(header)
+}
+
+// Signed lags covered by a row of n_lags, as signed_lag maps them
+fn lag_range(n_lags: usize) -> (i64, i64) {
+    if n_lags == 0 {
+        return (0, 0);
+    }
+    (-((n_lags / 2) as i64), ((n_lags - 1) / 2) as i64)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn incompatible(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// 64 bit FNV-1a hash
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    fnv1a64_update(FNV_OFFSET, data)
+}
+
+fn fnv1a64_update(mut hash: u64, data: &[u8]) -> u64 {
+    for byte in data.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
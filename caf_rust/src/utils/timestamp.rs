@@ -0,0 +1,216 @@
+// Absolute times to the nanosecond
+// A Timestamp is whole seconds and nanoseconds since the Unix epoch,
+// UTC. f64 seconds since the epoch only resolve about 240ns today, too
+// coarse to compare receivers started a few microseconds apart, so
+// times are kept as integers and differences taken exactly. They are
+// written as ISO 8601 (SigMF's core:datetime, e.g.
+// 2026-10-19T12:00:00.000000250Z), and read from that or plain Unix
+// seconds. Digits past the nanosecond are dropped and leap seconds
+// are not counted, as in Unix time.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+const SECS_PER_DAY: i64 = 86400;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    pub secs: i64, // since 1970-01-01T00:00:00Z
+    pub nanos: u32, // 0 to 999999999
+}
+
+impl Timestamp {
+
+    // Constructor, carrying any excess nanoseconds into the seconds
+    pub fn new(secs: i64, nanos: i64) -> Self {
+        Self::from_nanos(secs as i128 * NANOS_PER_SEC + nanos as i128)
+    }
+
+    pub fn from_nanos(nanos: i128) -> Self {
+        Timestamp {
+            secs: nanos.div_euclid(NANOS_PER_SEC) as i64,
+            nanos: nanos.rem_euclid(NANOS_PER_SEC) as u32,
+        }
+    }
+
+    // Nanoseconds since the epoch
+    pub fn as_nanos(&self) -> i128 {
+        self.secs as i128 * NANOS_PER_SEC + self.nanos as i128
+    }
+
+    // A UHD/GNU Radio time, whole and fractional seconds
+    pub fn from_gr_time(whole: u64, frac: f64) -> Self {
+        Self::new(whole as i64, (frac * 1e9).round() as i64)
+    }
+
+    // This time moved on by nanos
+    pub fn add_nanos(&self, nanos: i128) -> Self {
+        Self::from_nanos(self.as_nanos() + nanos)
+    }
+
+    // The time of the sample count samples on at fs, to the nearest
+    // nanosecond
+    pub fn add_samples(&self, count: i64, fs: u32) -> Self {
+        let scaled = count as i128 * NANOS_PER_SEC;
+        let fs = fs as i128;
+        let nanos = (2 * scaled + scaled.signum() * fs) / (2 * fs);
+        self.add_nanos(nanos)
+    }
+
+    // Exact time from earlier to this, negative if earlier is later
+    pub fn nanos_since(&self, earlier: &Timestamp) -> i128 {
+        self.as_nanos() - earlier.as_nanos()
+    }
+
+    // Whole seconds and the rest kept apart so short spans stay exact
+    pub fn seconds_since(&self, earlier: &Timestamp) -> f64 {
+        let nanos = self.nanos_since(earlier);
+        (nanos / NANOS_PER_SEC) as f64 + (nanos % NANOS_PER_SEC) as f64 / 1e9
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    // ISO 8601 date and time, UTC unless it has an offset, or Unix
+    // seconds with up to 9 decimals
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let bad = || format!("'{}' is not an ISO 8601 time or Unix seconds", s);
+        if !s.contains(&['T', 't', ' ', ':'][..]) {
+            let (negative, digits) = match s.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, s.strip_prefix('+').unwrap_or(s)),
+            };
+            let nanos = decimal_nanos(digits).ok_or_else(bad)?;
+            return Ok(Self::from_nanos(if negative { -nanos } else { nanos }));
+        }
+
+        // Date, then time, then an offset from UTC
+        let (date, time) = s.split_once(&['T', 't', ' '][..]).ok_or_else(bad)?;
+        let date: Vec<&str> = date.splitn(3, '-').collect();
+        if date.len() != 3 {
+            return Err(bad());
+        }
+        let year = number(date[0]).ok_or_else(bad)?;
+        let month = number(date[1]).filter(|m| (1..=12).contains(m)).ok_or_else(bad)?;
+        let day = number(date[2])
+            .filter(|d| *d >= 1 && *d <= days_in_month(year, month))
+            .ok_or_else(bad)?;
+
+        let (time, offset_s) = match time.find(&['Z', 'z', '+', '-'][..]) {
+            Some(pos) => (&time[..pos], utc_offset(&time[pos..]).ok_or_else(bad)?),
+            None => (time, 0),
+        };
+        let time: Vec<&str> = time.splitn(3, ':').collect();
+        if time.len() != 3 {
+            return Err(bad());
+        }
+        let hour = number(time[0]).filter(|h| *h < 24).ok_or_else(bad)?;
+        let minute = number(time[1]).filter(|m| *m < 60).ok_or_else(bad)?;
+        let second = decimal_nanos(time[2]).filter(|n| *n < 61 * NANOS_PER_SEC).ok_or_else(bad)?;
+
+        let secs = days_from_civil(year, month, day) * SECS_PER_DAY
+            + hour * 3600 + minute * 60 - offset_s;
+        Ok(Self::new(secs, 0).add_nanos(second))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.secs.div_euclid(SECS_PER_DAY));
+        let secs = self.secs.rem_euclid(SECS_PER_DAY);
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z", year, month, day,
+            secs / 3600, secs / 60 % 60, secs % 60, self.nanos)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+// Digits only, no sign
+fn number(s: &str) -> Option<i64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// Nanoseconds in a decimal number of seconds, exactly
+fn decimal_nanos(s: &str) -> Option<i128> {
+    let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+    if whole.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let whole = if whole.is_empty() { 0 } else { number(whole)? };
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac: String = frac.chars().chain(std::iter::repeat('0')).take(9).collect();
+    Some(whole as i128 * NANOS_PER_SEC + frac.parse::<i128>().ok()?)
+}
+
+// Seconds east of UTC in "Z", "+hh:mm", "+hhmm" or "+hh"
+fn utc_offset(s: &str) -> Option<i64> {
+    if s.eq_ignore_ascii_case("z") {
+        return Some(0);
+    }
+    let sign = if s.starts_with('-') { -1 } else { 1 };
+    let digits = s[1..].replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        2 => (number(&digits)?, 0),
+        4 => (number(&digits[..2])?, number(&digits[2..])?),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// (year, month, day) of a count of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // from March
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
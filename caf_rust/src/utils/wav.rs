@@ -0,0 +1,132 @@
+// WAV (RIFF) reader for real-valued audio and IF captures
+// Handles PCM 8/16/24/32 bit and IEEE float 32/64 bit, plain or
+// WAVE_FORMAT_EXTENSIBLE, any number of channels. Integer samples
+// are scaled to roughly [-1, 1) like the IQ formats (8 bit is
+// offset binary, the rest two's complement). Chunks other than
+// "fmt " and "data" are skipped.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use num_complex::Complex64;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wav {
+    pub fs: u32,
+    pub channels: Vec<Vec<f64>>, // one Vec per channel, deinterleaved
+}
+
+impl Wav {
+
+    // Number of samples per channel
+    pub fn len(&self) -> usize {
+        self.channels.first().map(|c| c.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Channels 0 and 1 as I and Q, for stereo IQ recordings
+    pub fn iq(&self) -> io::Result<Vec<Complex64>> {
+        if self.channels.len() != 2 {
+            return Err(invalid(format!("IQ needs 2 channels, found {}", self.channels.len())));
+        }
+        Ok(self.channels[0].iter().zip(self.channels[1].iter())
+            .map(|(i, q)| Complex64::new(*i, *q))
+            .collect())
+    }
+}
+
+// Read a whole WAV file
+pub fn read_wav(filename: &str) -> io::Result<Wav> {
+    let mut bytes = Vec::new();
+    File::open(filename)?.read_to_end(&mut bytes)?;
+    parse_wav(&bytes)
+}
+
+// Parse WAV file contents
+pub fn parse_wav(bytes: &[u8]) -> io::Result<Wav> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF WAVE file".to_string()));
+    }
+
+    // Walk the chunks, each padded to an even length
+    let mut format = None;
+    let mut data = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32_le(&bytes[pos + 4..]) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " => format = Some(parse_format(body)?),
+            b"data" => data = Some(body),
+            _ => {},
+        }
+        pos += 8 + len + len % 2;
+    }
+    let (tag, channels, fs, bits) = format.ok_or_else(|| invalid("no fmt chunk".to_string()))?;
+    let data = data.ok_or_else(|| invalid("no data chunk".to_string()))?;
+
+    // Decoder for one sample
+    let decode: fn(&[u8]) -> f64 = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => |b| (b[0] as f64 - 128.0) / 128.0,
+        (WAVE_FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768.0,
+        (WAVE_FORMAT_PCM, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64
+            / 8388608.0,
+        (WAVE_FORMAT_PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+            / 2147483648.0,
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => |b| f64::from_le_bytes([b[0], b[1], b[2], b[3],
+            b[4], b[5], b[6], b[7]]),
+        _ => return Err(invalid(format!("unsupported WAV encoding {} at {} bits", tag, bits))),
+    };
+
+    // Deinterleave whole frames, ignoring any partial one at the end
+    let sample_bytes = bits as usize / 8;
+    let frame_bytes = sample_bytes * channels;
+    let frames = data.len() / frame_bytes;
+    let mut out = vec![Vec::with_capacity(frames); channels];
+    for frame in data.chunks_exact(frame_bytes) {
+        for (channel, sample) in out.iter_mut().zip(frame.chunks_exact(sample_bytes)) {
+            channel.push(decode(sample));
+        }
+    }
+    Ok(Wav { fs, channels: out })
+}
+
+// (format tag, channels, sample rate, bits per sample) from a fmt
+// chunk, looking through WAVE_FORMAT_EXTENSIBLE to its sub-format
+fn parse_format(body: &[u8]) -> io::Result<(u16, usize, u32, u16)> {
+    if body.len() < 16 {
+        return Err(invalid("short fmt chunk".to_string()));
+    }
+    let mut tag = u16::from_le_bytes([body[0], body[1]]);
+    let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+    let fs = u32_le(&body[4..]);
+    let bits = u16::from_le_bytes([body[14], body[15]]);
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if body.len() < 26 {
+            return Err(invalid("short extensible fmt chunk".to_string()));
+        }
+        tag = u16::from_le_bytes([body[24], body[25]]);
+    }
+    if channels == 0 || bits == 0 || !bits.is_multiple_of(8) {
+        return Err(invalid(format!("bad WAV format, {} channels of {} bits", channels, bits)));
+    }
+    Ok((tag, channels, fs, bits))
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
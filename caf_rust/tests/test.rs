@@ -7,9 +7,14 @@ extern crate caf_rust;
 #[cfg(test)]
 mod tests {
 
-    use num_complex::Complex64;
+    use std::f64::consts::PI;
+    use std::io::prelude::*;
+
+    use num_complex::{Complex32, Complex64};
     use caf_rust::caf::*;
-    use caf_rust::utils::read_file_c64;
+    use caf_rust::geo::*;
+    use caf_rust::render::*;
+    use caf_rust::utils::*;
 
     #[test]
     fn test_rustfft_chirp0() {
@@ -315,6 +320,1603 @@ mod tests {
         assert_eq!(samp_idx, 176);
     }
 
+    #[test]
+    fn test_nco_long_run_error() {
+        // Dyadic increment (1234.5 / 65536) so the exact phase
+        // at every sample index is representable in f64
+        let freq = 1234.5;
+        let fs = 65536;
+        let phase_inc = freq / (fs as f64);
+
+        // Mix 10^8 samples of ones in blocks, checking the result
+        // against the exact phase along the way
+        let block_len = 1 << 20;
+        let mut nco = Nco::new(freq, fs);
+        let mut nco32 = Nco::new(freq, fs);
+        let mut block = vec![Complex64::new(1.0, 0.0); block_len];
+        let mut block32 = vec![Complex32::new(1.0, 0.0); block_len];
+        let mut max_mag_err: f64 = 0.0;
+        let mut max_phase_err: f64 = 0.0;
+        let mut max_err32: f32 = 0.0;
+        let mut n: u64 = 0;
+        while n < 100_000_000 {
+            for samp in block.iter_mut() {
+                *samp = Complex64::new(1.0, 0.0);
+            }
+            for samp in block32.iter_mut() {
+                *samp = Complex32::new(1.0, 0.0);
+            }
+            nco.mix(&mut block);
+            nco32.mix_c32(&mut block32);
+            for i in (0..block_len).step_by(997).chain(Some(block_len - 1)) {
+                let cycles = ((n + i as u64) as f64) * phase_inc;
+                let exact = Complex64::from_polar(&1.0,
+                    &(2.0 * PI * (cycles - cycles.floor())));
+                max_mag_err = max_mag_err.max((block[i].norm() - 1.0).abs());
+                max_phase_err = max_phase_err.max((block[i] * exact.conj()).arg().abs());
+                let exact32 = Complex32::new(exact.re as f32, exact.im as f32);
+                max_err32 = max_err32.max((block32[i] - exact32).norm());
+            }
+            n += block_len as u64;
+        }
+
+        // Confirm no drift in magnitude or phase
+        assert!(max_mag_err < 1e-12, "magnitude error {}", max_mag_err);
+        assert!(max_phase_err < 1e-9, "phase error {}", max_phase_err);
+        assert!(max_err32 < 1e-6, "f32 error {}", max_err32);
+
+        // Non-dyadic 1000.1Hz at 48kHz, checked against the exact phase
+        // from integers: n * 10001 / 480000 cycles. The increment itself
+        // is only good to ~1e-18 cycles, which 10^8 samples grow to ~1e-9
+        // radians, so anything more is drift
+        let mut nco = Nco::new(1000.1, 48000);
+        let mut max_phase_err: f64 = 0.0;
+        let mut n: u64 = 0;
+        while n < 100_000_000 {
+            for samp in block.iter_mut() {
+                *samp = Complex64::new(1.0, 0.0);
+            }
+            nco.mix(&mut block);
+            for i in (0..block_len).step_by(997).chain(Some(block_len - 1)) {
+                let cycles = ((n + i as u64) * 10001 % 480000) as f64 / 480000.0;
+                let exact = Complex64::from_polar(&1.0, &(2.0 * PI * cycles));
+                max_phase_err = max_phase_err.max((block[i] * exact.conj()).arg().abs());
+            }
+            n += block_len as u64;
+        }
+        assert!(max_phase_err < 5e-9, "non-dyadic phase error {}", max_phase_err);
+    }
+
+    #[test]
+    fn test_rustfft_drift_synthetic() {
+        // Noise needle, delayed and given an accelerating offset
+        let fs = 4000;
+        let needle = gen_noise(1024, 1);
+        let haystack = gen_haystack(&needle, 37, 20.0, 400.0, fs);
+
+        // -50Hz to 50Hz at 2Hz, -800Hz/s to 800Hz/s at 200Hz/s
+        let shifts = gen_float_shifts(-50.0, 50.0, 2.0);
+        let rates = gen_float_shifts(-800.0, 801.0, 200.0);
+
+        // Get the CAF volume estimates
+        let volume = CafRustFFT::caf_volume(&needle, &haystack, &shifts, &rates, fs);
+        let (freq, rate, samp_idx) = CafRustFFT::find_peak_drift(volume);
+
+        // Confirm correct results. The offset is referenced to the
+        // start of the needle, which sees the drift 37 samples late
+        assert_eq!(rate, 400.0);
+        assert_eq!(samp_idx, 37);
+        assert!((freq - (20.0 + 400.0 * 37.0 / 4000.0)).abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_rustfft_wideband_synthetic() {
+        // Sum of tones under a Hann envelope, so the time-scaled
+        // haystack can be evaluated exactly at any instant
+        let len = 1024;
+        let tones: Vec<(f64, f64)> = gen_noise(48, 7).iter()
+            .map(|x| (0.35 * x.re, PI * x.im)).collect();
+        let signal = |t: f64| {
+            if t <= 0.0 || t >= len as f64 {
+                return Complex64::new(0.0, 0.0);
+            }
+            let env = (PI * t / (len as f64)).sin().powi(2);
+            tones.iter().map(|&(f, p)| Complex64::from_polar(&env, &(2.0 * PI * f * t + p)))
+                .fold(Complex64::new(0.0, 0.0), |acc, x| acc + x)
+        };
+        let needle: Vec<Complex64> = (0..len).map(|n| signal(n as f64)).collect();
+        let haystack: Vec<Complex64> = (0..len)
+            .map(|n| signal(1.002 * (n as f64 - 25.0))).collect();
+
+        // 0.996 to 1.004 time scale, no frequency offset
+        let scales: Vec<f64> = (0..9).map(|i| 0.996 + 0.001 * (i as f64)).collect();
+        let volume = CafRustFFT::caf_wideband(&needle, &haystack, &[0.0], &scales, 48000);
+        let (freq, scale, samp_idx) = CafRustFFT::find_peak_wideband(volume);
+
+        // Confirm correct results
+        assert_eq!(freq, 0.0);
+        assert!((scale - 1.002).abs() < 1e-9);
+        assert_eq!(samp_idx, 25);
+    }
+
+    #[test]
+    fn test_resampler_unity() {
+        // A scale of 1 should pass band-limited samples through
+        let needle = gen_noise(256, 3);
+        let resampled = Resampler::new(1.0).resample(&needle);
+        for (a, b) in needle.iter().zip(resampled.iter()).skip(32).take(192) {
+            assert!((a - b).norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_gcc_weightings_synthetic() {
+        // Colored noise with an echo 15 samples behind the direct path
+        let fs = 48000;
+        let white = gen_noise(1024 + 8, 11);
+        let needle: Vec<Complex64> = white.windows(8)
+            .map(|w| w.iter().sum::<Complex64>() / 8.0).take(1024).collect();
+        let direct = gen_haystack(&needle, 60, 0.0, 0.0, fs);
+        let echo = gen_haystack(&needle, 75, 0.0, 0.0, fs);
+        let haystack: Vec<Complex64> = direct.iter().zip(echo.iter())
+            .map(|(d, e)| d + 0.5 * e).collect();
+
+        // Every weighting on both engines should find the direct path
+        let weightings = [Weighting::None, Weighting::Phat, Weighting::Scot,
+            Weighting::Roth, Weighting::Eckart, Weighting::Ml];
+        for weighting in weightings.iter() {
+            let surface = CafRustFFT::caf_surface_weighted(
+                &needle, &haystack, &[0.0], fs, *weighting);
+            let (_, samp_idx) = CafRustFFT::find_peak(surface);
+            assert_eq!(samp_idx, 60, "RustFFT {:?}", weighting);
+
+            let surface = CafFFTW::caf_surface_weighted(
+                &needle, &haystack, &[0.0], fs, *weighting);
+            let (_, samp_idx) = CafFFTW::find_peak(surface);
+            assert_eq!(samp_idx, 60, "FFTW {:?}", weighting);
+        }
+
+        // PHAT should sharpen the peak relative to its neighbours
+        let sharpness = |weighting| {
+            let surface = CafRustFFT::caf_surface_weighted(
+                &needle, &haystack, &[0.0], fs, weighting);
+            surface[0].xcor_mag[60] / surface[0].xcor_mag[62]
+        };
+        assert!(sharpness(Weighting::Phat) > 10.0 * sharpness(Weighting::None));
+
+        // SCOT whitens by smoothed auto-spectra, so on coloured noise it
+        // is not PHAT scaled: their sidelobes differ
+        let normalized_db = |weighting| {
+            let surface = CafRustFFT::caf_surface_weighted(
+                &needle, &haystack, &[0.0], fs, weighting);
+            let row = &surface[0];
+            row.xcor_mag.iter().map(|v| 10.0 * (v / row.xcor_peak_val).log10())
+                .collect::<Vec<f64>>()
+        };
+        let (scot, phat) = (normalized_db(Weighting::Scot), normalized_db(Weighting::Phat));
+        let mean_db = scot.iter().zip(phat.iter())
+            .map(|(s, p)| (s - p).abs())
+            .sum::<f64>() / scot.len() as f64;
+        assert!(mean_db > 1.0);
+    }
+
+    #[test]
+    fn test_gcc_weights_known_coherence() {
+        // Over any 9 bins a common part plus a tone at a different
+        // rate in each: smoothed Gab = 1/n, Gaa = Gbb = 2/n, so the
+        // coherence is exactly 1/4
+        let n = 36;
+        let tone = |k: usize, cycles: usize| Complex64::from_polar(
+            &1.0, &(2.0 * PI * (cycles * k) as f64 / 9.0));
+        let fa: Vec<Complex64> = (0..n).map(|k| 1.0 + tone(k, 1)).collect();
+        let fb: Vec<Complex64> = (0..n).map(|k| 1.0 + tone(k, 2)).collect();
+        let cross: Vec<Complex64> = fa.iter().zip(fb.iter())
+            .map(|(a, b)| a * b.conj() / n as f64).collect();
+        let weights = |weighting: Weighting| {
+            let mut weighted = cross.clone();
+            weighting.apply(&mut weighted, &fa, &fb);
+            weighted.iter().zip(cross.iter()).map(|(w, c)| (w / c).re).collect::<Vec<f64>>()
+        };
+        let n = n as f64;
+        let expect = |weighting, expected: &dyn Fn(usize) -> f64| {
+            for (k, w) in weights(weighting).iter().enumerate() {
+                assert!((w / expected(k) - 1.0).abs() < 1e-9, "{:?} bin {}", weighting, k);
+            }
+        };
+        expect(Weighting::Phat, &|k| 1.0 / cross[k].norm());
+        expect(Weighting::Scot, &|_| n / 2.0); // 1 / sqrt(Gaa * Gbb)
+        expect(Weighting::Roth, &|k| n / fa[k].norm_sqr()); // unsmoothed
+        expect(Weighting::Eckart, &|_| n); // |Gab| / ((Gaa - |Gab|) * (Gbb - |Gab|))
+        expect(Weighting::Ml, &|_| n / 3.0); // 1/4 / (|Gab| * 3/4)
+    }
+
+    #[test]
+    fn test_engine_bandpass_synthetic() {
+        // Signal of interest within +/-2kHz, delayed 50 samples
+        let fs = 48000;
+        let lowpass = Fir::lowpass(2000.0, fs, 101, Window::Blackman);
+        let needle = lowpass.filter(&gen_noise(2048, 5));
+        let haystack = gen_haystack(&needle, 50, 0.0, 0.0, fs);
+
+        // Much stronger interferer around 12kHz, common to both
+        // captures with no delay
+        let interferer = Fir::bandpass(10000.0, 14000.0, fs, 101, Window::Blackman)
+            .filter(&gen_noise(2048, 6));
+        let needle: Vec<Complex64> = needle.iter().zip(interferer.iter())
+            .map(|(s, i)| s + 10.0 * i).collect();
+        let haystack: Vec<Complex64> = haystack.iter().zip(interferer.iter())
+            .map(|(s, i)| s + 10.0 * i).collect();
+
+        // Unfiltered, the interferer wins
+        let engine: CafEngine<CafRustFFT> = CafEngine::new();
+        let (_, samp_idx) = engine.find_peak(engine.caf_surface(&needle, &haystack, &[0.0], fs));
+        assert_eq!(samp_idx, 0);
+
+        // Band-limited and tapered, the signal of interest wins
+        let engine: CafEngine<CafRustFFT> = CafEngine::new()
+            .with_bandpass(Fir::kaiser_bandpass(-3000.0, 3000.0, fs, 1000.0, 60.0))
+            .with_window(Window::Hann);
+        let (_, samp_idx) = engine.find_peak(engine.caf_surface(&needle, &haystack, &[0.0], fs));
+        assert_eq!(samp_idx, 50);
+    }
+
+    #[test]
+    fn test_window_coefficients() {
+        // Symmetric with the expected end points
+        for window in [Window::Hann, Window::Hamming, Window::Blackman,
+            Window::Kaiser(8.6)].iter() {
+            let w = window.coefficients(65);
+            assert!((w[32] - 1.0).abs() < 1e-12, "{:?}", window);
+            for i in 0..65 {
+                assert!((w[i] - w[64 - i]).abs() < 1e-12, "{:?}", window);
+            }
+        }
+        assert!(Window::Hann.coefficients(65)[0].abs() < 1e-12);
+        assert!((Window::Hamming.coefficients(65)[0] - 0.08).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sample_format_round_trip() {
+        // Stay inside the integer formats' range and resolution
+        let samples: Vec<Complex64> = gen_noise(1000, 13).iter()
+            .map(|x| x * 0.9).collect();
+        let formats = [(SampleFormat::Ci8, 1.0 / 128.0),
+            (SampleFormat::Cu8, 1.0 / 128.0),
+            (SampleFormat::Ci16, 1.0 / 32768.0),
+            (SampleFormat::Cf32, 1e-7),
+            (SampleFormat::Cf64, 0.0)];
+        for (format, tol) in formats.iter() {
+            for endian in [Endian::Little, Endian::Big].iter() {
+                let filename = temp_filename(&format!("round_trip_{}_{:?}", format, endian));
+                write_file(&filename, &samples, *format, *endian).unwrap();
+                let read = read_file(&filename, *format, *endian).unwrap();
+                std::fs::remove_file(&filename).unwrap();
+
+                // Same length, and within half a quantization step
+                assert_eq!(read.len(), samples.len());
+                for (a, b) in samples.iter().zip(read.iter()) {
+                    assert!((a.re - b.re).abs() <= 0.5 * tol, "{} {:?}", format, endian);
+                    assert!((a.im - b.im).abs() <= 0.5 * tol, "{} {:?}", format, endian);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sample_format_scaling() {
+        // Known byte patterns for each integer format
+        let filename = temp_filename("scaling");
+        std::fs::write(&filename, [0x80u8, 0x7f]).unwrap();
+        assert_eq!(read_file(&filename, SampleFormat::Ci8, Endian::Little).unwrap(),
+            vec![Complex64::new(-1.0, 127.0 / 128.0)]);
+        assert_eq!(read_file(&filename, SampleFormat::Cu8, Endian::Little).unwrap(),
+            vec![Complex64::new(0.5 / 128.0, -0.5 / 128.0)]);
+        std::fs::write(&filename, [0x40u8, 0x00, 0x00, 0x40]).unwrap();
+        assert_eq!(read_file(&filename, SampleFormat::Ci16, Endian::Big).unwrap(),
+            vec![Complex64::new(0.5, 64.0 / 32768.0)]);
+        std::fs::remove_file(&filename).unwrap();
+
+        // Format names, including SDR aliases
+        assert_eq!("sc16".parse::<SampleFormat>().unwrap(), SampleFormat::Ci16);
+        assert_eq!("c64".parse::<SampleFormat>().unwrap(), SampleFormat::Cf32);
+        assert_eq!("BE".parse::<Endian>().unwrap(), Endian::Big);
+        assert!("cf16".parse::<SampleFormat>().is_err());
+    }
+
+    #[test]
+    fn test_sigmf_round_trip() {
+        // Write a ci16 big-endian recording with an unknown extension field
+        let base = temp_filename("sigmf_round_trip");
+        let (meta_path, data_path) = sigmf_paths(&format!("{}.sigmf-data", base));
+        let samples: Vec<Complex64> = gen_noise(64, 17).iter().map(|x| x * 0.5).collect();
+        write_file(&data_path.to_string_lossy(), &samples, SampleFormat::Ci16, Endian::Big).unwrap();
+        std::fs::write(&meta_path, r#"{
+            "global": {
+                "core:datatype": "ci16_be",
+                "core:sample_rate": 2400000.0,
+                "core:version": "1.0.0",
+                "vendor:gain": 42
+            },
+            "captures": [{
+                "core:sample_start": 0,
+                "core:frequency": 915000000.0,
+                "core:datetime": "2020-01-01T00:00:00.000001Z"
+            }],
+            "annotations": []
+        }"#).unwrap();
+
+        // Metadata and samples come back out
+        let (mut meta, read) = read_sigmf(&base).unwrap();
+        assert_eq!(meta.sample_rate(), Some(2400000.0));
+        assert_eq!(meta.frequency(), Some(915000000.0));
+        assert_eq!(meta.datetime(), Some("2020-01-01T00:00:00.000001Z"));
+        assert_eq!(meta.sample_format().unwrap(), (SampleFormat::Ci16, Endian::Big));
+        assert_eq!(read.len(), samples.len());
+        assert!((read[3] - samples[3]).norm() < 1e-4);
+
+        // Annotate a peak and make sure nothing else was lost
+        meta.annotate_caf_peak(202, 4096, 69.25, 0.5, "caf_peak");
+        meta.write(&meta_path.to_string_lossy()).unwrap();
+        let meta = SigMFMeta::read(&meta_path.to_string_lossy()).unwrap();
+        assert_eq!(meta.global.extra["vendor:gain"], 42);
+        assert_eq!(meta.annotations.len(), 1);
+        assert_eq!(meta.annotations[0].sample_start, 202);
+        assert_eq!(meta.annotations[0].sample_count, Some(4096));
+        assert_eq!(meta.annotations[0].freq_lower_edge, Some(915000069.0));
+        assert_eq!(meta.annotations[0].freq_upper_edge, Some(915000069.5));
+        std::fs::remove_file(&meta_path).unwrap();
+        std::fs::remove_file(&data_path).unwrap();
+
+        // Only complex datatypes are supported
+        assert!(parse_datatype("rf32_le").is_err());
+        assert_eq!(parse_datatype("cu8").unwrap(), (SampleFormat::Cu8, Endian::Little));
+
+        // Spec names only, with a byte order wherever it matters
+        for datatype in ["c64", "i8", "sc16_le", "fc32_be", "cf32", "ci8_le", "cf32_xe"].iter() {
+            assert!(parse_datatype(datatype).is_err(), "{}", datatype);
+        }
+    }
+
+    #[test]
+    fn test_mapped_and_chunked_readers() {
+        // A ci16 capture with a trailing partial sample
+        let filename = temp_filename("mapped");
+        let samples: Vec<Complex64> = gen_noise(1000, 19).iter().map(|x| x * 0.5).collect();
+        write_file(&filename, &samples, SampleFormat::Ci16, Endian::Little).unwrap();
+        let mut f = std::fs::OpenOptions::new().append(true).open(&filename).unwrap();
+        std::io::Write::write_all(&mut f, &[0x12, 0x34]).unwrap();
+        let whole = read_file(&filename, SampleFormat::Ci16, Endian::Little).unwrap();
+
+        // Windows by offset/length, clipped at the end
+        let capture = MappedCapture::open(&filename, SampleFormat::Ci16, Endian::Little).unwrap();
+        assert_eq!(capture.len(), 1000);
+        assert_eq!(capture.window(100, 50), &whole[100..150]);
+        assert_eq!(capture.window(990, 50), &whole[990..]);
+        assert!(capture.window(2000, 50).is_empty());
+
+        // Overlapping blocks cover everything
+        let blocks: Vec<(usize, Vec<Complex64>)> = capture.blocks(300, 100).collect();
+        assert_eq!(blocks.iter().map(|b| b.0).collect::<Vec<usize>>(), vec![0, 200, 400, 600, 800]);
+        assert_eq!(blocks[1].1, &whole[200..500]);
+        assert_eq!(blocks[4].1, &whole[800..]);
+
+        // Chunked blocks concatenate back to the whole file
+        let f = std::fs::File::open(&filename).unwrap();
+        let chunks: Vec<Vec<Complex64>> = ChunkedReader::new(f, SampleFormat::Ci16, Endian::Little, 256)
+            .map(|c| c.unwrap()).collect();
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<usize>>(), vec![256, 256, 256, 232]);
+        assert_eq!(chunks.concat(), whole);
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_read_ranges() {
+        // 1s of cf64 at 1kHz
+        let filename = temp_filename("ranges");
+        let samples = gen_noise(1000, 23);
+        write_file(&filename, &samples, SampleFormat::Cf64, Endian::Little).unwrap();
+
+        // By sample offset and by time
+        let window = read_range(&filename, SampleFormat::Cf64, Endian::Little, 250, 100).unwrap();
+        assert_eq!(window, &samples[250..350]);
+        let window = read_time_range(&filename, SampleFormat::Cf64, Endian::Little,
+            1000, 0.5, 0.1).unwrap();
+        assert_eq!(window, &samples[500..600]);
+
+        // Exactly the window or an error, never a short read
+        assert!(read_range(&filename, SampleFormat::Cf64, Endian::Little, 950, 100).is_err());
+        let huge = read_range(&filename, SampleFormat::Cf64, Endian::Little, 0, usize::MAX / 2);
+        assert_eq!(huge.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(read_range(&filename, SampleFormat::Cf64, Endian::Little, u64::MAX / 4, 1).is_err());
+        std::fs::remove_file(&filename).unwrap();
+
+        // Range strings
+        let range: SampleRange = "0.5s..0.6s".parse().unwrap();
+        assert_eq!(range.resolve(48000), (24000, 4800));
+        let range: SampleRange = "1000+4096".parse().unwrap();
+        assert_eq!(range.resolve(48000), (1000, 4096));
+        let range: SampleRange = "0.25s+0.5s".parse().unwrap();
+        assert_eq!(range.resolve(1000), (250, 500));
+        assert!("0.5s+100".parse::<SampleRange>().is_err());
+        assert!("100".parse::<SampleRange>().is_err());
+        assert!(format!("1+{}", u64::MAX).parse::<SampleRange>().is_err());
+        assert!("1e308s+1e308s".parse::<SampleRange>().is_err());
+        assert!("5000..1000".parse::<SampleRange>().is_err());
+        assert!("0.6s..0.5s".parse::<SampleRange>().is_err());
+        assert!("1000..1000".parse::<SampleRange>().is_err());
+        assert!("0.5s..30000".parse::<SampleRange>().is_err());
+    }
+
+    #[test]
+    fn test_numpy_export() {
+        // Small surface, out of frequency order like the threaded backends
+        let fs = 48000;
+        let needle = gen_noise(64, 29);
+        let haystack = gen_haystack(&needle, 5, 0.0, 0.0, fs);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &[10.0, -10.0, 0.0], fs);
+
+        // .npy header describes the 3x128 array and data is 64-byte aligned
+        let filename = temp_filename("surface.npy");
+        surface.write_npy(&filename).unwrap();
+        let bytes = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (3, 128), }"));
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(bytes.len(), 10 + header_len + 3 * 128 * 8);
+
+        // First row is the lowest frequency
+        let rows: Vec<&CafSurfaceRow> = surface.iter().filter(|r| r.freq == -10.0).collect();
+        let first = f64::from_le_bytes([bytes[10 + header_len], bytes[11 + header_len],
+            bytes[12 + header_len], bytes[13 + header_len], bytes[14 + header_len],
+            bytes[15 + header_len], bytes[16 + header_len], bytes[17 + header_len]]);
+        assert_eq!(first, rows[0].xcor_mag[0]);
+
+        // .npz holds the three arrays, stored with valid CRCs
+        let filename = temp_filename("surface.npz");
+        surface.write_npz(&filename).unwrap();
+        let bytes = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(&bytes[..4], &[0x50, 0x4b, 0x03, 0x04]);
+        for name in ["surface.npy", "freqs.npy", "lags.npy"].iter() {
+            assert!(bytes.windows(name.len()).any(|w| w == name.as_bytes()));
+        }
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        // Past what a zip without zip64 can index, nothing is written
+        let names: Vec<String> = (0..65536).map(|k| format!("a{}", k)).collect();
+        let arrays: Vec<(&str, Vec<u8>)> = names.iter()
+            .map(|name| (name.as_str(), Vec::new()))
+            .collect();
+        assert!(write_npz(&filename, &arrays).is_err());
+        assert!(write_npz(&filename, &arrays[1..]).is_ok());
+        std::fs::remove_file(&filename).unwrap();
+
+        // Lag axis wraps to negative lags half way along
+        assert_eq!(signed_lag(5, 128), 5);
+        assert_eq!(signed_lag(64, 128), -64);
+        assert_eq!(signed_lag(127, 128), -1);
+    }
+
+    #[test]
+    fn test_mat_export() {
+        let fs = 48000;
+        let needle = gen_noise(64, 29);
+        let haystack = gen_haystack(&needle, 5, 0.0, 0.0, fs);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &[10.0, -10.0, 0.0], fs);
+        let mut result = CafResult::from_surface(&surface, fs, 2);
+        result.backend = Some("CafRustFFTThreads".to_string());
+
+        // Header, version and endianness marker
+        let filename = temp_filename("surface.mat");
+        surface.write_mat(&filename, fs, Some(&result)).unwrap();
+        let bytes = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert!(bytes.starts_with(b"MATLAB 5.0 MAT-file"));
+        assert_eq!(&bytes[124..128], &[0x00, 0x01, b'I', b'M']);
+
+        // Variables are miMATRIX elements, 8 byte aligned, in order
+        let mut names = Vec::new();
+        let mut pos = 128;
+        while pos < bytes.len() {
+            assert_eq!(u32_at(&bytes, pos), 14);
+            let len = u32_at(&bytes, pos + 4) as usize;
+            assert_eq!(len % 8, 0);
+            let name_len = u32_at(&bytes, pos + 44) as usize;
+            names.push(String::from_utf8(bytes[pos + 48..pos + 48 + name_len].to_vec()).unwrap());
+            pos += 8 + len;
+        }
+        assert_eq!(pos, bytes.len());
+        assert_eq!(names, ["surface", "freqs", "lags", "fs", "peaks", "backend"]);
+
+        // Surface is 3x128, column-major with rows by frequency
+        assert_eq!((u32_at(&bytes, 128 + 32), u32_at(&bytes, 128 + 32 + 4)), (3, 128));
+        let vars = surface_mat_vars(&surface);
+        let lowest = surface.iter().find(|r| r.freq == -10.0).unwrap();
+        let zero = surface.iter().find(|r| r.freq == 0.0).unwrap();
+        match &vars[0].1 {
+            MatVar::Double { rows, cols, data } => {
+                assert_eq!((*rows, *cols), (3, 128));
+                assert_eq!(data[0], lowest.xcor_mag[0]);
+                assert_eq!(data[1], zero.xcor_mag[0]);
+                assert_eq!(data[3], lowest.xcor_mag[1]);
+            },
+            _ => panic!("surface isn't a double matrix"),
+        }
+        assert_eq!(vars[2].1, MatVar::row(&(0..128)
+            .map(|i| signed_lag(i, 128) as f64).collect::<Vec<f64>>()));
+
+        // Peaks struct has one column per field
+        match peaks_mat_var(&result) {
+            MatVar::Struct(fields) => {
+                assert_eq!(fields[0].0, "freq_hz");
+                assert_eq!(fields[1].1, MatVar::column(&[5.0, result.peaks[1].lag_samples as f64]));
+            },
+            _ => panic!("peaks isn't a struct"),
+        }
+
+        // Names MATLAB can't use are refused
+        assert!(write_mat(&filename, &[("2d", MatVar::scalar(1.0))]).is_err());
+        assert_eq!(mat_bytes(&[("fs", MatVar::scalar(1.0))]).unwrap().len(), 128 + 72);
+
+        // So are dimensions past the 32 bit fields
+        let tall = MatVar::Double { rows: 1 << 31, cols: 0, data: vec![] };
+        let err = mat_bytes(&[("tall", tall)]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_render_heatmap() {
+        // Delayed, shifted copy of noise in a small surface
+        let fs = 48000;
+        let needle = gen_noise(256, 31);
+        let haystack = gen_haystack(&needle, 12, 250.0, 0.0, fs);
+        let freqs = gen_float_shifts(-500.0, 500.0, 50.0);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &freqs, fs);
+
+        // Axes are sorted and the peak lands on the right cell
+        let heatmap = Heatmap::new(&surface, fs).unwrap().with_size(200, 100);
+        assert_eq!(heatmap.freqs()[0], -500.0);
+        assert_eq!(heatmap.lags()[0], -256);
+        assert_eq!(heatmap.lags()[511], 255);
+        let (row, col) = heatmap.peak();
+        assert_eq!(heatmap.freqs()[row], 250.0);
+        assert_eq!(heatmap.lags()[col], 12);
+
+        // PNG with the plot area plus margins
+        let filename = temp_filename("heatmap.png");
+        heatmap.write(&filename).unwrap();
+        let bytes = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&bytes[12..16], b"IHDR");
+        let width = u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
+        let height = u32::from_be_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]);
+        assert_eq!((width, height), (354, 176));
+        let ihdr_crc = u32::from_be_bytes([bytes[29], bytes[30], bytes[31], bytes[32]]);
+        assert_eq!(ihdr_crc, crc32(&bytes[12..29]));
+
+        // SVG embeds the plot area and has labelled axes
+        let svg = heatmap.to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("data:image/png;base64,iVBORw0KGgo"));
+        assert!(svg.contains("Frequency (Hz)"));
+
+        // Cropping the lags keeps the peak
+        let cropped = Heatmap::new(&surface, fs).unwrap().with_lag_range(-20, 20);
+        assert_eq!(cropped.lags().len(), 41);
+        assert_eq!(cropped.lags()[cropped.peak().1], 12);
+
+        // Nothing to plot is an error, not a panic
+        assert!(Heatmap::new(&[], fs).is_err());
+        let empty = CafSurfaceRow { freq: 0.0, xcor_mag: Vec::new(), xcor_peak_idx: 0,
+            xcor_peak_val: 0.0 };
+        assert!(Heatmap::new(&[empty], fs).is_err());
+
+        // Slices through the peak
+        let canvas = heatmap.row_slice(row).render();
+        assert!(canvas.width() > 0 && canvas.height() > 0);
+        assert!(heatmap.column_slice(col).to_svg().contains("<polyline"));
+
+        // Scales and colormaps
+        assert_eq!(Scale::Db(60.0).apply(1.0, 1.0), 0.0);
+        assert_eq!(Scale::Db(60.0).apply(0.0, 1.0), -60.0);
+        assert_eq!(Scale::Linear.apply(0.5, 2.0), 0.25);
+        assert_eq!("db40".parse::<Scale>().unwrap(), Scale::Db(40.0));
+        assert_eq!("lin".parse::<Scale>().unwrap(), Scale::Linear);
+        assert!("loud".parse::<Scale>().is_err());
+        assert_eq!(Colormap::Gray.color(0.0), [0, 0, 0]);
+        assert_eq!(Colormap::Gray.color(2.0), [255, 255, 255]);
+        assert_eq!(Colormap::Viridis.color(1.0), [253, 231, 37]);
+        assert_eq!("magma".parse::<Colormap>().unwrap(), Colormap::Magma);
+    }
+
+    #[test]
+    fn test_render_text_preview() {
+        // Delayed, shifted copy of noise in a small surface
+        let fs = 48000;
+        let needle = gen_noise(256, 37);
+        let haystack = gen_haystack(&needle, 20, -100.0, 0.0, fs);
+        let freqs = gen_float_shifts(-500.0, 500.0, 50.0);
+        let surface = CafRustFFT::caf_surface(&needle, &haystack, &freqs, fs);
+        let heatmap = Heatmap::new(&surface, fs).unwrap();
+
+        // Fits the terminal, with the peak marked and both cuts
+        for style in [TextStyle::Blocks, TextStyle::Braille].iter() {
+            let text = heatmap.to_text(60, 30, *style);
+            let lines: Vec<&str> = text.lines().collect();
+            assert!(lines.len() < 30);
+            assert!(lines.iter().all(|line| line.chars().count() <= 60));
+            assert!(lines[0].contains("-100.00 Hz, 20 samples"));
+            assert_eq!(text.matches('+').count(), 1);
+            assert!(text.contains("Lag cut at -100.00 Hz"));
+            assert!(text.contains("Doppler cut at 20 samples"));
+        }
+
+        // Marker sits in the peak's row and column: -100Hz is row 11 of
+        // 20 counting down from 450Hz, and lag 20 is 276 of 512 lags
+        let text = heatmap.to_text(60, 30, TextStyle::Blocks);
+        let map: Vec<Vec<char>> = text.lines().skip(1).take(16)
+            .map(|line| line.chars().skip(12).collect())
+            .collect();
+        let (y, x) = map.iter().enumerate()
+            .find_map(|(y, row)| row.iter().position(|c| *c == '+').map(|x| (y, x)))
+            .unwrap();
+        assert_eq!(y, 9); // 20 rows over 16 lines, line 9 covers row 11
+        assert_eq!(x, 25); // 512 lags over 48 columns, column 25 covers 266-276
+        assert_eq!("braille".parse::<TextStyle>().unwrap(), TextStyle::Braille);
+    }
+
+    #[test]
+    fn test_result_output() {
+        // Shift between two 10Hz bins, well inside the ~47Hz mainlobe
+        let fs = 48000;
+        let needle = gen_noise(1024, 41);
+        let haystack = gen_haystack(&needle, 12, 134.0, 0.0, fs);
+        let freqs = gen_float_shifts(-300.0, 300.0, 10.0);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &freqs, fs);
+
+        // Strongest peak matches find_peak, refinement moves toward the truth
+        let result = CafResult::from_surface(&surface, fs, 3);
+        let peak = result.peak();
+        assert_eq!(result.peaks.len(), 3);
+        assert_eq!(peak.freq_hz, 130.0);
+        assert_eq!(peak.lag_samples, 12);
+        assert!((peak.refined_freq_hz - 134.0).abs() < 1.0);
+        assert!((peak.refined_lag_samples - 12.0).abs() < 0.1);
+        assert_eq!(peak.relative_db, 0.0);
+        assert!(result.peaks[1].value <= peak.value);
+        assert!(result.quality.peak_to_mean_db > 20.0);
+        assert_eq!(result.config.n_freqs, 60);
+        assert_eq!(result.config.fstep_hz, 10.0);
+        let (freq, samp_idx) = CafRustFFTThreads::find_peak(surface);
+        assert_eq!((freq, samp_idx), (peak.freq_hz, peak.lag_idx));
+
+        // JSON reads back (floats to within rounding)
+        let mut json = Vec::new();
+        write_results(&mut json, std::slice::from_ref(&result), OutputFormat::Json).unwrap();
+        let parsed: CafResult = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed.config, result.config);
+        assert_eq!(parsed.peaks.len(), 3);
+        assert_eq!(parsed.peak().lag_idx, peak.lag_idx);
+        assert!((parsed.peak().refined_freq_hz - peak.refined_freq_hz).abs() < 1e-9);
+
+        // JSON Lines is one result per line, CSV one peak per line
+        let mut jsonl = Vec::new();
+        write_results(&mut jsonl, &[result.clone(), result.clone()], OutputFormat::Jsonl).unwrap();
+        assert_eq!(String::from_utf8(jsonl).unwrap().lines().count(), 2);
+        let mut csv = Vec::new();
+        write_results(&mut csv, std::slice::from_ref(&result), OutputFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("rank,freq_hz,lag_idx"));
+        assert!(lines[1].starts_with("1,130,12,12,"));
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+
+        // Text is what the CLI always printed
+        let mut text = Vec::new();
+        write_results(&mut text, &[result], OutputFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+            "Frequency offset: 130.0Hz\nTime offset: 12 samples (0.250ms)\n");
+    }
+
+    #[test]
+    fn test_real_input() {
+        // Analytic signal of a real tone (whole cycles) is a complex tone
+        let fs = 8000;
+        let tone: Vec<f64> = (0..1024).map(|n| (2.0 * PI * 500.0 * n as f64 / fs as f64).cos())
+            .collect();
+        let analytic = analytic_signal(&tone);
+        assert_eq!(analytic.len(), tone.len());
+        for (n, x) in analytic.iter().enumerate() {
+            let expected = Complex64::from_polar(&1.0, &(2.0 * PI * 500.0 * n as f64 / fs as f64));
+            assert!((x - expected).norm() < 1e-9);
+        }
+        assert!(analytic_signal(&[]).is_empty());
+
+        // Real noise through its analytic signal finds the delay
+        let real: Vec<f64> = gen_noise(4096, 47).iter().map(|x| x.re).collect();
+        let delayed: Vec<f64> = (0..real.len()).map(|n| if n < 20 { 0.0 } else { real[n - 20] })
+            .collect();
+        let needle = analytic_signal(&real);
+        let haystack = analytic_signal(&delayed);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &[-10.0, 0.0, 10.0], fs);
+        assert_eq!(CafRustFFTThreads::find_peak(surface), (0.0, 20));
+
+        // DDC brings a 1kHz tone to 0Hz at a quarter of the rate
+        let tone: Vec<f64> = (0..4000).map(|n| (2.0 * PI * 1000.0 * n as f64 / fs as f64).cos())
+            .collect();
+        let ddc = Ddc::with_decimation(1000.0, fs, 4);
+        assert_eq!(ddc.output_rate(), 2000);
+        let baseband = ddc.process_real(&tone);
+        assert_eq!(baseband.len(), 1000);
+        for samp in baseband[100..900].iter() {
+            assert!((samp - Complex64::new(1.0, 0.0)).norm() < 1e-2);
+        }
+
+        // Delays survive decimation, scaled by the factor
+        let ddc = Ddc::with_decimation(2000.0, fs, 2);
+        let needle = ddc.process_real(&real);
+        let haystack = ddc.process_real(&delayed);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &[0.0], 4000);
+        assert_eq!(CafRustFFTThreads::find_peak(surface), (0.0, 10));
+    }
+
+    #[test]
+    fn test_wav_reader() {
+        // Stereo PCM16, second channel inverted
+        let mut data = Vec::new();
+        for v in [0i16, 16384, -32768].iter() {
+            data.extend_from_slice(&v.to_le_bytes());
+            data.extend_from_slice(&(v.saturating_neg()).to_le_bytes());
+        }
+        let wav = parse_wav(&gen_wav(1, 2, 44100, 16, &data)).unwrap();
+        assert_eq!(wav.fs, 44100);
+        assert_eq!(wav.len(), 3);
+        assert_eq!(wav.channels[0], vec![0.0, 0.5, -1.0]);
+        assert_eq!(wav.channels[1], vec![0.0, -0.5, 32767.0 / 32768.0]);
+        assert_eq!(wav.iq().unwrap()[1], Complex64::new(0.5, -0.5));
+
+        // Mono PCM24 and PCM32, sign extended
+        let wav = parse_wav(&gen_wav(1, 1, 8000, 24, &[0x00, 0x00, 0xc0, 0xff, 0xff, 0x7f]))
+            .unwrap();
+        assert_eq!(wav.channels, vec![vec![-0.5, 8388607.0 / 8388608.0]]);
+        assert!(wav.iq().is_err());
+        let wav = parse_wav(&gen_wav(1, 1, 8000, 32, &(-1i32 << 30).to_le_bytes())).unwrap();
+        assert_eq!(wav.channels[0], vec![-0.5]);
+
+        // Float, plain and extensible
+        let wav = parse_wav(&gen_wav(3, 1, 8000, 32, &0.25f32.to_le_bytes())).unwrap();
+        assert_eq!(wav.channels[0], vec![0.25]);
+        let wav = parse_wav(&gen_wav(0xFFFE, 1, 8000, 64, &(-0.75f64).to_le_bytes())).unwrap();
+        assert_eq!(wav.channels[0], vec![-0.75]);
+
+        // Unsupported or broken files are refused
+        assert!(parse_wav(&gen_wav(1, 1, 8000, 12, &[0, 0])).is_err());
+        assert!(parse_wav(&gen_wav(2, 1, 8000, 16, &[0, 0])).is_err());
+        assert!(parse_wav(b"RIFF\x04\x00\x00\x00WAVE").is_err());
+        assert!(parse_wav(b"not a wav").is_err());
+
+        // And from disk
+        let filename = temp_filename("audio.wav");
+        std::fs::write(&filename, gen_wav(1, 1, 22050, 16, &[0x00, 0x40])).unwrap();
+        let wav = read_wav(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!((wav.fs, wav.channels[0][0]), (22050, 0.5));
+    }
+
+    #[test]
+    fn test_channel_matrix() {
+        // Three channels, delayed and shifted copies of channel 0
+        let fs = 8000;
+        let base = gen_noise(512, 53);
+        let truth = [(0, 0.0), (7, 20.0), (15, -30.0)];
+        let sources: Vec<Vec<Complex64>> = truth.iter()
+            .map(|(delay, freq)| gen_haystack(&base, *delay, *freq, 0.0, fs))
+            .collect();
+
+        // Interleaved on disk, read back per channel
+        let mut interleaved = Vec::new();
+        for n in 0..base.len() {
+            interleaved.extend(sources.iter().map(|c| c[n]));
+        }
+        let filename = temp_filename("channels.cf64");
+        write_file(&filename, &interleaved, SampleFormat::Cf64, Endian::Little).unwrap();
+        let channels = read_channels(&filename, SampleFormat::Cf64, Endian::Little, 3).unwrap();
+        let window = read_channels_range(&filename, SampleFormat::Cf64, Endian::Little, 3, 10, 4)
+            .unwrap();
+        assert!(read_channels_range(&filename, SampleFormat::Cf64, Endian::Little, 3,
+            u64::MAX / 2, 4).is_err());
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(channels, sources);
+        assert_eq!(window[2], sources[2][10..14].to_vec());
+        assert_eq!(deinterleave(&interleaved[..7], 3)[0].len(), 2);
+
+        // Every pair, j relative to i, mirrored below the diagonal
+        let freqs = gen_float_shifts(-60.0, 60.0, 10.0);
+        let matrix = CafRustFFTThreads::caf_matrix(&channels, &freqs, fs, Pairing::AllPairs);
+        assert_eq!(matrix.pairs(), vec![(0, 1), (0, 2), (1, 2)]);
+        for i in 0..3 {
+            assert!(matrix.get(i, i).is_none());
+            for j in 0..3 {
+                if i == j {
+                    continue;
+                }
+                let peak = matrix.get(i, j).unwrap();
+                assert_eq!(peak.lag_samples, truth[j].0 as i64 - truth[i].0 as i64);
+                assert_eq!(peak.freq_hz, truth[j].1 - truth[i].1);
+                assert_eq!(peak.lag_idx as i64, (peak.lag_samples + 1024) % 1024);
+            }
+        }
+        let tdoa = matrix.tdoa_s();
+        assert!((tdoa[0][1].unwrap() - 7.0 / 8000.0).abs() < 1e-5);
+        assert_eq!(tdoa[1][0].unwrap(), -tdoa[0][1].unwrap());
+        assert_eq!(matrix.fdoa_hz()[2][2], None);
+
+        // Against a reference only
+        let matrix = CafRustFFTThreads::caf_matrix(&channels, &freqs, fs, Pairing::Reference(2));
+        assert_eq!(matrix.pairs(), vec![(2, 0), (2, 1)]);
+        assert!(matrix.get(0, 1).is_none());
+        assert_eq!(matrix.get(2, 1).unwrap().lag_samples, -8);
+        assert_eq!(matrix.get(1, 2).unwrap().freq_hz, -50.0);
+
+        // Text lists the pairs, JSON reads back
+        let mut text = Vec::new();
+        write_matrix(&mut text, &matrix, OutputFormat::Text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Channel 0 relative to 2: 30.0Hz, -15 samples (-1.875ms)\n"));
+        let mut json = Vec::new();
+        write_matrix(&mut json, &matrix, OutputFormat::Json).unwrap();
+        let parsed: CafMatrix = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed.reference, Some(2));
+        assert_eq!(parsed.get(2, 0).unwrap().lag_samples, -15);
+    }
+
+    #[test]
+    fn test_pairwise_batch() {
+        // Four receivers, delayed and shifted copies of one emitter
+        let fs = 8000;
+        let base = gen_noise(512, 59);
+        let truth = [(0, 0.0), (7, 20.0), (15, -30.0), (4, 40.0)];
+        let signals: Vec<Vec<Complex64>> = truth.iter()
+            .map(|(delay, freq)| gen_haystack(&base, *delay, *freq, 0.0, fs))
+            .collect();
+
+        // Same peaks as a surface per pair
+        let freqs = gen_float_shifts(-80.0, 80.0, 10.0);
+        let matrix = CafMatrix::pairwise(&signals, &freqs, fs, Weighting::None);
+        let reference = CafRustFFTThreads::caf_matrix(&signals, &freqs, fs, Pairing::AllPairs);
+        assert_eq!(matrix.pairs().len(), 6);
+        for (i, j) in matrix.pairs() {
+            let peak = matrix.get(i, j).unwrap();
+            assert_eq!(peak.lag_samples, truth[j].0 as i64 - truth[i].0 as i64);
+            assert_eq!(peak.freq_hz, truth[j].1 - truth[i].1);
+            assert_eq!(peak.lag_samples, reference.get(i, j).unwrap().lag_samples);
+            assert_eq!(matrix.get(j, i).unwrap().lag_samples, -peak.lag_samples);
+        }
+
+        // Every triple closes
+        assert_eq!(matrix.closures.len(), 4);
+        assert_eq!(matrix.closures[0].channels, (0, 1, 2));
+        let (tdoa_s, fdoa_hz) = matrix.max_closure_error();
+        assert!(tdoa_s < 0.1 / fs as f64, "{}", tdoa_s);
+        assert!(fdoa_hz < 1.0, "{}", fdoa_hz);
+        assert!(matrix.is_consistent(0.1 / fs as f64, 1.0));
+        assert_eq!(reference.closures.len(), 4);
+
+        // No closures against a reference
+        let reference = CafRustFFTThreads::caf_matrix(&signals, &freqs, fs, Pairing::Reference(0));
+        assert!(reference.closures.is_empty());
+        assert_eq!(reference.max_closure_error(), (0.0, 0.0));
+
+        // PHAT weighting finds the same delays
+        let phat = CafMatrix::pairwise(&signals, &freqs, fs, Weighting::Phat);
+        assert_eq!(phat.get(1, 2).unwrap().lag_samples, 8);
+
+        // Every weighting from reused spectra matches the engine's
+        for weighting in [Weighting::Scot, Weighting::Roth, Weighting::Eckart, Weighting::Ml] {
+            let matrix = CafMatrix::pairwise(&signals, &freqs, fs, weighting);
+            let peak = matrix.get(0, 2).unwrap();
+            let surface = CafRustFFT::caf_surface_weighted(&signals[0], &signals[2],
+                &freqs, fs, weighting);
+            let row = surface.iter().find(|row| row.freq == peak.freq_hz).unwrap();
+            let value = row.xcor_mag[peak.lag_idx];
+            assert!((peak.value / value - 1.0).abs() < 1e-9, "{:?}", weighting);
+        }
+    }
+
+    #[test]
+    fn test_template_bank() {
+        // Three preambles of different lengths and powers, two of
+        // them buried in a weak noise floor
+        let fs = 8000;
+        let mut bank = TemplateBank::new();
+        let lengths = [128, 200, 96];
+        for (k, len) in lengths.iter().enumerate() {
+            let samples = gen_noise(*len, 70 + k as u64).iter().map(|x| x * (k + 1) as f64).collect();
+            assert_eq!(bank.add(&format!("preamble{}", k), samples), k);
+        }
+        assert_eq!(bank.len(), 3);
+        assert_eq!(bank.names(), vec!["preamble0", "preamble1", "preamble2"]);
+
+        let mut haystack: Vec<Complex64> = gen_noise(4096, 80).iter().map(|x| x * 0.05).collect();
+        for (template, start, freq) in [(1, 500, 30.0), (2, 2000, -20.0)].iter() {
+            let len = lengths[*template];
+            let shifted = gen_haystack(&gen_noise(len, 70 + *template as u64), 0, *freq, 0.0, fs);
+            for (k, x) in shifted.iter().enumerate() {
+                haystack[start + k] += x * 0.5;
+            }
+        }
+
+        // One detection per burst, with its template and offset
+        let freqs = gen_float_shifts(-50.0, 50.0, 10.0);
+        let detections = bank.detect(&haystack, &freqs, fs, 0.5);
+        assert_eq!(detections.len(), 2, "{:?}", detections);
+        let mut found: Vec<(usize, usize, f64)> = detections.iter()
+            .map(|d| (d.template, d.lag_samples, d.freq_hz))
+            .collect();
+        found.sort_by_key(|d| d.1);
+        assert_eq!(found, vec![(1, 500, 30.0), (2, 2000, -20.0)]);
+        assert_eq!(detections[0].name, format!("preamble{}", detections[0].template));
+        assert!(detections.iter().all(|d| d.score > 0.9 && d.score <= 1.0 + 1e-9));
+        assert_eq!(detections[1].lag_s, detections[1].lag_samples as f64 / fs as f64);
+
+        // Best of every template, the absent one scoring low
+        let best = bank.search(&haystack, &freqs, fs);
+        assert_eq!(best.len(), 3);
+        assert_eq!(best[2].template, 0);
+        assert!(best[2].score < 0.2);
+        assert!(bank.detect(&haystack, &freqs, fs, 1.1).is_empty());
+
+        // Templates longer than the haystack are skipped
+        assert_eq!(bank.search(&haystack[..150], &freqs, fs).len(), 2);
+
+        // A silent template scores 0 rather than NaN
+        bank.add("silence", vec![Complex64::default(); 64]);
+        let best = bank.search(&haystack, &freqs, fs);
+        assert_eq!(best.len(), 4);
+        assert_eq!((best[3].template, best[3].score), (3, 0.0));
+        assert_eq!(bank.detect(&haystack, &freqs, fs, 0.5).len(), 2);
+    }
+
+    #[test]
+    fn test_streaming_caf() {
+        // Three bursts of the needle in a weak noise floor, the last
+        // ending with the stream
+        let fs = 8000;
+        let needle = gen_noise(256, 90);
+        let mut stream: Vec<Complex64> = gen_noise(5000, 91).iter().map(|x| x * 0.05).collect();
+        let bursts = [(300, 20.0), (3000, -10.0), (4744, 0.0)];
+        for (start, freq) in bursts.iter() {
+            let shifted = gen_haystack(&needle, 0, *freq, 0.0, fs);
+            for (k, x) in shifted.iter().enumerate() {
+                stream[start + k] += x * 0.5;
+            }
+        }
+        let freqs = gen_float_shifts(-40.0, 40.0, 10.0);
+
+        // Pushed all at once
+        let mut caf = CafStream::new(&needle, &freqs, fs).with_threshold(0.6);
+        assert_eq!(caf.latency_samples(), 511);
+        let mut whole = caf.push(&stream);
+        assert_eq!(whole.len(), 2);
+        whole.extend(caf.finish());
+        assert_eq!(caf.samples_seen(), 5000);
+        let found: Vec<(u64, f64)> = whole.iter().map(|d| (d.sample, d.freq_hz)).collect();
+        assert_eq!(found, vec![(300, 20.0), (3000, -10.0), (4744, 0.0)]);
+        assert!(whole.iter().all(|d| d.score > 0.9 && d.score <= 1.0 + 1e-9));
+        assert_eq!(whole[1].time_s, 3000.0 / 8000.0);
+
+        // Read in odd blocks through a reader, each burst reported
+        // within the latency bound
+        let mut bytes = Vec::new();
+        encode_samples(&stream, SampleFormat::Cf32, Endian::Little, &mut bytes);
+        let mut caf = CafStream::new(&needle, &freqs, fs).with_threshold(0.6);
+        let mut streamed = Vec::new();
+        for block in ChunkedReader::new(std::io::Cursor::new(bytes), SampleFormat::Cf32,
+            Endian::Little, 77) {
+
+            for detection in caf.push(&block.unwrap()) {
+                assert!(caf.samples_seen() <= detection.sample + 511 + 77);
+                streamed.push(detection);
+            }
+        }
+        streamed.extend(caf.finish());
+        assert_eq!(streamed.iter().map(|d| d.sample).collect::<Vec<u64>>(), vec![300, 3000, 4744]);
+        assert!((streamed[0].score - whole[0].score).abs() < 1e-6);
+
+        // Nothing in noise alone
+        let mut quiet = CafStream::new(&needle, &freqs, fs).with_threshold(0.6);
+        assert!(quiet.push(&gen_noise(3000, 92)).is_empty());
+        assert!(quiet.finish().is_empty());
+
+        // One line per detection, CSV with its header first
+        let mut text = Vec::new();
+        DetectionWriter::new(&mut text, OutputFormat::Text).write(&whole[0]).unwrap();
+        assert!(String::from_utf8(text).unwrap()
+            .starts_with("Needle at sample 300 (0.037500s): 20.0Hz, score 0.9"));
+        let mut csv = Vec::new();
+        let mut writer = DetectionWriter::new(&mut csv, OutputFormat::Csv);
+        for detection in whole.iter() {
+            writer.write(detection).unwrap();
+        }
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.starts_with("sample,time_s,freq_hz,value,score\n300,0.0375,20,"));
+        let mut jsonl = Vec::new();
+        DetectionWriter::new(&mut jsonl, OutputFormat::Json).write(&whole[2]).unwrap();
+        let parsed: StreamDetection = serde_json::from_slice(&jsonl).unwrap();
+        assert_eq!(parsed.sample, 4744);
+        assert!((parsed.score - whole[2].score).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_socket_sources() {
+        // Socket names
+        assert_eq!("tcp://127.0.0.1:5000".parse::<SocketSpec>().unwrap(),
+            SocketSpec::TcpConnect("127.0.0.1:5000".to_string()));
+        let listen: SocketSpec = "tcp-listen://0.0.0.0:5000".parse().unwrap();
+        assert_eq!(listen.to_string(), "tcp-listen://0.0.0.0:5000");
+        assert!(SocketSpec::is_socket("udp://0.0.0.0:2000"));
+        assert!(!SocketSpec::is_socket("capture.cf32"));
+        assert!("sctp://host:1".parse::<SocketSpec>().is_err());
+        assert!("udp://".parse::<SocketSpec>().is_err());
+        assert_eq!("seqnum".parse::<UdpHeader>().unwrap(), UdpHeader::SeqNum);
+
+        // A burst of the needle in complex float, as the sinks send it
+        let fs = 8000;
+        let needle = gen_noise(128, 93);
+        let mut samples: Vec<Complex64> = gen_noise(1840, 94).iter().map(|x| x * 0.05).collect();
+        for (k, x) in needle.iter().enumerate() {
+            samples[600 + k] += x * 0.5;
+        }
+        let mut bytes = Vec::new();
+        encode_samples(&samples, SampleFormat::Cf32, Endian::Little, &mut bytes);
+        let freqs = gen_float_shifts(-20.0, 20.0, 10.0);
+        let detect = |reader: Box<dyn std::io::Read + Send>| {
+            let mut caf = CafStream::new(&needle, &freqs, fs).with_threshold(0.6);
+            let mut found = Vec::new();
+            for block in ChunkedReader::new(reader, SampleFormat::Cf32, Endian::Little, 128) {
+                found.extend(caf.push(&block.unwrap()));
+            }
+            found.extend(caf.finish());
+            found.iter().map(|d| d.sample).collect::<Vec<u64>>()
+        };
+
+        // TCP Sink as server, we connect
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let spec = SocketSpec::TcpConnect(server.local_addr().unwrap().to_string());
+        let payload = bytes.clone();
+        let sender = std::thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            for chunk in payload.chunks(1000) {
+                stream.write_all(chunk).unwrap();
+            }
+        });
+        assert_eq!(detect(spec.open(UdpHeader::None).unwrap()), vec![600]);
+        sender.join().unwrap();
+
+        // TCP Sink as client, we listen
+        let source = TcpSource::listen("127.0.0.1:0").unwrap();
+        let addr = source.local_addr().unwrap();
+        let payload = bytes.clone();
+        let sender = std::thread::spawn(move || {
+            std::net::TcpStream::connect(addr).unwrap().write_all(&payload).unwrap();
+        });
+        let mut received = Vec::new();
+        source.accept().unwrap().read_to_end(&mut received).unwrap();
+        sender.join().unwrap();
+        assert_eq!(received, bytes);
+
+        // UDP Sink, plain payloads then an empty datagram
+        let source = UdpSource::bind("127.0.0.1:0", UdpHeader::None).unwrap();
+        let addr = source.local_addr().unwrap();
+        let payload = bytes.clone();
+        let sender = std::thread::spawn(move || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            for chunk in payload.chunks(1472) {
+                socket.send_to(chunk, addr).unwrap();
+            }
+            socket.send_to(&[], addr).unwrap();
+        });
+        assert_eq!(detect(Box::new(source)), vec![600]);
+        sender.join().unwrap();
+
+        // With sequence numbers, a lost datagram becomes zeros
+        let mut source = UdpSource::bind("127.0.0.1:0", UdpHeader::SeqNum).unwrap();
+        let addr = source.local_addr().unwrap();
+        let payload = bytes.clone();
+        let sender = std::thread::spawn(move || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            for (seq, chunk) in payload.chunks(1472).enumerate() {
+                if seq != 3 {
+                    let mut datagram = (seq as u64 + 100).to_le_bytes().to_vec();
+                    datagram.extend_from_slice(chunk);
+                    socket.send_to(&datagram, addr).unwrap();
+                }
+            }
+            socket.send_to(&[], addr).unwrap();
+        });
+        let mut received = Vec::new();
+        source.read_to_end(&mut received).unwrap();
+        sender.join().unwrap();
+        assert_eq!(source.lost_datagrams(), 1);
+        assert_eq!(received.len(), bytes.len());
+        assert_eq!(received[..3 * 1472], bytes[..3 * 1472]);
+        assert!(received[3 * 1472..4 * 1472].iter().all(|b| *b == 0));
+        assert_eq!(received[4 * 1472..], bytes[4 * 1472..]);
+
+        // A jump too far to fill, either way, resyncs instead
+        let mut source = UdpSource::bind("127.0.0.1:0", UdpHeader::SeqNum).unwrap();
+        let addr = source.local_addr().unwrap();
+        let sender = std::thread::spawn(move || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            for seq in [7, u64::MAX - 1, u64::MAX, 0, 3].iter() {
+                let mut datagram = seq.to_le_bytes().to_vec();
+                datagram.extend_from_slice(&[1; 16]);
+                socket.send_to(&datagram, addr).unwrap();
+            }
+            socket.send_to(&[], addr).unwrap();
+        });
+        let mut received = Vec::new();
+        source.read_to_end(&mut received).unwrap();
+        sender.join().unwrap();
+        assert_eq!(source.resyncs(), 1);
+        assert_eq!(source.lost_datagrams(), 2);
+        assert_eq!(received.len(), 7 * 16);
+        assert_eq!(received.iter().filter(|b| **b == 0).count(), 2 * 16);
+    }
+
+    #[test]
+    fn test_gr_meta() {
+        // PMTs survive serialization
+        let pmt = Pmt::Vector(vec![
+            Pmt::Complex(Complex64::new(1.5, -2.0)),
+            Pmt::Int(-7),
+            Pmt::Int(1 << 40),
+            Pmt::Uniform { kind: 0x08, bytes: vec![0x3f, 0x80, 0, 0, 0x40, 0, 0, 0] },
+            Pmt::dict(&[("freq", Pmt::Double(915e6)), ("name", Pmt::Symbol("rx".to_string()))]),
+        ]);
+        let bytes = pmt.to_bytes();
+        assert_eq!(Pmt::parse(&bytes).unwrap(), (pmt, bytes.len()));
+        assert!(Pmt::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        // The File Meta Sink's header is always 149 bytes
+        let header = gr_meta_header(32000.0, (1700000000, 0.25), 0, &[]);
+        assert_eq!(header.len(), 149);
+
+        // Two segments with attached headers, a stream tag on the first
+        let fs = 32000;
+        let first = gen_noise(300, 95);
+        let second = gen_noise(200, 96);
+        let mut file = Vec::new();
+        let extras = [("rx_freq", Pmt::Double(2.4e9))];
+        for (samples, time, extras) in [(&first, 0.25, &extras[..]), (&second, 0.5, &[][..])] {
+            let mut data = Vec::new();
+            encode_samples(samples, SampleFormat::Cf32, Endian::Little, &mut data);
+            file.extend(gr_meta_header(fs as f64, (1700000000, time), data.len(), extras));
+            file.extend(data);
+        }
+        let attached = temp_filename("gr_meta_attached.cf32");
+        std::fs::write(&attached, &file).unwrap();
+        assert!(is_gr_meta(&attached));
+        let meta = GrMeta::read(&attached).unwrap();
+        assert!(!meta.detached);
+        assert_eq!(meta.segments.len(), 2);
+        assert_eq!(meta.segments[0].extras, vec![("rx_freq".to_string(), Pmt::Double(2.4e9))]);
+        assert_eq!(meta.sample_rate(), fs as f64);
+        assert_eq!(meta.start_time(), Some(Timestamp::new(1700000000, 250_000_000)));
+        assert_eq!(meta.sample_format().unwrap(), (SampleFormat::Cf32, Endian::Little));
+        assert_eq!(meta.len(), 500);
+
+        // Segments read as one run of samples, headers skipped
+        let mut expected = first.clone();
+        expected.extend(second.iter());
+        let close = |a: &[Complex64], b: &[Complex64]| a.len() == b.len()
+            && a.iter().zip(b).all(|(x, y)| (x - y).norm() < 1e-6);
+        assert!(close(&meta.read_samples().unwrap(), &expected));
+        assert!(close(&meta.read_range(250, 100).unwrap(), &expected[250..350]));
+        assert!(close(&meta.read_range(450, 100).unwrap(), &expected[450..]));
+        assert!(meta.read_range(u64::MAX / 2, usize::MAX).unwrap().is_empty());
+        assert!(close(&meta.read_range(450, usize::MAX).unwrap(), &expected[450..]));
+        let mut streamed = Vec::new();
+        meta.data_reader().unwrap().read_to_end(&mut streamed).unwrap();
+        let mut data = Vec::new();
+        encode_samples(&expected, SampleFormat::Cf32, Endian::Little, &mut data);
+        assert_eq!(streamed, data);
+
+        // Headers longer than a first read, with a big extra dictionary
+        let note = Pmt::Symbol("x".repeat(3000));
+        let mut long = gr_meta_header(fs as f64, (0, 0.0), 2400, &[("note", note.clone())]);
+        long.extend_from_slice(&data[..2400]);
+        std::fs::write(&attached, &long).unwrap();
+        let meta = GrMeta::read(&attached).unwrap();
+        assert_eq!(meta.segments[0].extras, vec![("note".to_string(), note)]);
+        assert!(close(&meta.read_samples().unwrap(), &expected[..300]));
+
+        // The same recording with detached headers, named either way
+        let detached = temp_filename("gr_meta_detached.cf32");
+        std::fs::write(&detached, &data).unwrap();
+        let mut headers = gr_meta_header(fs as f64, (1700000000, 0.25), 2400, &extras);
+        headers.extend(gr_meta_header(fs as f64, (1700000000, 0.5), 1600, &[]));
+        std::fs::write(format!("{}.hdr", detached), &headers).unwrap();
+        for name in [detached.clone(), format!("{}.hdr", detached)] {
+            assert!(is_gr_meta(&name));
+            let meta = GrMeta::read(&name).unwrap();
+            assert!(meta.detached);
+            assert_eq!(meta.segments[1].data_offset, 2400);
+            assert!(close(&meta.read_range(250, 100).unwrap(), &expected[250..350]));
+        }
+
+        // Lengths that are negative or run past the end are refused
+        assert_eq!(Pmt::Int(-1).as_u64(), None);
+        let huge = gr_meta_header(fs as f64, (0, 0.0), usize::MAX, &[]);
+        assert!(parse_gr_meta(&huge, false).is_err());
+        assert!(parse_gr_meta(&[huge.clone(), huge].concat(), true).is_err());
+        assert!(parse_gr_meta(&file[..file.len() - 1], false).is_err());
+
+        // Plain samples are not gr_meta, and real items are refused
+        let plain = temp_filename("gr_meta_plain.cf32");
+        std::fs::write(&plain, &data).unwrap();
+        assert!(!is_gr_meta(&plain));
+        assert!(!is_gr_meta("-"));
+        let mut real = parse_gr_meta(&gr_meta_header(fs as f64, (0, 0.0), 0, &[]), false).unwrap();
+        real[0].complex = false;
+        let meta = GrMeta { data_path: plain.clone().into(), detached: false, segments: real };
+        assert!(meta.sample_format().is_err());
+
+        for name in [attached, detached.clone(), format!("{}.hdr", detached), plain] {
+            std::fs::remove_file(name).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_absolute_tdoa() {
+        // Times to the nanosecond, however they're written
+        let t: Timestamp = "2026-10-19T12:34:56.123456789Z".parse().unwrap();
+        assert_eq!(t.to_string(), "2026-10-19T12:34:56.123456789Z");
+        assert_eq!("2026-10-19T14:34:56.123456789+02:00".parse::<Timestamp>().unwrap(), t);
+        assert_eq!(format!("{}.123456789", t.secs).parse::<Timestamp>().unwrap(), t);
+        assert_eq!("2024-02-29T00:00:00Z".parse::<Timestamp>().unwrap().secs, 1709164800);
+        assert_eq!("1969-12-31T23:59:59.5Z".parse::<Timestamp>().unwrap(), Timestamp::new(-1, 500_000_000));
+        assert_eq!(Timestamp::new(-1, 500_000_000).to_string(), "1969-12-31T23:59:59.500000000Z");
+        assert!("2025-02-29T00:00:00Z".parse::<Timestamp>().is_err());
+        assert!("yesterday".parse::<Timestamp>().is_err());
+        assert_eq!(Timestamp::from_gr_time(1700000000, 0.25), Timestamp::new(1700000000, 250_000_000));
+
+        // Sample offsets and differences stay exact
+        assert_eq!(t.add_samples(3, 48000).nanos_since(&t), 62500);
+        assert_eq!(t.add_samples(-1, 3).nanos_since(&t), -333_333_333);
+        assert_eq!(t.add_nanos(-250).seconds_since(&t), -250e-9);
+
+        // SigMF datetimes are taken back to sample 0
+        let meta: SigMFMeta = serde_json::from_str(r#"{
+            "global": {"core:datatype": "cf32_le", "core:sample_rate": 1000.0},
+            "captures": [{"core:sample_start": 5, "core:datetime": "2026-10-19T12:00:00.005Z"}]
+        }"#).unwrap();
+        assert_eq!(meta.start_time().unwrap().unwrap().to_string(), "2026-10-19T12:00:00.000000000Z");
+        let meta: SigMFMeta = serde_json::from_str(r#"{
+            "global": {"core:datatype": "cf32_le", "core:sample_rate": 3.0},
+            "captures": [{"core:sample_start": 3000000000001,
+                "core:datetime": "2026-10-19T12:00:00.333333333Z"}]
+        }"#).unwrap();
+        let time: Timestamp = "2026-10-19T12:00:00.333333333Z".parse().unwrap();
+        assert_eq!(meta.start_time().unwrap(), Some(time.add_samples(-3000000000001, 3)));
+
+        // The haystack receiver started 1ms and 250ns after the needle's,
+        // so its 12 sample lag is that much more in absolute terms
+        let fs = 48000;
+        let needle = gen_noise(1024, 41);
+        let haystack = gen_haystack(&needle, 12, 130.0, 0.0, fs);
+        let freqs = gen_float_shifts(-300.0, 300.0, 10.0);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &freqs, fs);
+        let haystack_start = t.add_nanos(1_000_250);
+        let result = CafResult::from_surface(&surface, fs, 2).with_start_times(t, haystack_start);
+        let peak = result.peak();
+        assert_eq!(peak.lag_samples, 12);
+        assert_eq!(result.start_times.unwrap().offset_ns, 1_000_250);
+        assert!((peak.tdoa_s.unwrap() - (1_000_250e-9 + 12.0 / fs as f64)).abs() < 1e-12);
+        assert!((peak.refined_tdoa_s.unwrap() - peak.tdoa_s.unwrap()).abs() < 1e-6);
+
+        // Start times travel with the result
+        let mut json = Vec::new();
+        write_results(&mut json, std::slice::from_ref(&result), OutputFormat::Json).unwrap();
+        let parsed: CafResult = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed.start_times, result.start_times);
+        let mut text = Vec::new();
+        write_results(&mut text, &[result], OutputFormat::Text).unwrap();
+        assert!(String::from_utf8(text).unwrap()
+            .ends_with("Absolute TDOA: 0.001250250s (0.001000250s between start times)\n"));
+    }
+
+    #[test]
+    fn test_segmented_caf() {
+        let fs = 8000;
+        let freqs = gen_float_shifts(-40.0, 40.0, 10.0);
+
+        // One segment is the plain CAF
+        let needle = gen_noise(1024, 97);
+        let haystack = gen_haystack(&needle, 30, 20.0, 0.0, fs);
+        let plain = CafRustFFT::caf_surface(&needle, &haystack, &freqs, fs);
+        let single = SegmentedCaf::new(1024).caf_surface(&needle, &haystack, &freqs, fs);
+        for (a, b) in plain.iter().zip(single.surface.iter()) {
+            assert_eq!(a.freq, b.freq);
+            assert_eq!(a.xcor_peak_idx, b.xcor_peak_idx);
+            assert!(a.xcor_mag.iter().zip(b.xcor_mag.iter()).all(|(x, y)| (x - y).abs() < 1e-9 * a.xcor_peak_val));
+        }
+
+        // A signal 26dB under the noise is lost in most 512 sample
+        // segments but found by adding 16 of them up coherently
+        let needle = gen_noise(8192, 98);
+        let haystack: Vec<Complex64> = gen_haystack(&needle, 70, 20.0, 0.0, fs).iter()
+            .zip(gen_noise(8192, 99).iter())
+            .map(|(signal, noise)| signal * 0.05 + noise)
+            .collect();
+        let caf = SegmentedCaf::new(512);
+        let coherent = caf.caf_surface(&needle, &haystack, &freqs, fs);
+        let result = CafResult::from_segmented(&coherent, fs, 1);
+        assert_eq!((result.peak().freq_hz, result.peak().lag_samples), (20.0, 70));
+        assert_eq!(coherent.surface[0].xcor_mag.len(), 1024);
+        assert_eq!(result.segments.len(), 16);
+        assert_eq!(result.segments[3].start_sample, 1536);
+        let found = result.segments.iter().filter(|s| s.lag_samples == 70).count();
+        assert!(found < 8);
+
+        // Non-coherent gains less, but still finds it
+        let noncoherent = caf.with_accumulation(Accumulation::NonCoherent)
+            .caf_surface(&needle, &haystack, &freqs, fs);
+        let weaker = CafResult::from_segmented(&noncoherent, fs, 1);
+        assert_eq!(weaker.peak().lag_samples, 70);
+        assert!(weaker.quality.peak_to_mean_db < result.quality.peak_to_mean_db);
+        assert_eq!(noncoherent.segments, coherent.segments);
+
+        // Phase jumps between segments break coherent accumulation only
+        let clean = gen_haystack(&needle, 70, 20.0, 0.0, fs);
+        let jumped: Vec<Complex64> = clean.iter().enumerate()
+            .map(|(n, x)| x * Complex64::from_polar(&1.0, &(2.4 * (n / 512) as f64)))
+            .collect();
+        let peak = |haystack: &[Complex64], accumulation| {
+            let surface = SegmentedCaf::new(512).with_accumulation(accumulation)
+                .caf_surface(&needle, haystack, &freqs, fs).surface;
+            CafRustFFT::find_peak(surface)
+        };
+        assert_eq!(peak(&clean, Accumulation::Coherent), (20.0, 70));
+        assert_eq!(peak(&jumped, Accumulation::NonCoherent), (20.0, 70));
+        let at_peak = |haystack: &[Complex64], accumulation| {
+            let surface = SegmentedCaf::new(512).with_accumulation(accumulation)
+                .caf_surface(&needle, haystack, &freqs, fs).surface;
+            surface.iter().find(|row| row.freq == 20.0).unwrap().xcor_mag[70]
+        };
+        let aligned = at_peak(&clean, Accumulation::Coherent);
+        assert!(at_peak(&jumped, Accumulation::Coherent) < 0.2 * aligned);
+        assert!((at_peak(&clean, Accumulation::NonCoherent) * 16.0 / aligned - 1.0).abs() < 0.1);
+
+        // Text output lists the segments
+        let mut text = Vec::new();
+        write_results(&mut text, &[result], OutputFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap().lines().count(), 18);
+    }
+
+    #[test]
+    fn test_surface_file() {
+        let fs = 8000;
+        let needle = gen_noise(256, 43);
+        let haystack = gen_haystack(&needle, 5, 20.0, 0.0, fs);
+        let freqs = gen_float_shifts(-50.0, 50.0, 10.0);
+        let surface = CafRustFFTThreads::caf_surface(&needle, &haystack, &freqs, fs);
+
+        // Sources are hashed as FNV-1a
+        assert_eq!(fnv1a64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+        let input = temp_filename("surface_source");
+        std::fs::write(&input, b"a").unwrap();
+        let source = SourceFile::from_path(&input).unwrap().with_range(0, 256);
+        std::fs::remove_file(&input).unwrap();
+        assert_eq!(source.bytes, 1);
+        assert_eq!(source.fnv1a64, "af63dc4c8601ec8c");
+
+        // Only the bytes used are hashed, with the file's whole size
+        std::fs::write(&input, b"xay").unwrap();
+        let part = SourceFile::from_bytes(&input, 1, 1).unwrap();
+        std::fs::remove_file(&input).unwrap();
+        assert_eq!(part.bytes, 3);
+        assert_eq!(part.fnv1a64, "af63dc4c8601ec8c");
+
+        // Round trip rebuilds the rows exactly, in frequency order
+        let header = SurfaceHeader::new(&surface, fs)
+            .with_source(source.clone())
+            .with_backend("CafRustFFTThreads");
+        assert_eq!(header.lags, (-256, 255));
+        let filename = temp_filename("surface.cafs");
+        write_surface(&filename, &surface, &header).unwrap();
+        let (read_header, rows) = read_surface(&filename).unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(read_surface_header(&filename).unwrap(), header);
+        assert_eq!(rows.len(), freqs.len());
+        for row in rows.iter() {
+            let original = surface.iter().find(|r| r.freq == row.freq).unwrap();
+            assert_eq!(row.xcor_mag, original.xcor_mag);
+            assert_eq!(row.xcor_peak_idx, original.xcor_peak_idx);
+            assert_eq!(row.xcor_peak_val, original.xcor_peak_val);
+        }
+        assert_eq!(CafRustFFTThreads::find_peak(rows), (20.0, 5));
+
+        // Compatibility checks
+        assert!(header.check_search(fs, &freqs).is_ok());
+        assert!(header.check_search(fs + 1, &freqs).is_err());
+        assert!(header.check_search(fs, &freqs[1..]).is_err());
+        assert!(header.check_sources(std::slice::from_ref(&source)).is_ok());
+        assert!(header.check_sources(&[source.clone().with_range(0, 128)]).is_err());
+        assert!(header.check_sources(&[source.clone().with_decoding("ci16 be")]).is_err());
+        assert!(header.check_sources(&[]).is_err());
+
+        // f32 halves the data and keeps the peak
+        let small = temp_filename("surface_f32.cafs");
+        write_surface(&small, &surface, &header.clone().with_dtype(SurfaceDtype::F32)).unwrap();
+        let data_len = |name: &str| std::fs::metadata(name).unwrap().len();
+        assert!(data_len(&small) < data_len(&filename));
+        let (_, rows) = read_surface(&small).unwrap();
+        assert_eq!(CafRustFFTThreads::find_peak(rows), (20.0, 5));
+        std::fs::remove_file(&small).unwrap();
+
+        // Newer versions, truncated data and foreign files are refused
+        let mut bytes = std::fs::read(&filename).unwrap();
+        bytes.truncate(bytes.len() - 8);
+        std::fs::write(&filename, &bytes).unwrap();
+        assert!(read_surface(&filename).is_err());
+        bytes[8] = (SURFACE_FORMAT_VERSION + 1) as u8;
+        std::fs::write(&filename, &bytes).unwrap();
+        assert!(read_surface_header(&filename).is_err());
+        std::fs::write(&filename, b"not a surface file").unwrap();
+        assert!(read_surface_header(&filename).is_err());
+        std::fs::remove_file(&filename).unwrap();
+
+        // Rows must match the header
+        let other = SurfaceHeader::new(&surface[1..], fs);
+        assert!(write_surface(&filename, &surface, &other).is_err());
+    }
+
+    #[test]
+    fn test_geolocation() {
+        // Four receivers on a plane, exact TDOAs of every pair
+        let receivers: Vec<Receiver> = [[0.0, 0.0], [10000.0, 0.0], [0.0, 10000.0], [10000.0, 9000.0]]
+            .iter()
+            .map(|p| Receiver::new([p[0], p[1], 0.0]))
+            .collect();
+        let emitter = [3000.0, 7000.0, 0.0];
+        let measurements = gen_measurements(&receivers, emitter, None, 1e-8, 1.0);
+        let locator = Locator::new(Frame::Flat2d, receivers.clone());
+        let initial = locator.initial_estimate(&measurements).unwrap();
+        assert!(distance(initial, emitter) < 1e-3, "{:?}", initial);
+        let solution = locator.solve(&measurements).unwrap();
+        assert!(solution.converged);
+        assert!(distance(solution.position, emitter) < 1e-3);
+        assert!(solution.residuals.iter().all(|r| r.abs() < 1e-12));
+        assert_eq!(solution.covariance[2], [0.0; 3]);
+        assert!(solution.geodetic.is_none());
+
+        // Three receivers, the quadratic case
+        let three = Locator::new(Frame::Flat2d, receivers[..3].to_vec());
+        let measurements = gen_measurements(&receivers[..3], emitter, None, 1e-8, 1.0);
+        assert!(distance(three.initial_estimate(&measurements).unwrap(), emitter) < 1e-3);
+
+        // Moving receivers overhead with FDOA, held to the ground
+        let receivers = vec![
+            Receiver::moving([0.0, 0.0, 5000.0], [200.0, 0.0, 0.0]),
+            Receiver::moving([30000.0, 0.0, 6000.0], [0.0, 150.0, 0.0]),
+            Receiver::moving([0.0, 30000.0, 5500.0], [-100.0, -100.0, 0.0]),
+            Receiver::moving([25000.0, 30000.0, 7000.0], [0.0, -250.0, 10.0]),
+        ];
+        let emitter = [20000.0, 15000.0, 0.0];
+        let measurements = gen_measurements(&receivers, emitter, Some(1e9), 1e-8, 0.1);
+        assert_eq!(measurements.len(), 12);
+        let locator = Locator::new(Frame::Flat3d, receivers.clone())
+            .with_carrier(1e9)
+            .with_altitude(0.0, 1.0);
+        let solution = locator.solve(&measurements).unwrap();
+        assert!(distance(solution.position, emitter) < 1e-2, "{:?}", solution.position);
+        for r in 0..3 {
+            assert!(solution.covariance[r][r] > 0.0);
+            for c in 0..3 {
+                assert!((solution.covariance[r][c] - solution.covariance[c][r]).abs()
+                    <= 1e-9 * solution.covariance[r][r].max(solution.covariance[c][c]));
+            }
+        }
+
+        // Covariance grows with sigma squared when nothing else constrains
+        let unheld = Locator::new(Frame::Flat3d, receivers.clone()).with_carrier(1e9);
+        let small = unheld.solve(&measurements).unwrap();
+        let noisy = gen_measurements(&receivers, emitter, Some(1e9), 2e-8, 0.2);
+        let large = unheld.solve(&noisy).unwrap();
+        assert!((large.covariance[0][0] / small.covariance[0][0] - 4.0).abs() < 1e-3);
+
+        // Errors of about a sigma land within a few rms errors
+        let perturbed: Vec<Measurement> = measurements.iter().enumerate()
+            .map(|(n, m)| {
+                let nudge = if n % 2 == 0 { 1.0 } else { -1.0 };
+                match *m {
+                    Measurement::Tdoa { i, j, value_s, sigma_s } =>
+                        Measurement::Tdoa { i, j, value_s: value_s + nudge * sigma_s, sigma_s },
+                    Measurement::Fdoa { i, j, value_hz, sigma_hz } =>
+                        Measurement::Fdoa { i, j, value_hz: value_hz + nudge * sigma_hz, sigma_hz },
+                }
+            })
+            .collect();
+        let solution = locator.solve(&perturbed).unwrap();
+        assert!(solution.converged);
+        assert!(distance(solution.position, emitter) < 5.0 * solution.rms_error_m());
+        assert!(solution.chi_squared > 0.0 && solution.chi_squared < 13.0);
+
+        // Bad input
+        let tdoa_only = Locator::new(Frame::Flat3d, receivers.clone());
+        assert!(tdoa_only.solve(&measurements).is_err());
+        let stray = [Measurement::Tdoa { i: 0, j: 4, value_s: 0.0, sigma_s: 1e-8 }];
+        assert!(locator.solve(&stray).is_err());
+        assert!(locator.solve(&measurements[..1]).is_err());
+
+        // CAF pairs as measurements
+        let fs = 8000;
+        let base = gen_noise(256, 61);
+        let signals: Vec<Vec<Complex64>> = [(0, 0.0), (3, 10.0), (9, -20.0)].iter()
+            .map(|(delay, freq)| gen_haystack(&base, *delay, *freq, 0.0, fs))
+            .collect();
+        let freqs = gen_float_shifts(-50.0, 50.0, 10.0);
+        let matrix = CafMatrix::pairwise(&signals, &freqs, fs, Weighting::None);
+        let measurements = Measurement::from_matrix(&matrix, 1e-5, Some(1.0));
+        assert_eq!(measurements.len(), 6);
+        assert_eq!(measurements[2].receivers(), (0, 2));
+        match measurements[2] {
+            Measurement::Tdoa { value_s, .. } => assert!((value_s - 9.0 / 8000.0).abs() < 1e-5),
+            _ => panic!("expected a TDOA"),
+        }
+        match measurements[3] {
+            Measurement::Fdoa { value_hz, .. } => assert!((value_hz + 20.0).abs() < 1.0),
+            _ => panic!("expected an FDOA"),
+        }
+        assert_eq!(Measurement::from_matrix(&matrix, 1e-5, None).len(), 3);
+    }
+
+    #[test]
+    fn test_geolocation_ecef() {
+        // WGS84 conversions
+        assert_eq!(Geodetic::new(0.0, 0.0, 0.0).to_ecef(), [WGS84_A, 0.0, 0.0]);
+        let pole = Geodetic::new(90.0, 0.0, 0.0).to_ecef();
+        assert!((pole[2] - WGS84_A * (1.0 - WGS84_F)).abs() < 1e-6);
+        let place = Geodetic::new(40.0, -75.0, 120.0);
+        let back = Geodetic::from_ecef(place.to_ecef());
+        assert!((back.lat_deg - 40.0).abs() < 1e-10);
+        assert!((back.lon_deg + 75.0).abs() < 1e-10);
+        assert!((back.alt_m - 120.0).abs() < 1e-6);
+        let north = Geodetic::new(40.001, -75.0, 120.0).enu(place.to_ecef());
+        assert!(north[0].abs() < 1e-6 && north[1] < -110.0 && north[1] > -112.0);
+
+        // Ground stations around an emitter on the ellipsoid
+        let receivers: Vec<Receiver> = [(40.0, -75.0), (40.2, -75.1), (39.9, -74.8),
+            (40.15, -74.75), (40.05, -75.2)].iter()
+            .map(|(lat, lon)| Receiver::new(Geodetic::new(*lat, *lon, 0.0).to_ecef()))
+            .collect();
+        let emitter = Geodetic::new(40.06, -74.93, 0.0).to_ecef();
+        let measurements = gen_measurements(&receivers, emitter, None, 1e-9, 1.0);
+        let locator = Locator::new(Frame::Ecef, receivers).with_altitude(0.0, 1.0);
+        let solution = locator.solve(&measurements).unwrap();
+        assert!(solution.converged);
+        let geodetic = solution.geodetic.unwrap();
+        assert!((geodetic.lat_deg - 40.06).abs() < 1e-7, "{:?}", geodetic);
+        assert!((geodetic.lon_deg + 74.93).abs() < 1e-7);
+        assert!(geodetic.alt_m.abs() < 1e-3);
+
+        // Height is held to its sigma, the plane by the TDOAs
+        let enu = solution.covariance_enu.unwrap();
+        assert!(enu[2][2] > 0.5 && enu[2][2] <= 1.0, "{:?}", enu);
+        assert!(enu[0][0] < 1.0 && enu[1][1] < 1.0);
+        let trace: f64 = (0..3).map(|k| solution.covariance[k][k]).sum();
+        assert!((trace - enu.iter().enumerate().map(|(k, row)| row[k]).sum::<f64>()).abs()
+            < 1e-9 * trace);
+    }
+
     // Helper to load and trim files by filename
     fn load_files(needle_filename: &str, haystack_filename: &str)
         -> (Vec<Complex64>, Vec<Complex64>) {
@@ -350,4 +1952,127 @@ mod tests {
         }
         shifts
     }
+
+    // Helper to generate repeatable complex white noise
+    fn gen_noise(len: usize, seed: u64) -> Vec<Complex64> {
+        let mut state = seed.wrapping_mul(0x9E3779B97F4A7C15) | 1;
+        let mut next = || {
+            // xorshift64*, mapped to [-1, 1)
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            let bits = state.wrapping_mul(0x2545F4914F6CDD1D) >> 11;
+            (bits as f64) / ((1u64 << 52) as f64) - 1.0
+        };
+        (0..len).map(|_| Complex64::new(next(), next())).collect()
+    }
+
+    // Helper to build an equal-length haystack from a needle with a
+    // delay, frequency offset and frequency rate applied
+    fn gen_haystack(needle: &[Complex64], delay: usize, freq: f64,
+        rate: f64, fs: u32) -> Vec<Complex64> {
+
+        let mut haystack = vec![Complex64::new(0.0, 0.0); needle.len()];
+        haystack[delay..].copy_from_slice(&needle[..needle.len() - delay]);
+        for (n, samp) in haystack.iter_mut().enumerate() {
+            let t = (n as f64) / (fs as f64);
+            let phase = 2.0 * PI * (freq * t + 0.5 * rate * t * t);
+            *samp *= Complex64::from_polar(&1.0, &phase);
+        }
+        haystack
+    }
+
+    // Helper to build a WAV file with an odd-length chunk to skip. An
+    // extensible format tag carries its real tag in the sub-format
+    fn gen_wav(tag: u16, channels: u16, fs: u32, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&fs.to_le_bytes());
+        fmt.extend_from_slice(&(fs * (channels * bits / 8) as u32).to_le_bytes());
+        fmt.extend_from_slice(&(channels * bits / 8).to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        if tag == 0xFFFE {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&bits.to_le_bytes());
+            fmt.extend_from_slice(&0u32.to_le_bytes());
+            fmt.extend_from_slice(&[3, 0]);
+            fmt.extend_from_slice(&[0; 14]);
+        }
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [(&b"fmt "[..], &fmt[..]), (b"LIST", b"odd"), (b"data", data)].iter() {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        wav.extend(body);
+        wav
+    }
+
+    // Helper to read a little-endian u32 at pos
+    fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+    }
+
+    // Helper to compute exact TDOAs, and FDOAs given a carrier, of
+    // every receiver pair for a stationary emitter
+    fn gen_measurements(receivers: &[Receiver], emitter: [f64; 3], carrier_hz: Option<f64>,
+        sigma_s: f64, sigma_hz: f64) -> Vec<Measurement> {
+
+        let range = |r: &Receiver| distance(r.position, emitter);
+        let closing = |r: &Receiver| (0..3)
+            .map(|k| r.velocity[k] * (emitter[k] - r.position[k]))
+            .sum::<f64>() / range(r);
+        let mut measurements = Vec::new();
+        for i in 0..receivers.len() {
+            for j in i + 1..receivers.len() {
+                let value_s = (range(&receivers[j]) - range(&receivers[i])) / SPEED_OF_LIGHT;
+                measurements.push(Measurement::Tdoa { i, j, value_s, sigma_s });
+                if let Some(carrier_hz) = carrier_hz {
+                    let value_hz = carrier_hz / SPEED_OF_LIGHT
+                        * (closing(&receivers[j]) - closing(&receivers[i]));
+                    measurements.push(Measurement::Fdoa { i, j, value_hz, sigma_hz });
+                }
+            }
+        }
+        measurements
+    }
+
+    // Helper to measure the distance between two points
+    fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+        (0..3).map(|k| (a[k] - b[k]).powi(2)).sum::<f64>().sqrt()
+    }
+
+    // Helper to serialize a File Meta Sink header for a segment of
+    // complex float samples, with any extra dictionary after it
+    fn gr_meta_header(rate: f64, time: (u64, f64), bytes: usize, extras: &[(&str, Pmt)])
+        -> Vec<u8> {
+
+        let extra = if extras.is_empty() { Vec::new() } else { Pmt::dict(extras).to_bytes() };
+        let header = |strt: usize| Pmt::dict(&[
+            ("version", Pmt::Int(0)),
+            ("rx_rate", Pmt::Double(rate)),
+            ("rx_time", Pmt::Tuple(vec![Pmt::UInt64(time.0), Pmt::Double(time.1)])),
+            ("size", Pmt::Int(8)),
+            ("type", Pmt::Int(5)),
+            ("cplx", Pmt::Bool(true)),
+            ("strt", Pmt::UInt64(strt as u64)),
+            ("bytes", Pmt::UInt64(bytes as u64)),
+        ]).to_bytes();
+        let mut out = header(header(0).len() + extra.len());
+        out.extend(extra);
+        out
+    }
+
+    // Helper to get a scratch file path unique to this test run
+    fn temp_filename(name: &str) -> String {
+        let mut path = std::env::temp_dir();
+        path.push(format!("caf_rust_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
 }